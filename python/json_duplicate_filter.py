@@ -68,34 +68,40 @@ def get_full_answer(answer):
     return answer_text
 
 
+# Pages reachable under several URLs are merged by their canonical URI, if known
+def dedup_key(line):
+    return line.get("Canonical_URI") or line["URI"]
+
+
 def generate_new_datapoint(line, dataset):
-    dataset[line["URI"]] = {
+    key = dedup_key(line)
+    dataset[key] = {
         "Language": line["Language"],
         "Fasttext_language": line["Fasttext_language"],
         "URI": line["URI"],
         "UUID": line["UUID"],
         "WARC_ID": line["WARC_ID"],
     }
-    dataset[line["URI"]]["Questions"] = {}
+    dataset[key]["Questions"] = {}
     for question in line["Questions"]:
         condensed_question = copy.copy(question)
         # Remove answers to only look at questions
         condensed_question.pop("Answers")
-        dataset[line["URI"]]["Questions"][
+        dataset[key]["Questions"][
             normalize_answer(get_full_question(condensed_question))
         ] = condensed_question
-        dataset[line["URI"]]["Questions"][
+        dataset[key]["Questions"][
             normalize_answer(get_full_question(condensed_question))
         ]["Answers"] = {}
         for answer in question["Answers"]:
-            dataset[line["URI"]]["Questions"][
+            dataset[key]["Questions"][
                 normalize_answer(get_full_question(condensed_question))
             ]["Answers"][normalize_answer(get_full_answer(answer))] = answer
     return dataset
 
 
 def update_datapoint(line, dataset):
-    curr_object = dataset[line["URI"]]
+    curr_object = dataset[dedup_key(line)]
     for new_question in line["Questions"]:
         new_question_text = get_full_question(new_question)
         if len(new_question_text) > 0:
@@ -120,7 +126,7 @@ def update_datapoint(line, dataset):
                 curr_object["Questions"][
                     normalize_answer(get_full_question(condensed_question))
                 ] = condensed_question
-                dataset[line["URI"]]["Questions"][
+                curr_object["Questions"][
                     normalize_answer(get_full_question(condensed_question))
                 ]["Answers"] = {}
                 for answer in new_question["Answers"]:
@@ -136,15 +142,39 @@ if __name__ == "__main__":
     )
     parser.add_argument("--json_dataset_path", help="Path to the json dataset")
     parser.add_argument("--output_path", help="Path to the output file")
+    parser.add_argument(
+        "--dedup_whitelist",
+        help="File of UUIDs or URIs, one per line, whose datapoints are kept unchanged",
+    )
     args = parser.parse_args()
     dataset_path = args.json_dataset_path
 
+    whitelist = set()
+    if args.dedup_whitelist is not None:
+        with open(args.dedup_whitelist, "r") as whitelist_file:
+            for entry in whitelist_file:
+                entry = entry.strip()
+                if entry and not entry.startswith("#"):
+                    whitelist.add(entry)
+
     with open(dataset_path, "r") as data_file:
         dataset = {}
+        seen_content_hashes = set()
+        whitelisted = []
         for idx, line in enumerate(data_file):
             line = json.loads(line)
+            # Whitelisted datapoints are neither dropped nor merged
+            if line["UUID"] in whitelist or line["URI"] in whitelist:
+                whitelisted.append(line)
+                continue
+            # Identical pages (same minified HTML) add nothing to merge
+            content_hash = line.get("Content_hash")
+            if content_hash is not None:
+                if content_hash in seen_content_hashes:
+                    continue
+                seen_content_hashes.add(content_hash)
             # Add in dictionary format for better runtime
-            if line["URI"] not in dataset.keys():
+            if dedup_key(line) not in dataset.keys():
                 dataset = generate_new_datapoint(line, dataset)
             else:
                 dataset = update_datapoint(line, dataset)
@@ -173,3 +203,5 @@ if __name__ == "__main__":
                 data_object["Questions"][-1]["Answers"] = answers
             json_record = json.dumps(data_object)
             f.write(json_record + "\n")
+        for line in whitelisted:
+            f.write(json.dumps(line) + "\n")
@@ -6,11 +6,18 @@
 # LICENSE file in the root directory of this source tree.
 
 import argparse
+import gzip
 import html
+import io
 import json
+import math
 import multiprocessing
 import os
+import shlex
+import subprocess
+import re
 import time
+import unicodedata
 import uuid
 
 import fasttext
@@ -18,6 +25,145 @@ import lxml.html
 from lxml import etree
 
 
+ANSWER_COUNTS = [
+    ("upvoteCount", "upvote_count"),
+    ("downvoteCount", "downvote_count"),
+    ("commentCount", "comment_count"),
+]
+QUESTION_COUNTS = ANSWER_COUNTS + [("answerCount", "answer_count")]
+# interactionType values of the interactionStatistic counters, legacy types and actions
+INTERACTION_COUNTS = [
+    (("UserPageVisits", "ViewAction", "WatchAction"), "view_count"),
+    (("UserComments", "CommentAction"), "comment_count"),
+]
+
+# Abbreviated magnitudes written after counts, e.g. "1.2k", "3 Mio.", "2万", longest first. Kept
+# in sync with COUNT_SUFFIXES of rust/src/qa.rs, whose parse_count this mirrors.
+COUNT_SUFFIXES = [
+    ("tsd", 10 ** 3),
+    ("mil", 10 ** 3),
+    ("mio", 10 ** 6),
+    ("mln", 10 ** 6),
+    ("mrd", 10 ** 9),
+    ("k", 10 ** 3),
+    ("千", 10 ** 3),
+    ("万", 10 ** 4),
+    ("萬", 10 ** 4),
+    ("m", 10 ** 6),
+    ("b", 10 ** 9),
+]
+NUMBER_PATTERN = re.compile(r"([-+]?)([0-9][0-9.,'\s]*)")
+
+
+def parse_count(value):
+    # Parse a count as displayed on the page into an integer, None if it isn't one. Handles
+    # localized digits ("١٢٣"), thousands separators ("1,234", "1.234", "1 234") and
+    # abbreviated magnitudes ("1,2 k"):
+    #
+    #     "12 votes" -> 12, "1.234,5" -> 1235, "1.5" -> 2, "3 Mio." -> 3000000, "5 km" -> 5
+    if value is None:
+        return None
+    normalized = []
+    for char in value.strip().lower():
+        if char == "٫":  # Arabic decimal separator
+            normalized.append(".")
+        elif char == "٬":  # Arabic thousands separator
+            normalized.append(",")
+        elif char.isspace():
+            normalized.append(" ")
+        elif unicodedata.category(char) == "Nd":
+            normalized.append(str(unicodedata.decimal(char)))
+        else:
+            normalized.append(char)
+    normalized = "".join(normalized)
+    match = NUMBER_PATTERN.search(normalized)
+    if match is None:
+        return None
+    sign, number = match.groups()
+    # A magnitude only counts as one when no other word follows it, "5 km" isn't 5000
+    rest = normalized[match.end() :].lstrip()
+    suffix, magnitude = None, 1
+    for name, factor in COUNT_SUFFIXES:
+        if rest.startswith(name):
+            after = rest[len(name) :]
+            if after.startswith("."):
+                after = after[1:]
+            if not ("a" <= after[:1] <= "z"):
+                suffix, magnitude = name, factor
+                break
+    number = re.sub(r"[\s']", "", number).rstrip(".,")
+    separators = [char for char in number if char in ".,"]
+    if len(set(separators)) == 2:
+        # The last separator is the decimal one, the other groups thousands
+        decimal = separators[-1]
+        number = number.replace("," if decimal == "." else ".", "").replace(decimal, ".")
+    elif len(separators) > 1:
+        number = number.replace(separators[0], "")
+    elif len(separators) == 1:
+        integer, fraction = number.split(separators[0])
+        # "1,234" groups thousands, "1,2" (or "1,234k") has a decimal part
+        if len(fraction) == 3 and suffix is None:
+            number = integer + fraction
+        else:
+            number = integer + "." + fraction
+    try:
+        count = float(number) * magnitude
+    except ValueError:
+        return None
+    # Halves round up, as in Rust
+    count = math.floor(count + 0.5)
+    return -count if sign == "-" else count
+
+
+def find_count(node, prop):
+    count_node = find_itemprop(node, prop)
+    if count_node is None:
+        return None
+    if count_node.tag == "meta":
+        return parse_count(count_node.get("content"))
+    return parse_count("".join(count_node.itertext()))
+
+
+def find_interaction_counts(node):
+    # Popularity counts of the item's own interactionStatistic counters, e.g. the
+    # userInteractionCount of an InteractionCounter with interactionType .../ViewAction
+    counts = {}
+    for statistic in node.iter():
+        if "interactionStatistic" not in (statistic.get("itemprop") or "").split():
+            continue
+        scope = statistic.getparent()
+        while scope is not None and scope.get("itemscope") is None:
+            scope = scope.getparent()
+        if scope is not node:
+            continue
+        type_node = find_itemprop(statistic, "interactionType")
+        if type_node is None:
+            continue
+        interaction = type_node.get("content") or "".join(type_node.itertext())
+        interaction = interaction.strip().rstrip("/")
+        for names, key in INTERACTION_COUNTS:
+            if any(
+                interaction == name or interaction.endswith("schema.org/" + name)
+                for name in names
+            ):
+                count = find_count(statistic, "userInteractionCount")
+                if count is not None:
+                    counts.setdefault(key, count)
+    return counts
+
+
+def find_direction(node, root):
+    # Text direction of a node as kept by --keep-direction: the closest dir attribute at or above
+    # the node, up to the Question or Answer root
+    while node is not None:
+        if node.get("dir") in ("ltr", "rtl", "auto"):
+            return node.get("dir")
+        if node is root:
+            return None
+        node = node.getparent()
+    return None
+
+
 def collect_question(node):
     question = {}
     # name
@@ -32,6 +178,14 @@ def collect_question(node):
         text_node = text_cleanup(text_node)
         question["text_markup"] = turn_into_string(text_node)
 
+    # direction of the question text (rtl/ltr/auto)
+    direction = find_direction(
+        text_node if text_node is not None else name_node if name_node is not None else node,
+        node,
+    )
+    if direction is not None:
+        question["direction"] = direction
+
     # date/time {created|modified|published}
     date_created = find_itemprop(node, "dateCreated")
     if date_created is not None:
@@ -46,41 +200,14 @@ def collect_question(node):
         date_published = date_published.get("datetime")
         question["date_published"] = date_published
 
-    # upvote count
-    upvote_count = find_itemprop(node, "upvoteCount")
-    if upvote_count is not None:
-        if upvote_count.tag == "meta":
-            upvote_count = upvote_count.get("content")
-        else:
-            upvote_count = upvote_count.text
-        question["upvote_count"] = upvote_count
-
-    # downvote count
-    downvote_count = find_itemprop(node, "downvoteCount")
-    if downvote_count is not None:
-        if downvote_count.tag == "meta":
-            downvote_count = downvote_count.get("content")
-        else:
-            downvote_count = downvote_count.text
-        question["downvote_count"] = downvote_count
-
-    # comment count
-    comment_count = find_itemprop(node, "commentCount")
-    if comment_count is not None:
-        if comment_count.tag == "meta":
-            comment_count = comment_count.get("content")
-        else:
-            comment_count = comment_count.text
-        question["comment_count"] = comment_count
-
-    # Answer count
-    answer_count = find_itemprop(node, "answerCount")
-    if answer_count is not None:
-        if answer_count.tag == "meta":
-            answer_count = answer_count.get("content")
-        else:
-            answer_count = answer_count.text
-        question["answer_count"] = answer_count
+    # upvote, downvote, comment and answer counts
+    for prop, key in QUESTION_COUNTS:
+        count = find_count(node, prop)
+        if count is not None:
+            question[key] = count
+    # view and comment counts of interactionStatistic, commentCount taking precedence
+    for key, count in find_interaction_counts(node).items():
+        question.setdefault(key, count)
 
     return question
 
@@ -93,6 +220,11 @@ def collect_answer(node):
         text_node = text_cleanup(text_node)
         answer["text_markup"] = turn_into_string(text_node)
 
+    # direction of the answer text (rtl/ltr/auto)
+    direction = find_direction(text_node if text_node is not None else node, node)
+    if direction is not None:
+        answer["direction"] = direction
+
     # suggested|accepted
     suggested_accepted = node.get("itemprop")
     answer["status"] = suggested_accepted
@@ -111,32 +243,11 @@ def collect_answer(node):
         date_published = date_published.get("datetime")
         answer["date_published"] = date_published
 
-    # upvote count
-    upvote_count = find_itemprop(node, "upvoteCount")
-    if upvote_count is not None:
-        if upvote_count.tag == "meta":
-            upvote_count = upvote_count.get("content")
-        else:
-            upvote_count = upvote_count.text
-        answer["upvote_count"] = upvote_count
-
-    # downvote count
-    downvote_count = find_itemprop(node, "downvoteCount")
-    if downvote_count is not None:
-        if downvote_count.tag == "meta":
-            downvote_count = downvote_count.get("content")
-        else:
-            downvote_count = downvote_count.text
-        answer["downvote_count"] = downvote_count
-
-    # comment count
-    comment_count = find_itemprop(node, "commentCount")
-    if comment_count is not None:
-        if comment_count.tag == "meta":
-            comment_count = comment_count.get("content")
-        else:
-            comment_count = comment_count.text
-        answer["comment_count"] = comment_count
+    # upvote, downvote and comment counts
+    for prop, key in ANSWER_COUNTS:
+        count = find_count(node, prop)
+        if count is not None:
+            answer[key] = count
 
     return answer
 
@@ -268,9 +379,32 @@ def find_itemprop(node, prop):
     return None
 
 
+QUESTION_SEPARATOR = re.compile(r"<!--ccqa-question ({.*?})-->")
+
+
+def split_questions(html_content):
+    # Split an mhtml written with --question-separators into its Questions, using the length in
+    # the separator headers. Returns None for mhtml without separators.
+    encoded = html_content.encode("utf-8")
+    questions = []
+    position = 0
+    while True:
+        match = QUESTION_SEPARATOR.match(html_content, position)
+        if match is None:
+            break
+        header = json.loads(match.group(1))
+        start = len(html_content[: match.end()].encode("utf-8"))
+        question = encoded[start : start + header["bytes"]].decode("utf-8")
+        questions.append(question)
+        position = match.end() + len(question)
+    if len(questions) == 0:
+        return None
+    return questions
+
+
 def get_all_questions(node, question_list):
     if "itemtype" in node.keys():
-        if "//schema.org/Question" in node.get("itemtype"):
+        if "schema.org/Question" in node.get("itemtype"):
             question_list.append(node)
             return
     for child in node:
@@ -296,6 +430,54 @@ def predict_question_language(json_question, ft_model):
     return language
 
 
+def detect_title_body_languages(json_question, ft_model):
+    # Detect the language of the question title (name) and body (text) separately, flagging
+    # partially translated pages where they differ
+    if "name_markup" not in json_question.keys() or "text_markup" not in json_question.keys():
+        return False
+    title_language = ft_model.predict(html.unescape(json_question["name_markup"]))[0][
+        0
+    ].replace("__label__", "")
+    body_language = ft_model.predict(html.unescape(json_question["text_markup"]))[0][
+        0
+    ].replace("__label__", "")
+    json_question["Title_language"] = title_language
+    json_question["Body_language"] = body_language
+    json_question["Language_mismatch"] = title_language != body_language
+    return json_question["Language_mismatch"]
+
+
+class ExternalSummarizer:
+    # Hook producing a short summary per question, e.g. for query generation datasets. The
+    # command is started once and kept running: every question is written to its stdin as one
+    # JSON object {"name": ..., "text": ...} per line, and one line of summary text is expected
+    # back on its stdout per question.
+    def __init__(self, command):
+        self.process = subprocess.Popen(
+            shlex.split(command),
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            universal_newlines=True,
+            bufsize=1,
+        )
+
+    def summarize(self, json_question):
+        request = {
+            "name": html.unescape(json_question.get("name_markup", "")),
+            "text": html.unescape(json_question.get("text_markup", "")),
+        }
+        self.process.stdin.write(json.dumps(request) + "\n")
+        self.process.stdin.flush()
+        summary = self.process.stdout.readline()
+        if not summary:
+            raise RuntimeError("Summarization command exited unexpectedly")
+        return summary.rstrip("\n")
+
+    def close(self):
+        self.process.stdin.close()
+        self.process.wait()
+
+
 def has_at_least_Q_or_A(json_question):
     if "name_markup" in json_question.keys() or "text_markup" in json_question.keys():
         return True
@@ -306,7 +488,7 @@ def has_at_least_Q_or_A(json_question):
 
 
 def search_tree(node, json_context):
-    if "itemtype" in node.keys() and "//schema.org/Answer" in node.get("itemtype"):
+    if "itemtype" in node.keys() and "schema.org/Answer" in node.get("itemtype"):
         if "Answers" not in json_context.keys():
             # Stacked question (not in the schema.org definition)
             if node.getparent() is not None:
@@ -318,7 +500,7 @@ def search_tree(node, json_context):
     for child in node:
         search_tree(child, json_context)
     if "itemtype" in node.keys():
-        if "//schema.org/Question" in node.get("itemtype"):
+        if "schema.org/Question" in node.get("itemtype"):
             if "Answers" not in json_context.keys():
                 # Stacked question (not in the schema.org definition)
                 if node.getparent() is not None:
@@ -329,7 +511,7 @@ def search_tree(node, json_context):
                 json_context.update(element)
             if node.getparent() is not None:
                 node.getparent().remove(node)
-        elif "//schema.org/Answer" in node.get("itemtype"):
+        elif "schema.org/Answer" in node.get("itemtype"):
             element = collect_answer(node)
             json_context.update(element)
             if node.getparent() is not None:
@@ -342,28 +524,57 @@ def search_tree(node, json_context):
                 node.getparent().remove(node)
 
 
-def generate_structured_json(files, output_folder, output_file, fasttext_bin):
+def load_webpages(f):
+    # The Rust processor writes a single JSON array, JSON Lines (--format jsonl) or a stream of
+    # MessagePack maps (--format msgpack)
+    raw = f.read()
+    if raw[:1] and (0x80 <= raw[0] <= 0x8F or raw[0] in (0xDE, 0xDF)):
+        try:
+            import msgpack
+        except ImportError:
+            raise SystemExit(
+                "Reading --format msgpack outputs needs the msgpack package: pip install msgpack"
+            )
+
+        return list(msgpack.Unpacker(io.BytesIO(raw), raw=False))
+    content = raw.decode("utf-8")
+    if content.lstrip().startswith("["):
+        return json.loads(content)
+    return [json.loads(line) for line in content.splitlines() if line.strip()]
+
+
+def generate_structured_json(
+    files, output_folder, output_file, fasttext_bin, summarizer=None
+):
     ft_model = fasttext.load_model(fasttext_bin)
     for warc_file in files:
-        with open(warc_file) as f, open(
+        # Minified files can be gzipped by the Rust processor (--compress gzip)
+        opener = gzip.open if warc_file.endswith(".gz") else open
+        warc_id = os.path.basename(warc_file).replace(".gz", "").replace(".mhtml", "")
+        with opener(warc_file, "rb") as f, open(
             os.path.join(
                 output_folder,
-                output_file.replace(
-                    "PLACEHOLDER", os.path.basename(warc_file).replace(".mhtml", "")
-                ),
+                output_file.replace("PLACEHOLDER", warc_id),
             ),
             "a+",
         ) as g:
-            webpages = json.loads(f.read())
+            webpages = load_webpages(f)
             for idx, element in enumerate(webpages):
                 document = {}
                 html_content = element["mhtml"]
                 language = element["language"]
                 uri = element["uri"]
 
-                html_root = etree.HTML(html_content)
                 html_questions, json_questions, questions_language = [], [], []
-                get_all_questions(html_root, html_questions)
+                mixed_language = False
+                # Parse the Questions one by one if the boundaries were kept
+                question_contents = (
+                    element.get("questions")
+                    or split_questions(html_content)
+                    or [html_content]
+                )
+                for question_content in question_contents:
+                    get_all_questions(etree.HTML(question_content), html_questions)
                 for html_question in html_questions:
                     json_question = {"Answers": []}
                     search_tree(html_question, json_question)
@@ -373,22 +584,70 @@ def generate_structured_json(files, output_folder, output_file, fasttext_bin):
                         questions_language.append(
                             predict_question_language(json_question, ft_model)
                         )
+                        if detect_title_body_languages(json_question, ft_model):
+                            mixed_language = True
+                        if summarizer is not None:
+                            json_question["Summary"] = summarizer.summarize(json_question)
                         json_questions.append(json_question)
                 if len(json_questions) > 0:
-                    question_uuid = str(uuid.uuid4())
+                    # Keep the (namespaced) id assigned during WARC processing if present
+                    question_uuid = element.get("id", str(uuid.uuid4()))
                     predicted_language = predict_majority_language(questions_language)
-                    json_record = json.dumps(
-                        {
-                            "Language": language,
-                            "Fasttext_language": predicted_language,
-                            "URI": uri,
-                            "UUID": question_uuid,
-                            "WARC_ID": os.path.basename(warc_file).replace(
-                                ".mhtml", ""
-                            ),
-                            "Questions": json_questions,
-                        }
-                    )
+                    document = {
+                        "Language": language,
+                        "Fasttext_language": predicted_language,
+                        "URI": uri,
+                        # <link rel="canonical"> of the page, the better key for URL dedup
+                        "Canonical_URI": element.get("canonical_uri") or uri,
+                        "Crawl_date": element.get("crawl_date"),
+                        "WARC_record_ID": element.get("record_id"),
+                        "Payload_digest": element.get("payload_digest"),
+                        "Title": element.get("title"),
+                        "UUID": question_uuid,
+                        "WARC_ID": warc_id,
+                        "Questions": json_questions,
+                        # Any question with title and body in different languages
+                        "Mixed_language": mixed_language,
+                    }
+                    # Sidecar metadata joined during WARC processing (--sidecar)
+                    if "metadata" in element:
+                        document["Metadata"] = element["metadata"]
+                    # OpenGraph title and description, meta description (--page-meta)
+                    for key in ["og_title", "og_description", "meta_description"]:
+                        if element.get(key):
+                            document[key.capitalize()] = element[key]
+                    # Language identified from the Question text (--detect-language)
+                    if element.get("detected_language"):
+                        document["Detected_language"] = element["detected_language"]
+                        document["Detected_language_confidence"] = element.get(
+                            "detected_language_confidence"
+                        )
+                    # Keywords and tag-list topics of the Questions
+                    if "tags" in element:
+                        document["Tags"] = element["tags"]
+                    # Page paragraphs outside of the Questions (--context-passages)
+                    if "context" in element:
+                        document["Context"] = element["context"]
+                    # Text contains bidirectional control characters (--bidi-controls flag)
+                    if element.get("has_bidi_controls"):
+                        document["Has_bidi_controls"] = True
+                    if "extraction_confidence" in element:
+                        document["Extraction_confidence"] = element["extraction_confidence"]
+                    # Original Question HTML before cleaning (--keep-raw)
+                    if "raw_questions" in element:
+                        document["Raw_questions"] = element["raw_questions"]
+                    # Questions as typed fields parsed during WARC processing (--structured)
+                    if "structured" in element:
+                        document["Structured"] = element["structured"]
+                    # Hash of the minified HTML, for deduplication without rehashing
+                    if element.get("content_hash"):
+                        document["Content_hash"] = element["content_hash"]
+                    if "config_hash" in element:
+                        document["Config_hash"] = element["config_hash"]
+                    # Offline ASN / reverse DNS enrichment (--asn-table, --reverse-dns)
+                    if "network" in element:
+                        document["Network"] = element["network"]
+                    json_record = json.dumps(document)
                     g.write(json_record + "\n")
 
 
@@ -397,6 +656,10 @@ if __name__ == "__main__":
     parser.add_argument("--fasttext_path", help="Path to the fasttext lid.176.bin file")
     parser.add_argument("--input_folder", help="Path to the mhtml folder")
     parser.add_argument("--output_folder", help="Path to the output folder")
+    parser.add_argument(
+        "--summarize_command",
+        help="Command adding a Summary to every question: reads one JSON question per line on stdin and writes one summary line per question",
+    )
     args = parser.parse_args()
 
     fasttext_bin = args.fasttext_path
@@ -411,7 +674,12 @@ if __name__ == "__main__":
     files = [
         os.path.join(input_folder, f)
         for f in os.listdir(input_folder)
-        if f.endswith(".mhtml")
+        if f.endswith(".mhtml") or f.endswith(".mhtml.gz")
     ]
 
-    generate_structured_json(files, output_folder, output_file, fasttext_bin)
+    summarizer = (
+        ExternalSummarizer(args.summarize_command) if args.summarize_command else None
+    )
+    generate_structured_json(files, output_folder, output_file, fasttext_bin, summarizer)
+    if summarizer is not None:
+        summarizer.close()
@@ -0,0 +1,55 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Global allocator counting the bytes allocated by every thread, so the worker statistics
+// (--worker-stats) show which workers churn through memory on huge documents. The binary installs
+// it; embedding applications can install it as well, otherwise no allocations are counted:
+//
+//     #[global_allocator]
+//     static ALLOCATOR: ccqa_rust::CountingAllocator = ccqa_rust::CountingAllocator;
+//
+// Only allocations are counted, as the bytes requested (and the growth of reallocations), not the
+// memory in use.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+pub struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATED: Cell<u64> = Cell::new(0);
+}
+
+fn count(bytes: usize) {
+    // Allocations while the thread is torn down go uncounted
+    let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + bytes as u64));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size.saturating_sub(layout.size()));
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+// Bytes allocated by the calling thread so far, stuck at 0 without the CountingAllocator
+pub(crate) fn allocated_bytes() -> u64 {
+    ALLOCATED.try_with(Cell::get).unwrap_or(0)
+}
@@ -0,0 +1,84 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// AMP pages (`<html amp>` or `<html ⚡>`), the mobile-optimized versions of many forum pages.
+// Their content sits in `amp-*` custom elements, and their Questions are often only given as
+// JSON-LD, which is therefore read from AMP pages even when only microdata is extracted
+// otherwise. Components without content of their own (analytics, ads, state) are removed, the
+// others are unwrapped into their children unless they carry microdata themselves.
+
+use kuchiki::NodeRef;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// Components holding no page content
+const NON_CONTENT_COMPONENTS: &[&str] = &[
+    "amp-ad",
+    "amp-analytics",
+    "amp-auto-ads",
+    "amp-consent",
+    "amp-geo",
+    "amp-pixel",
+    "amp-script",
+    "amp-state",
+    "amp-sticky-ad",
+];
+
+// Cheap check of the raw page for the AMP marker before building the DOM
+pub(crate) fn is_amp(text: &str) -> bool {
+    lazy_static! {
+        static ref AMP: Regex = Regex::new(r#"(?i)<html\s[^>]*?(\bamp|⚡)(\s|=|/?>)"#).unwrap();
+    }
+    AMP.is_match(text)
+}
+
+pub(crate) fn is_amp_document(document: &NodeRef) -> bool {
+    document
+        .descendants()
+        .find(|node| {
+            node.as_element()
+                .map_or(false, |element| &*element.name.local == "html")
+        })
+        .and_then(|html| {
+            let element = html.as_element()?;
+            let attributes = element.attributes.borrow();
+            Some(attributes.contains("amp") || attributes.contains("⚡"))
+        })
+        .unwrap_or(false)
+}
+
+fn has_microdata(node: &NodeRef) -> bool {
+    node.as_element().map_or(false, |element| {
+        element
+            .attributes
+            .borrow()
+            .map
+            .keys()
+            .any(|name| name.local.starts_with("item"))
+    })
+}
+
+// Remove the non-content components and unwrap all others without microdata
+pub(crate) fn unwrap_components(document: &NodeRef) {
+    let components = document
+        .descendants()
+        .filter(|node| {
+            node.as_element()
+                .map_or(false, |element| element.name.local.starts_with("amp-"))
+        })
+        .collect::<Vec<NodeRef>>();
+    for component in components {
+        let name = component.as_element().unwrap().name.local.to_string();
+        if NON_CONTENT_COMPONENTS.contains(&name.as_str()) {
+            component.detach();
+        } else if !has_microdata(&component) {
+            for child in component.children() {
+                component.insert_before(child);
+            }
+            component.detach();
+        }
+    }
+}
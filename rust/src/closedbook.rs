@@ -0,0 +1,64 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Closed-book (sequence-to-sequence) training files straight from minified outputs, the Rust
+// counterpart of `closed_book_processing.py`. Every selected question/answer pair becomes one
+// line of `<output>.source` (the question) and the same line of `<output>.target` (the answer).
+// Answers that only make sense in the context of the page ("Yes.", "As above") can be dropped
+// with `--drop-back-references`. The source is the question title and body, or only one of them
+// with `--question-part`; questions without the selected part are skipped.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+use crate::qa::{self, AnswerSelection, QaOptions, QuestionPart};
+
+pub(crate) fn generate(
+    inputs: &[&str],
+    output_path: &str,
+    options: &QaOptions,
+    selection: AnswerSelection,
+    question_part: QuestionPart,
+    drop_back_references: bool,
+) -> std::io::Result<()> {
+    let mut source = BufWriter::new(File::create(format!("{}.source", output_path))?);
+    let mut target = BufWriter::new(File::create(format!("{}.target", output_path))?);
+    let mut num_pairs = 0;
+    let mut num_dropped = 0;
+    for input in inputs.iter() {
+        let records = qa::read_questions(input, options)?;
+        for question in records.iter().flat_map(|record| record.questions.iter()) {
+            let source_text = question.part(question_part);
+            if source_text.is_empty() {
+                continue;
+            }
+            for answer in qa::select_answers(question, selection) {
+                if drop_back_references && answer.back_reference {
+                    num_dropped += 1;
+                    continue;
+                }
+                writeln!(source, "{}", source_text)?;
+                writeln!(target, "{}", answer.text)?;
+                num_pairs += 1;
+            }
+        }
+        eprintln!("Read {} records of {}", records.len(), input);
+    }
+    source.flush()?;
+    target.flush()?;
+    if drop_back_references {
+        eprintln!(
+            "Dropped {} answers starting with a back-reference",
+            num_dropped
+        );
+    }
+    println!(
+        "Wrote {} question/answer pairs to {}.source and {}.target",
+        num_pairs, output_path, output_path
+    );
+    Ok(())
+}
@@ -0,0 +1,201 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Arrow representation of HTMLMinified records, shared by the columnar output formats
+
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, BooleanArray, Float64Array, ListBuilder, StringArray, StringBuilder, UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+
+use crate::HTMLMinified;
+
+// Number of records collected before a record batch is written
+pub(crate) const BATCH_SIZE: usize = 4096;
+
+pub(crate) fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("mhtml", DataType::Utf8, false),
+        Field::new(
+            "questions",
+            DataType::List(Box::new(Field::new("item", DataType::Utf8, true))),
+            false,
+        ),
+        Field::new("num_questions", DataType::UInt64, false),
+        Field::new("content_hash", DataType::Utf8, false),
+        Field::new(
+            "raw_questions",
+            DataType::List(Box::new(Field::new("item", DataType::Utf8, true))),
+            false,
+        ),
+        // Typed Questions (--structured) serialized as a JSON list
+        Field::new("structured", DataType::Utf8, true),
+        Field::new("language", DataType::Utf8, false),
+        Field::new("detected_language", DataType::Utf8, true),
+        Field::new("detected_language_confidence", DataType::Float64, true),
+        Field::new("uri", DataType::Utf8, true),
+        Field::new("canonical_uri", DataType::Utf8, true),
+        Field::new("ip_address", DataType::Utf8, true),
+        Field::new("crawl_date", DataType::Utf8, true),
+        Field::new("record_id", DataType::Utf8, true),
+        Field::new("payload_digest", DataType::Utf8, true),
+        Field::new("title", DataType::Utf8, true),
+        Field::new("og_title", DataType::Utf8, true),
+        Field::new("og_description", DataType::Utf8, true),
+        Field::new("meta_description", DataType::Utf8, true),
+        // Sidecar metadata serialized as a JSON object
+        Field::new("metadata", DataType::Utf8, true),
+        Field::new(
+            "tags",
+            DataType::List(Box::new(Field::new("item", DataType::Utf8, true))),
+            false,
+        ),
+        Field::new(
+            "context",
+            DataType::List(Box::new(Field::new("item", DataType::Utf8, true))),
+            false,
+        ),
+        Field::new("has_bidi_controls", DataType::Boolean, false),
+        Field::new("extraction_confidence", DataType::Utf8, false),
+        Field::new("config_hash", DataType::Utf8, false),
+        // Network enrichment serialized as a JSON object
+        Field::new("network", DataType::Utf8, true),
+    ]))
+}
+
+fn string_column<'a, F: Fn(&'a HTMLMinified) -> &'a str>(
+    records: &'a [HTMLMinified],
+    field: F,
+) -> ArrayRef {
+    Arc::new(StringArray::from(
+        records.iter().map(field).collect::<Vec<&str>>(),
+    ))
+}
+
+fn optional_string_column<'a, F: Fn(&'a HTMLMinified) -> Option<&'a str>>(
+    records: &'a [HTMLMinified],
+    field: F,
+) -> ArrayRef {
+    Arc::new(StringArray::from(
+        records.iter().map(field).collect::<Vec<Option<&str>>>(),
+    ))
+}
+
+fn string_list_column<'a, F: Fn(&'a HTMLMinified) -> &'a [String]>(
+    records: &'a [HTMLMinified],
+    field: F,
+) -> Result<ArrayRef, ArrowError> {
+    let mut builder = ListBuilder::new(StringBuilder::new(records.len()));
+    for record in records {
+        for value in field(record) {
+            builder.values().append_value(value)?;
+        }
+        builder.append(true)?;
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+pub(crate) fn to_record_batch(records: &[HTMLMinified]) -> Result<RecordBatch, ArrowError> {
+    let metadata = records
+        .iter()
+        .map(|record| {
+            record
+                .metadata
+                .as_ref()
+                .map(|metadata| serde_json::Value::Object(metadata.clone()).to_string())
+        })
+        .collect::<Vec<Option<String>>>();
+    let structured = records
+        .iter()
+        .map(|record| {
+            if record.structured.is_empty() {
+                None
+            } else {
+                Some(serde_json::to_string(&record.structured).unwrap())
+            }
+        })
+        .collect::<Vec<Option<String>>>();
+    let network = records
+        .iter()
+        .map(|record| {
+            record
+                .network
+                .as_ref()
+                .map(|network| serde_json::to_string(network).unwrap())
+        })
+        .collect::<Vec<Option<String>>>();
+    RecordBatch::try_new(
+        schema(),
+        vec![
+            string_column(records, |record| record.id.as_str()),
+            string_column(records, |record| record.mhtml.as_str()),
+            string_list_column(records, |record| record.questions.as_slice())?,
+            Arc::new(UInt64Array::from(
+                records
+                    .iter()
+                    .map(|record| record.num_questions as u64)
+                    .collect::<Vec<u64>>(),
+            )),
+            string_column(records, |record| record.content_hash.as_str()),
+            string_list_column(records, |record| record.raw_questions.as_slice())?,
+            Arc::new(StringArray::from(
+                structured
+                    .iter()
+                    .map(|structured| structured.as_deref())
+                    .collect::<Vec<Option<&str>>>(),
+            )),
+            string_column(records, |record| record.language.as_str()),
+            optional_string_column(records, |record| record.detected_language.as_deref()),
+            Arc::new(Float64Array::from(
+                records
+                    .iter()
+                    .map(|record| record.detected_language_confidence)
+                    .collect::<Vec<Option<f64>>>(),
+            )),
+            optional_string_column(records, |record| record.uri.as_deref()),
+            optional_string_column(records, |record| record.canonical_uri.as_deref()),
+            optional_string_column(records, |record| record.ip_address.as_deref()),
+            optional_string_column(records, |record| record.crawl_date.as_deref()),
+            optional_string_column(records, |record| record.record_id.as_deref()),
+            optional_string_column(records, |record| record.payload_digest.as_deref()),
+            optional_string_column(records, |record| record.title.as_deref()),
+            optional_string_column(records, |record| record.og_title.as_deref()),
+            optional_string_column(records, |record| record.og_description.as_deref()),
+            optional_string_column(records, |record| record.meta_description.as_deref()),
+            Arc::new(StringArray::from(
+                metadata
+                    .iter()
+                    .map(|metadata| metadata.as_deref())
+                    .collect::<Vec<Option<&str>>>(),
+            )),
+            string_list_column(records, |record| record.tags.as_slice())?,
+            string_list_column(records, |record| record.context.as_slice())?,
+            Arc::new(BooleanArray::from(
+                records
+                    .iter()
+                    .map(|record| record.has_bidi_controls)
+                    .collect::<Vec<bool>>(),
+            )),
+            string_column(records, |record| record.extraction_confidence.name()),
+            string_column(records, |record| record.config_hash.as_str()),
+            Arc::new(StringArray::from(
+                network
+                    .iter()
+                    .map(|network| network.as_deref())
+                    .collect::<Vec<Option<&str>>>(),
+            )),
+        ],
+    )
+}
+
+pub(crate) fn to_io_error<E: std::fmt::Display>(err: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+}
@@ -0,0 +1,114 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Per-record extraction confidence, combining the individual quality signals into a single value
+// consumers can filter on without knowing every flag.
+
+use kuchiki::NodeRef;
+use serde::{Deserialize, Serialize};
+
+use crate::extract;
+use crate::itemtype;
+use crate::microdata::attribute;
+
+// Records written before the field existed are treated conservatively as low
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExtractionConfidence {
+    // No Question carries both a question and an answer
+    #[default]
+    Low,
+    // Usable, but markup is incomplete, the extraction has warnings (lenient itemtype, fallback,
+    // truncation, ...) or WARC headers are missing
+    Medium,
+    // Canonical markup, every Question is complete and the WARC headers are present
+    High,
+}
+
+impl ExtractionConfidence {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ExtractionConfidence::Low => "low",
+            ExtractionConfidence::Medium => "medium",
+            ExtractionConfidence::High => "high",
+        }
+    }
+}
+
+// Quality signals of a single cleaned Question subtree
+pub(crate) struct QuestionSignals {
+    // Marked up with the canonical itemtype rather than a lenient variant
+    pub(crate) canonical: bool,
+    pub(crate) has_question_text: bool,
+    pub(crate) has_answer: bool,
+}
+
+pub(crate) fn question_signals(question: &NodeRef) -> QuestionSignals {
+    let mut signals = QuestionSignals {
+        canonical: attribute(question, "itemtype")
+            .map_or(false, |itemtype| itemtype::is_question_itemtype(&itemtype)),
+        has_question_text: false,
+        has_answer: false,
+    };
+    for node in question.descendants() {
+        match attribute(&node, "itemprop").as_deref() {
+            Some("name") | Some("text") => signals.has_question_text = true,
+            Some("acceptedAnswer") | Some("suggestedAnswer") => signals.has_answer = true,
+            _ => {}
+        }
+        if attribute(&node, "itemtype").map_or(false, |itemtype| itemtype.ends_with("/Answer")) {
+            signals.has_answer = true;
+        }
+    }
+    signals
+}
+
+// Assess the cleaned Questions of a page, any warning of their extraction rules out high
+pub(crate) fn assess(
+    questions: &[extract::Extracted],
+    headers_complete: bool,
+) -> ExtractionConfidence {
+    let signals = questions
+        .iter()
+        .map(|question| question_signals(&question.node))
+        .collect::<Vec<QuestionSignals>>();
+    let is_complete = |signal: &QuestionSignals| signal.has_question_text && signal.has_answer;
+    if !signals.iter().any(is_complete) {
+        return ExtractionConfidence::Low;
+    }
+    if headers_complete
+        && questions
+            .iter()
+            .all(|question| question.warnings.is_empty())
+        && signals
+            .iter()
+            .all(|signal| signal.canonical && is_complete(signal))
+    {
+        ExtractionConfidence::High
+    } else {
+        ExtractionConfidence::Medium
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{minify_html, CancellationToken, ExtractOptions, ExtractWarning};
+
+    #[test]
+    fn warnings_rule_out_high() {
+        let page = r#"<html lang="en"><body><div itemscope itemtype="https://schema.org/Question">
+            <span itemprop="name">Why?</span><div itemprop="acceptedAnswer" itemscope
+            itemtype="https://schema.org/Answer"><span itemprop="text">Because.</span></div>
+            </div></body></html>"#;
+        let mut extracted =
+            minify_html(page, &ExtractOptions::default(), &CancellationToken::new()).unwrap();
+        assert_eq!(assess(&extracted, true), ExtractionConfidence::High);
+        assert_eq!(assess(&extracted, false), ExtractionConfidence::Medium);
+        extracted[0].warnings.push(ExtractWarning::Truncated);
+        assert_eq!(assess(&extracted, true), ExtractionConfidence::Medium);
+    }
+}
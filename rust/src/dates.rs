@@ -0,0 +1,150 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Normalization of the site-specific dates of dateCreated, datePublished and dateModified
+// properties into ISO-8601. Dates with a time zone are converted to UTC
+// (2021-05-06T12:34:56Z), dates without one keep their local time (2021-05-06T12:34:56) and
+// dates without a time stay dates (2021-05-06). Day-first and month-first numeric dates like
+// 06/05/2021 are ambiguous and not normalized.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+
+const ZONED_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+    "%Y-%m-%d %H:%M:%S%z",
+    "%Y-%m-%d %H:%M:%S %z",
+];
+
+const LOCAL_FORMATS: [&str; 6] = [
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M:%S",
+];
+
+const DATE_FORMATS: [&str; 7] = [
+    "%Y-%m-%d",
+    "%Y/%m/%d",
+    "%Y.%m.%d",
+    "%B %d, %Y",
+    "%b %d, %Y",
+    "%d %B %Y",
+    "%d %b %Y",
+];
+
+pub(crate) fn utc(date: DateTime<chrono::FixedOffset>) -> String {
+    date.with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// ISO-8601 form of a date as found on a page, None if it isn't in any known format
+pub(crate) fn normalize_date(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(utc(date));
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(text) {
+        return Some(utc(date));
+    }
+    for format in ZONED_FORMATS.iter() {
+        if let Ok(date) = DateTime::parse_from_str(text, format) {
+            return Some(utc(date));
+        }
+    }
+    for format in LOCAL_FORMATS.iter() {
+        if let Ok(date) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(date.format("%Y-%m-%dT%H:%M:%S").to_string());
+        }
+    }
+    // Ordinal suffixes and a leading weekday, e.g. "Thursday, May 6th, 2021"
+    let date_text = strip_weekday(text)
+        .replace("1st", "1")
+        .replace("2nd", "2")
+        .replace("3rd", "3")
+        .replace("th,", ",")
+        .replace("th ", " ");
+    for format in DATE_FORMATS.iter() {
+        if let Ok(date) = NaiveDate::parse_from_str(&date_text, format) {
+            return Some(date.format("%Y-%m-%d").to_string());
+        }
+    }
+    None
+}
+
+fn strip_weekday(text: &str) -> &str {
+    match text.find(", ") {
+        Some(pos) if text[..pos].chars().all(|c| c.is_alphabetic()) && pos >= 3 => {
+            let weekday = text[..pos].to_lowercase();
+            let is_weekday = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]
+                .iter()
+                .any(|prefix| weekday.starts_with(prefix));
+            if is_weekday {
+                &text[pos + 2..]
+            } else {
+                text
+            }
+        }
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_known_formats() {
+        let cases = [
+            // RFC 3339 and other zoned dates, converted to UTC
+            ("2021-05-06T12:34:56Z", "2021-05-06T12:34:56Z"),
+            ("2021-05-06T12:34:56+02:00", "2021-05-06T10:34:56Z"),
+            ("2021-05-06T12:34:56.789Z", "2021-05-06T12:34:56Z"),
+            ("Thu, 06 May 2021 12:34:56 +0000", "2021-05-06T12:34:56Z"),
+            ("2021-05-06 12:34:56 +0200", "2021-05-06T10:34:56Z"),
+            // Local times
+            ("2021-05-06T12:34:56", "2021-05-06T12:34:56"),
+            ("2021-05-06T12:34", "2021-05-06T12:34:00"),
+            ("2021/05/06 12:34:56", "2021-05-06T12:34:56"),
+            // Dates only
+            ("2021-05-06", "2021-05-06"),
+            (" 2021/05/06 ", "2021-05-06"),
+            ("2021.05.06", "2021-05-06"),
+            // Textual dates
+            ("May 6, 2021", "2021-05-06"),
+            ("Sep 21st, 2021", "2021-09-21"),
+            ("Thursday, May 6th, 2021", "2021-05-06"),
+            ("6 May 2021", "2021-05-06"),
+            ("6 Sep 2021", "2021-09-06"),
+        ];
+        for (text, expected) in cases.iter() {
+            assert_eq!(normalize_date(text).as_deref(), Some(*expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_formats() {
+        let cases = [
+            "",
+            "   ",
+            "yesterday",
+            "3 days ago",
+            // Ambiguous day-first or month-first dates
+            "06/05/2021",
+            // Months in other languages than English
+            "6 mai 2021",
+            "2021-13-01",
+        ];
+        for text in cases.iter() {
+            assert_eq!(normalize_date(text), None, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Append-only binary log of per-record pipeline decisions, so questions like "how many Russian
+// pages from domain X were dropped after cleaning" can be answered without rerunning extraction.
+//
+// The file starts with the magic bytes `CCQAEVT1`, followed by events encoded as
+//
+//     u64 record index | u8 stage | u8 outcome | u8 len + reason | u16 len + language | u16 len + uri
+//
+// with all integers in little endian.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::sync::Mutex;
+
+const MAGIC: &[u8; 8] = b"CCQAEVT1";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Stage {
+    Dedup = 0,
+    Prefilter = 1,
+    Parse = 2,
+    Extract = 3,
+    Clean = 4,
+    Output = 5,
+}
+
+const ALL_STAGES: [Stage; 6] = [
+    Stage::Dedup,
+    Stage::Prefilter,
+    Stage::Parse,
+    Stage::Extract,
+    Stage::Clean,
+    Stage::Output,
+];
+
+impl Stage {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Stage::Dedup => "dedup",
+            Stage::Prefilter => "prefilter",
+            Stage::Parse => "parse",
+            Stage::Extract => "extract",
+            Stage::Clean => "clean",
+            Stage::Output => "output",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Outcome {
+    Kept = 0,
+    Dropped = 1,
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Kept => "kept",
+            Outcome::Dropped => "dropped",
+        }
+    }
+}
+
+pub(crate) struct Event<'a> {
+    pub(crate) record_index: u64,
+    pub(crate) stage: Stage,
+    pub(crate) outcome: Outcome,
+    pub(crate) reason: &'a str,
+    pub(crate) language: &'a str,
+    pub(crate) uri: &'a str,
+}
+
+// Cut a string to at most `max` bytes without splitting a character
+fn truncate(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+fn encode(event: &Event, buffer: &mut Vec<u8>) {
+    let reason = truncate(event.reason, u8::MAX as usize);
+    let language = truncate(event.language, u16::MAX as usize);
+    let uri = truncate(event.uri, u16::MAX as usize);
+    buffer.extend_from_slice(&event.record_index.to_le_bytes());
+    buffer.push(event.stage as u8);
+    buffer.push(event.outcome as u8);
+    buffer.push(reason.len() as u8);
+    buffer.extend_from_slice(reason.as_bytes());
+    buffer.extend_from_slice(&(language.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(language.as_bytes());
+    buffer.extend_from_slice(&(uri.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(uri.as_bytes());
+}
+
+pub(crate) struct EventLog {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl EventLog {
+    pub(crate) fn open(path: &str) -> std::io::Result<EventLog> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(MAGIC)?;
+        }
+        Ok(EventLog {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub(crate) fn log(&self, event: &Event) {
+        // Encode outside of the lock and append each event with a single write
+        let mut buffer = Vec::with_capacity(64 + event.uri.len());
+        encode(event, &mut buffer);
+        let mut writer = self.writer.lock().unwrap();
+        writer
+            .write_all(&buffer)
+            .expect("Failed to write to the event log");
+    }
+
+    pub(crate) fn flush(&self) -> std::io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+// Decoded event with owned strings
+struct LoggedEvent {
+    stage: u8,
+    outcome: u8,
+    reason: String,
+    language: String,
+    uri: String,
+}
+
+fn read_string<R: Read>(reader: &mut R, len: usize) -> std::io::Result<String> {
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+// Read the next event, returning None at the end of the log
+fn read_event<R: Read>(reader: &mut R) -> std::io::Result<Option<LoggedEvent>> {
+    let mut record_index = [0u8; 8];
+    match reader.read_exact(&mut record_index) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let mut fixed = [0u8; 3];
+    reader.read_exact(&mut fixed)?;
+    let reason = read_string(reader, fixed[2] as usize)?;
+    let mut len = [0u8; 2];
+    reader.read_exact(&mut len)?;
+    let language = read_string(reader, u16::from_le_bytes(len) as usize)?;
+    reader.read_exact(&mut len)?;
+    let uri = read_string(reader, u16::from_le_bytes(len) as usize)?;
+    Ok(Some(LoggedEvent {
+        stage: fixed[0],
+        outcome: fixed[1],
+        reason,
+        language,
+        uri,
+    }))
+}
+
+// Host part of a URI, used to aggregate events by domain
+pub(crate) fn domain(uri: &str) -> &str {
+    let without_scheme = match uri.find("://") {
+        Some(pos) => &uri[pos + 3..],
+        None => uri,
+    };
+    without_scheme
+        .split(|c| c == '/' || c == '?' || c == '#' || c == ':')
+        .next()
+        .unwrap_or("")
+}
+
+// Replay an event log, printing the number of events per stage, outcome and reason
+// (optionally further split by language and/or domain) as TSV
+pub(crate) fn summarize(path: &str, by_language: bool, by_domain: bool) -> std::io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is not a CCQA event log", path),
+        ));
+    }
+    let mut counts: BTreeMap<Vec<String>, u64> = BTreeMap::new();
+    while let Some(event) = read_event(&mut reader)? {
+        let stage = ALL_STAGES
+            .iter()
+            .find(|stage| **stage as u8 == event.stage)
+            .map_or("unknown", |stage| stage.name());
+        let outcome = if event.outcome == Outcome::Kept as u8 {
+            Outcome::Kept.name()
+        } else {
+            Outcome::Dropped.name()
+        };
+        let mut key = vec![stage.to_string(), outcome.to_string(), event.reason];
+        if by_language {
+            key.push(event.language);
+        }
+        if by_domain {
+            key.push(domain(&event.uri).to_string());
+        }
+        *counts.entry(key).or_insert(0) += 1;
+    }
+    for (key, count) in counts.iter() {
+        println!("{}\t{}", key.join("\t"), count);
+    }
+    Ok(())
+}
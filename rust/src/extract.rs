@@ -0,0 +1,654 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Question extraction from a single HTML document, independent of the WARC input and the output
+// formats, for applications embedding the cleaning. Caveats of the extraction (lenient itemtype
+// matches, fallbacks, truncations, incomplete markup) are returned as warnings next to every
+// Question rather than only counted by the WARC pipeline.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use kuchiki::traits::*;
+use kuchiki::NodeRef;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::amp;
+use crate::confidence;
+use crate::events;
+use crate::itemtype;
+use crate::jsonld::{self, QuestionSource};
+use crate::langrules::{BidiControls, CleaningRules, Entities, LanguageRules};
+use crate::microdata::has_microdata;
+use crate::plaintext::{self, TextMode};
+use crate::profile::CleaningProfile;
+use crate::qa;
+use crate::whitelist::DedupWhitelist;
+use crate::{
+    find_lang_tag, is_question_scope, reduce_breaks, reduce_tilde, remove_empty_nodes,
+    resolve_itemrefs, transform_inside, transform_outside,
+};
+
+// Language of documents without a lang attribute
+pub(crate) const UNKNOWN_LANGUAGE: &str = "-";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtractOptions {
+    pub itemtypes: itemtype::Itemtypes,
+    pub lenient_itemtypes: bool,
+    // Vocabularies whose itemtypes are mapped onto schema.org before the extraction
+    pub vocabularies: Vec<&'static itemtype::Vocabulary>,
+    pub schema: itemtype::QuestionSchema,
+    pub bidi_controls: BidiControls,
+    pub cleaning_profile: CleaningProfile,
+    pub strip_joiners: bool,
+    pub entities: Entities,
+    // Markup or plain text of the cleaned Questions
+    pub text_mode: TextMode,
+    // Keep the uncleaned HTML of every Question
+    pub keep_raw: bool,
+    pub question_source: QuestionSource,
+    // Keep the text direction (dir attributes) of the Questions and their parts
+    pub keep_direction: bool,
+    // Questions with fewer answers (with a text) are dropped
+    pub min_answers: usize,
+    // Drop Questions identical to an earlier one with the same parent (related-question widgets)
+    pub dedup_questions: bool,
+    // Questions, or pages, that dedup_questions never drops
+    pub dedup_whitelist: Option<Arc<DedupWhitelist>>,
+}
+
+// The defaults of the command line
+impl Default for ExtractOptions {
+    fn default() -> ExtractOptions {
+        ExtractOptions {
+            itemtypes: itemtype::Itemtypes::default(),
+            lenient_itemtypes: false,
+            vocabularies: Vec::new(),
+            schema: itemtype::QuestionSchema::All,
+            bidi_controls: BidiControls::Strip,
+            cleaning_profile: CleaningProfile::Pretraining,
+            strip_joiners: false,
+            entities: Entities::Encode,
+            text_mode: TextMode::Markup,
+            keep_raw: false,
+            question_source: QuestionSource::Microdata,
+            keep_direction: false,
+            min_answers: 0,
+            dedup_questions: true,
+            dedup_whitelist: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExtractWarning {
+    // Matched through a misspelled itemtype (lenient_itemtypes)
+    LenientItemtype(&'static str),
+    // Read from the JSON-LD of an AMP page without microdata Questions
+    JsonLdFallback,
+    // The document (or its WARC record) was cut off, later Questions or answers may be missing
+    Truncated,
+    // The document has no lang attribute, the default language rules were used
+    MissingLanguage,
+    // Neither a name nor a text property survived the cleaning
+    MissingQuestionText,
+    // Neither an acceptedAnswer nor a suggestedAnswer
+    MissingAnswer,
+}
+
+impl fmt::Display for ExtractWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExtractWarning::LenientItemtype(variant) => {
+                write!(f, "matched the misspelled itemtype {}", variant)
+            }
+            ExtractWarning::JsonLdFallback => {
+                write!(f, "read from the JSON-LD of an AMP page without microdata")
+            }
+            ExtractWarning::Truncated => write!(f, "truncated document"),
+            ExtractWarning::MissingLanguage => {
+                write!(f, "no lang attribute, default language rules used")
+            }
+            ExtractWarning::MissingQuestionText => write!(f, "no question name or text"),
+            ExtractWarning::MissingAnswer => write!(f, "no answer"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExtractError {
+    // No element carries a Question itemtype
+    NoQuestion,
+    // Every Question was empty once cleaned
+    EmptyAfterCleaning,
+    // Questions were found, but none of the selected schema
+    OtherSchema,
+    // Every Question had fewer answers than min_answers
+    TooFewAnswers,
+    // The extraction was aborted through its cancellation token
+    Cancelled,
+}
+
+impl ExtractError {
+    // Pipeline stage reported in the event log and the rejection stream
+    pub(crate) fn stage(&self) -> events::Stage {
+        match self {
+            ExtractError::NoQuestion => events::Stage::Extract,
+            ExtractError::EmptyAfterCleaning => events::Stage::Clean,
+            ExtractError::OtherSchema => events::Stage::Extract,
+            ExtractError::TooFewAnswers => events::Stage::Extract,
+            ExtractError::Cancelled => events::Stage::Extract,
+        }
+    }
+
+    pub(crate) fn reason(&self) -> &'static str {
+        match self {
+            ExtractError::NoQuestion => "no Question element",
+            ExtractError::EmptyAfterCleaning => "empty after cleaning",
+            ExtractError::OtherSchema => "no Question of the selected schema",
+            ExtractError::TooFewAnswers => "too few answers",
+            ExtractError::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason())
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+// Cooperative cancellation for applications aborting long extractions, e.g. from a GUI or a
+// service shutting down. Clones share the flag; the record loop checks it before every record,
+// the extraction before every Question and the DOM traversals at every node.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// A single cleaned Question
+pub struct Extracted {
+    // Minified HTML
+    pub html: String,
+    // HTML before cleaning, if requested
+    pub raw_html: Option<String>,
+    // The cleaned subtree itself, for further inspection
+    pub node: NodeRef,
+    // Caveats for embedding applications, which lower the extraction confidence of the record
+    pub warnings: Vec<ExtractWarning>,
+    // Index of the Question this one was nested in (e.g. a related-question widget)
+    pub parent: Option<usize>,
+    // Keywords and tag-list topics, collected before the cleaning removes the tag lists
+    pub tags: Vec<String>,
+    // Whether the Question is an entry of a FAQPage container
+    pub faqpage: bool,
+}
+
+const DIRECTIONS: [&str; 3] = ["ltr", "rtl", "auto"];
+
+// Direction set by the closest valid dir attribute at or above the node, up to <html>
+fn direction(node: &NodeRef) -> Option<String> {
+    node.inclusive_ancestors().find_map(|ancestor| {
+        ancestor
+            .as_element()
+            .and_then(|element| {
+                element
+                    .attributes
+                    .borrow()
+                    .get("dir")
+                    .map(|dir| dir.trim().to_lowercase())
+            })
+            .filter(|dir| DIRECTIONS.contains(&dir.as_str()))
+    })
+}
+
+// The cleaning drops the dir attributes along with the elements carrying them, mostly wrappers
+// or <html> itself. Collect the directions to restore before: on the Question, and on every
+// item* element whose direction differs from the enclosing one.
+fn collect_directions(question: &NodeRef) -> Vec<(NodeRef, String)> {
+    let mut directions = Vec::new();
+    if let Some(dir) = direction(question) {
+        directions.push((question.clone(), dir));
+    }
+    for node in question.descendants().filter(has_microdata) {
+        let enclosing = node
+            .ancestors()
+            .find(|ancestor| ancestor == question || has_microdata(ancestor))
+            .and_then(|enclosing| direction(&enclosing));
+        match direction(&node) {
+            Some(dir) if Some(&dir) != enclosing.as_ref() => directions.push((node, dir)),
+            _ => {}
+        }
+    }
+    directions
+}
+
+// Whether a Question is an entry of a FAQPage
+fn in_faqpage(question: &NodeRef) -> bool {
+    question.ancestors().any(|ancestor| {
+        ancestor.as_element().map_or(false, |element| {
+            element
+                .attributes
+                .borrow()
+                .get("itemtype")
+                .map_or(false, itemtype::is_faqpage_itemtype)
+        })
+    })
+}
+
+// Identifiers of an uncleaned Question looked up in the dedup whitelist: its itemid and its own
+// url property, which the cleaning removes
+fn question_ids(question: &NodeRef) -> Vec<String> {
+    let is_scope = |node: &NodeRef| {
+        node.as_element().map_or(false, |element| {
+            element.attributes.borrow().contains("itemscope")
+        })
+    };
+    let mut ids = Vec::new();
+    for node in question.inclusive_descendants() {
+        let element = match node.as_element() {
+            Some(element) => element,
+            None => continue,
+        };
+        let attributes = element.attributes.borrow();
+        if node == *question {
+            ids.extend(
+                attributes
+                    .get("itemid")
+                    .map(|itemid| itemid.trim().to_string()),
+            );
+            continue;
+        }
+        let is_url = attributes.get("itemprop").map_or(false, |itemprop| {
+            itemprop.split_whitespace().any(|prop| prop == "url")
+        });
+        if is_url && node.ancestors().find(is_scope).as_ref() == Some(question) {
+            let url = attributes.get("href").or_else(|| attributes.get("content"));
+            ids.extend(url.map(|url| url.trim().to_string()));
+        }
+    }
+    ids
+}
+
+// Detach the Questions nested in a Question, so their content isn't duplicated inside it, and
+// list them on their own right after it together with the Question they were nested in
+fn denest(
+    question: NodeRef,
+    parent: Option<NodeRef>,
+    options: &ExtractOptions,
+    denested: &mut Vec<(NodeRef, Option<NodeRef>)>,
+) {
+    let is_scope =
+        |node: &NodeRef| is_question_scope(node, &options.itemtypes, options.lenient_itemtypes);
+    // Only the outermost nested ones, deeper ones are nested in those
+    let nested = question
+        .descendants()
+        .filter(|node| is_scope(node))
+        .filter(|node| {
+            node.ancestors()
+                .find(|ancestor| is_scope(ancestor))
+                .as_ref()
+                == Some(&question)
+        })
+        .collect::<Vec<NodeRef>>();
+    for node in nested.iter() {
+        node.detach();
+    }
+    denested.push((question.clone(), parent));
+    for node in nested {
+        denest(node, Some(question.clone()), options, denested);
+    }
+}
+
+fn contains_ignore_ascii_case(bytes: &[u8], needle: &[u8]) -> bool {
+    bytes
+        .windows(needle.len())
+        .any(|window| window.eq_ignore_ascii_case(needle))
+}
+
+// Whether a document opens <html> but never closes it, as when cut off by the crawler's size
+// limit. Fragments without <html> aren't taken for truncated. The closing tag is looked for from
+// the end, where it is found right away on complete pages.
+pub(crate) fn is_truncated(html: &str) -> bool {
+    let bytes = html.as_bytes();
+    contains_ignore_ascii_case(bytes, b"<html")
+        && !bytes
+            .windows(b"</html".len())
+            .rev()
+            .any(|window| window.eq_ignore_ascii_case(b"</html"))
+}
+
+// Extract the cleaned Questions of an HTML document
+pub fn minify_html(
+    html: &str,
+    options: &ExtractOptions,
+    cancellation: &CancellationToken,
+) -> Result<Vec<Extracted>, ExtractError> {
+    let document = kuchiki::parse_html().one(html);
+    let language = find_lang_tag(document.clone()).unwrap_or_else(|| UNKNOWN_LANGUAGE.to_string());
+    let mut extracted = minify_document(document, &language, &[], options, cancellation)?;
+    if is_truncated(html) {
+        for question in extracted.iter_mut() {
+            question.warnings.push(ExtractWarning::Truncated);
+        }
+    }
+    Ok(extracted)
+}
+
+// Extract the cleaned Questions of an already parsed document in the given language. Everything
+// outside of the Questions is removed from the document. The record id and URIs of the page are
+// looked up in the dedup whitelist, a whitelisted page keeps all its Questions.
+pub(crate) fn minify_document(
+    document: NodeRef,
+    language: &str,
+    page_ids: &[&str],
+    options: &ExtractOptions,
+    cancellation: &CancellationToken,
+) -> Result<Vec<Extracted>, ExtractError> {
+    let amp = amp::is_amp_document(&document);
+    if amp {
+        amp::unwrap_components(&document);
+    }
+    // AMP pages often only give their Questions as JSON-LD, read unless microdata Questions exist
+    let read_jsonld = options.question_source.jsonld()
+        || (amp
+            && transform_outside(
+                document.clone(),
+                &options.itemtypes,
+                options.lenient_itemtypes,
+                cancellation,
+            )
+            .is_none());
+    // Only the JSON-LD was read as a fallback
+    let jsonld_fallback = read_jsonld && !options.question_source.jsonld();
+    let jsonld_questions = if read_jsonld {
+        jsonld::question_nodes(&document)
+    } else {
+        Vec::new()
+    };
+    let document = if options.question_source.microdata() {
+        itemtype::map_vocabularies(&document, &options.vocabularies);
+        document
+    } else {
+        NodeRef::new_document()
+    };
+    // JSON-LD Questions join the document as microdata, to go through the same extraction
+    let root = match document.select_first("body") {
+        Ok(body) => body.as_node().clone(),
+        Err(_) => document.clone(),
+    };
+    for question in jsonld_questions {
+        root.append(question);
+    }
+    // The traversals stop early once cancelled, their partial results are discarded here
+    let questions = transform_outside(
+        document.clone(),
+        &options.itemtypes,
+        options.lenient_itemtypes,
+        cancellation,
+    );
+    if cancellation.is_cancelled() {
+        return Err(ExtractError::Cancelled);
+    }
+    let questions = questions.ok_or(ExtractError::NoQuestion)?;
+    for question in questions.iter() {
+        resolve_itemrefs(&document, question);
+    }
+    let questions = questions
+        .into_iter()
+        .filter(|question| options.schema.accepts(in_faqpage(question)))
+        .collect::<Vec<NodeRef>>();
+    if questions.is_empty() {
+        return Err(ExtractError::OtherSchema);
+    }
+    // FAQ entries and the Questions nested in them, before denesting detaches those
+    let faqpage_questions = questions
+        .iter()
+        .filter(|question| in_faqpage(question))
+        .flat_map(|question| question.descendants())
+        .filter(|node| is_question_scope(node, &options.itemtypes, options.lenient_itemtypes))
+        .collect::<Vec<NodeRef>>();
+    let mut denested = Vec::new();
+    for question in questions {
+        denest(question, None, options, &mut denested);
+    }
+    let rules = CleaningRules {
+        language: LanguageRules::for_language(language),
+        bidi: options.bidi_controls,
+        profile: options.cleaning_profile,
+        strip_joiners: options.strip_joiners,
+        entities: options.entities,
+    };
+    let mut extracted: Vec<Extracted> = Vec::new();
+    let mut too_few_answers = false;
+    // Kept Question of every parent and hash of the cleaned HTML, and the kept Question every
+    // dropped duplicate stands for, so the Questions nested in a duplicate are dropped as well
+    let mut seen_questions: HashMap<(Option<usize>, u64), usize> = HashMap::new();
+    let mut duplicates: Vec<(NodeRef, usize)> = Vec::new();
+    let whitelisted_page = options.dedup_whitelist.as_ref().map_or(false, |whitelist| {
+        page_ids.iter().any(|id| whitelist.contains(id, None))
+    });
+    for (question, parent) in denested {
+        if cancellation.is_cancelled() {
+            return Err(ExtractError::Cancelled);
+        }
+        let mut warnings = Vec::new();
+        if language == UNKNOWN_LANGUAGE {
+            warnings.push(ExtractWarning::MissingLanguage);
+        }
+        let variant = question.as_element().and_then(|element| {
+            element
+                .attributes
+                .borrow()
+                .get("itemtype")
+                .and_then(itemtype::question_variant)
+        });
+        if let Some(variant) = variant {
+            warnings.push(ExtractWarning::LenientItemtype(variant.name()));
+        }
+        if jsonld_fallback {
+            warnings.push(ExtractWarning::JsonLdFallback);
+        }
+        let raw_html = if options.keep_raw {
+            Some(question.to_string())
+        } else {
+            None
+        };
+        let tags = qa::find_tags(&question);
+        let whitelisted = whitelisted_page
+            || options.dedup_whitelist.as_ref().map_or(false, |whitelist| {
+                question_ids(&question)
+                    .iter()
+                    .any(|id| whitelist.contains(id, None))
+            });
+        let faqpage = faqpage_questions.contains(&question);
+        let directions = if options.keep_direction {
+            collect_directions(&question)
+        } else {
+            Vec::new()
+        };
+        // Remove everything without item* attribute inside
+        transform_inside(question.clone(), rules, cancellation);
+        if cancellation.is_cancelled() {
+            return Err(ExtractError::Cancelled);
+        }
+        for (node, dir) in directions {
+            if let Some(element) = node.as_element() {
+                element.attributes.borrow_mut().insert("dir", dir);
+            }
+        }
+        remove_empty_nodes(question.clone());
+        // Remove newline and carriage returns from the data to avoid additional linebreaks
+        let mut html = question.to_string().replace("\n", "").replace("\r", "");
+        html = reduce_tilde(html);
+        html = reduce_breaks(html);
+        if options.text_mode == TextMode::Plain {
+            html = plaintext::to_text(&question, options.entities);
+        }
+        if html.is_empty() {
+            continue;
+        }
+        if options.min_answers > 0 && qa::count_answers(&question) < options.min_answers {
+            too_few_answers = true;
+            continue;
+        }
+        let signals = confidence::question_signals(&question);
+        if !signals.has_question_text {
+            warnings.push(ExtractWarning::MissingQuestionText);
+        }
+        if !signals.has_answer {
+            warnings.push(ExtractWarning::MissingAnswer);
+        }
+        // The parent comes first, unless it was empty once cleaned
+        let parent = parent.and_then(|parent| {
+            extracted
+                .iter()
+                .position(|extracted| extracted.node == parent)
+                .or_else(|| {
+                    duplicates
+                        .iter()
+                        .find(|(duplicate, _)| *duplicate == parent)
+                        .map(|(_, original)| *original)
+                })
+        });
+        if options.dedup_questions {
+            let key = (parent, xxh3_64(html.as_bytes()));
+            if let Some(original) = seen_questions.get(&key) {
+                if !whitelisted {
+                    duplicates.push((question, *original));
+                    continue;
+                }
+            } else {
+                seen_questions.insert(key, extracted.len());
+            }
+        }
+        extracted.push(Extracted {
+            html,
+            raw_html,
+            node: question,
+            warnings,
+            parent,
+            tags,
+            faqpage,
+        });
+    }
+    if extracted.is_empty() {
+        if too_few_answers {
+            return Err(ExtractError::TooFewAnswers);
+        }
+        return Err(ExtractError::EmptyAfterCleaning);
+    }
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<html lang="en"><body><div itemscope itemtype="https://schema.org/Question">
+        <span itemprop="name">Why?</span><div itemprop="acceptedAnswer" itemscope
+        itemtype="https://schema.org/Answer"><span itemprop="text">Because.</span></div>
+        </div></body></html>"#;
+
+    #[test]
+    fn extracts_until_cancelled() {
+        let cancellation = CancellationToken::new();
+        let extracted = minify_html(PAGE, &ExtractOptions::default(), &cancellation).unwrap();
+        assert_eq!(extracted.len(), 1);
+        assert!(extracted[0].html.contains("Because."));
+        cancellation.cancel();
+        assert_eq!(
+            minify_html(PAGE, &ExtractOptions::default(), &cancellation).err(),
+            Some(ExtractError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn warns_about_truncated_documents() {
+        let options = ExtractOptions::default();
+        let cancellation = CancellationToken::new();
+        let extracted = minify_html(PAGE, &options, &cancellation).unwrap();
+        assert!(extracted[0].warnings.is_empty());
+        let cut = &PAGE[..PAGE.find("</div></body>").unwrap()];
+        let extracted = minify_html(cut, &options, &cancellation).unwrap();
+        assert_eq!(extracted[0].warnings, vec![ExtractWarning::Truncated]);
+        // Fragments have no <html> to close
+        let fragment = &cut[cut.find("<div").unwrap()..];
+        let extracted = minify_html(fragment, &options, &cancellation).unwrap();
+        assert!(!extracted[0].warnings.contains(&ExtractWarning::Truncated));
+    }
+
+    #[test]
+    fn warns_about_the_amp_json_ld_fallback() {
+        let page = r#"<html amp lang="en"><head><script type="application/ld+json">
+            {"@type": "Question", "name": "Why?",
+            "acceptedAnswer": {"@type": "Answer", "text": "Because."}}
+            </script></head><body></body></html>"#;
+        let cancellation = CancellationToken::new();
+        let extracted = minify_html(page, &ExtractOptions::default(), &cancellation).unwrap();
+        assert_eq!(extracted[0].warnings, vec![ExtractWarning::JsonLdFallback]);
+        // Not a fallback when JSON-LD is read anyway
+        let options = ExtractOptions {
+            question_source: QuestionSource::Both,
+            ..ExtractOptions::default()
+        };
+        let extracted = minify_html(page, &options, &cancellation).unwrap();
+        assert!(extracted[0].warnings.is_empty());
+    }
+
+    #[test]
+    fn keeps_whitelisted_duplicate_questions() {
+        let question = r#"<div itemscope itemtype="https://schema.org/Question">
+            <a itemprop="url" href="https://example.com/q/1"></a><span itemprop="name">Why?</span>
+            </div>"#;
+        let page = format!(r#"<html lang="en"><body>{0}{0}</body></html>"#, question);
+        let whitelist = |entries: &str| {
+            let path = std::env::temp_dir().join(format!("ccqa-whitelist-{}", std::process::id()));
+            std::fs::write(&path, entries).unwrap();
+            let whitelist = DedupWhitelist::load(path.to_str().unwrap()).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            Some(Arc::new(whitelist))
+        };
+        let cancellation = CancellationToken::new();
+        let count = |options: &ExtractOptions, page_ids: &[&str]| {
+            let document = kuchiki::parse_html().one(page.as_str());
+            minify_document(document, "en", page_ids, options, &cancellation)
+                .unwrap()
+                .len()
+        };
+        assert_eq!(count(&ExtractOptions::default(), &[]), 1);
+        let by_question = ExtractOptions {
+            dedup_whitelist: whitelist("https://example.com/q/1\n"),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(count(&by_question, &[]), 2);
+        let by_page = ExtractOptions {
+            dedup_whitelist: whitelist("https://example.com/page\n"),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(count(&by_page, &[]), 1);
+        assert_eq!(count(&by_page, &["https://example.com/page"]), 2);
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Export of minified outputs as a HuggingFace datasets directory, loadable with
+// `datasets.load_dataset("<dir>")` or `datasets.load_dataset("json", data_dir="<dir>/data")`:
+//
+//     <dir>/dataset_infos.json
+//     <dir>/data/train-00000-of-00002.jsonl
+//     <dir>/data/train-00001-of-00002.jsonl
+//
+// with one data file per input. The json loader infers a single schema for all files, so every
+// row carries every column of the columnar schema, and the free-form metadata and network objects
+// are JSON encoded like in the columnar outputs.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+use arrow::datatypes::DataType;
+use serde_json::{json, Map, Value};
+
+use crate::columnar;
+use crate::output;
+use crate::HTMLMinified;
+
+fn hf_row(record: &HTMLMinified) -> std::io::Result<Value> {
+    let mut row = match serde_json::to_value(record)? {
+        Value::Object(row) => row,
+        _ => unreachable!(),
+    };
+    row.insert(
+        "metadata".to_string(),
+        match &record.metadata {
+            Some(metadata) => Value::String(Value::Object(metadata.clone()).to_string()),
+            None => Value::Null,
+        },
+    );
+    row.insert(
+        "network".to_string(),
+        match &record.network {
+            Some(network) => Value::String(serde_json::to_string(network)?),
+            None => Value::Null,
+        },
+    );
+    // Fields skipped when empty are written out with their empty value
+    for field in columnar::schema().fields() {
+        if !row.contains_key(field.name()) {
+            let empty = match field.data_type() {
+                DataType::List(_) => json!([]),
+                DataType::Boolean => json!(false),
+                _ => Value::Null,
+            };
+            row.insert(field.name().clone(), empty);
+        }
+    }
+    Ok(Value::Object(row))
+}
+
+// datasets feature type of a columnar field
+fn hf_feature(data_type: &DataType) -> Value {
+    match data_type {
+        DataType::List(item) => json!({
+            "feature": hf_feature(item.data_type()),
+            "_type": "Sequence",
+        }),
+        DataType::Boolean => json!({"dtype": "bool", "_type": "Value"}),
+        DataType::UInt64 => json!({"dtype": "uint64", "_type": "Value"}),
+        DataType::Float64 => json!({"dtype": "float64", "_type": "Value"}),
+        _ => json!({"dtype": "string", "_type": "Value"}),
+    }
+}
+
+pub(crate) fn export(inputs: &[&str], dir: &str, split: &str) -> std::io::Result<()> {
+    let data_dir = Path::new(dir).join("data");
+    std::fs::create_dir_all(&data_dir)?;
+    let mut num_examples = 0;
+    let mut num_bytes = 0;
+    for (idx, input) in inputs.iter().enumerate() {
+        let records = output::read_records(Path::new(input))?;
+        let file_name = format!("{}-{:05}-of-{:05}.jsonl", split, idx, inputs.len());
+        let mut writer = BufWriter::new(File::create(data_dir.join(&file_name))?);
+        for record in records.iter() {
+            let mut line = serde_json::to_vec(&hf_row(record)?)?;
+            line.push(b'\n');
+            writer.write_all(&line)?;
+            num_bytes += line.len() as u64;
+        }
+        writer.flush()?;
+        num_examples += records.len() as u64;
+        eprintln!(
+            "Exported {} records of {} to {}",
+            records.len(),
+            input,
+            file_name
+        );
+    }
+
+    let features = columnar::schema()
+        .fields()
+        .iter()
+        .map(|field| (field.name().clone(), hf_feature(field.data_type())))
+        .collect::<Map<String, Value>>();
+    let infos = json!({
+        "default": {
+            "description": "CCQA: A New Web-Scale Question Answering Dataset for Model Pre-Training",
+            "citation": "",
+            "homepage": "https://github.com/facebookresearch/CCQA",
+            "license": "",
+            "features": features,
+            "builder_name": "json",
+            "config_name": "default",
+            "splits": {
+                split: {
+                    "name": split,
+                    "num_bytes": num_bytes,
+                    "num_examples": num_examples,
+                    "dataset_name": "json",
+                },
+            },
+            "dataset_size": num_bytes,
+        },
+    });
+    std::fs::write(
+        Path::new(dir).join("dataset_infos.json"),
+        serde_json::to_string_pretty(&infos)?,
+    )?;
+    println!(
+        "Exported {} records in {} files to {}",
+        num_examples,
+        inputs.len(),
+        dir
+    );
+    Ok(())
+}
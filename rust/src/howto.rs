@@ -0,0 +1,132 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// HowTo markup (https://schema.org/HowTo), written as instruction-style pre-training data into a
+// parallel stream next to the output (--write-howto), one JSON line per HowTo:
+//
+//     {"id":"...","uri":"...","language":"en","name":"How to repot a cactus",
+//      "description":"...","steps":["Put on gloves.","Loosen the soil.",...]}
+//
+// The steps are the HowToStep items of the HowTo in document order, including those grouped
+// into HowToSection items. A step contributes its text property, otherwise all of its text.
+
+use kuchiki::NodeRef;
+use serde::Serialize;
+
+use crate::microdata::{attribute, collapse_whitespace, has_itemprop, is_type, scope};
+use crate::{output, simd};
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct HowTo {
+    pub(crate) name: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) steps: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct HowToRecord<'a> {
+    id: &'a str,
+    uri: Option<&'a str>,
+    language: &'a str,
+    #[serde(flatten)]
+    howto: &'a HowTo,
+}
+
+// Cheap check of the raw page for HowTo markup before building the DOM
+pub(crate) fn contains_howto(text: &str) -> bool {
+    simd::find(text.as_bytes(), b"schema.org/HowTo").is_some()
+}
+
+// Text of the first `prop` property of an item, from its content attribute (meta) or its text
+fn property(item: &NodeRef, prop: &str) -> Option<String> {
+    let node = item
+        .descendants()
+        .find(|node| has_itemprop(node, prop) && scope(node).as_ref() == Some(item))?;
+    let text = attribute(&node, "content").unwrap_or_else(|| node.text_contents());
+    let text = collapse_whitespace(&text);
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn steps(howto: &NodeRef) -> Vec<String> {
+    howto
+        .descendants()
+        .filter(|node| is_type(node, "HowToStep"))
+        // Steps of a HowTo nested into this one belong to the nested one
+        .filter(|step| {
+            step.ancestors()
+                .find(|ancestor| is_type(ancestor, "HowTo"))
+                .as_ref()
+                == Some(howto)
+        })
+        .filter_map(|step| {
+            property(&step, "text").or_else(|| {
+                let text = collapse_whitespace(&step.text_contents());
+                if text.is_empty() {
+                    None
+                } else {
+                    Some(text)
+                }
+            })
+        })
+        .collect()
+}
+
+// All HowTo items of a document with at least one step, in document order
+pub(crate) fn extract(document: &NodeRef) -> Vec<HowTo> {
+    document
+        .descendants()
+        .filter(|node| is_type(node, "HowTo"))
+        .map(|howto| HowTo {
+            name: property(&howto, "name"),
+            description: property(&howto, "description"),
+            steps: steps(&howto),
+        })
+        .filter(|howto| !howto.steps.is_empty())
+        .collect()
+}
+
+// JSON Lines stream of the HowTos of all webpages
+pub(crate) struct HowToLog {
+    file: output::StreamFile,
+}
+
+impl HowToLog {
+    pub(crate) fn create(path: &str) -> std::io::Result<HowToLog> {
+        Ok(HowToLog {
+            file: output::StreamFile::create(path)?,
+        })
+    }
+
+    pub(crate) fn log(
+        &self,
+        id: &str,
+        uri: Option<&str>,
+        language: &str,
+        howtos: &[HowTo],
+    ) -> std::io::Result<()> {
+        // Serialize outside of the lock, so lines of concurrent workers never interleave
+        let mut lines = Vec::new();
+        for howto in howtos.iter() {
+            let record = HowToRecord {
+                id,
+                uri,
+                language,
+                howto,
+            };
+            serde_json::to_writer(&mut lines, &record)?;
+            lines.push(b'\n');
+        }
+        self.file.write_all(&lines)
+    }
+
+    pub(crate) fn finish(&self) -> std::io::Result<()> {
+        self.file.finish()
+    }
+}
@@ -0,0 +1,403 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// The HTTP response stored in a WARC record body: the crawler's response headers, followed by
+// the page payload. Pages in legacy encodings (GBK, Shift-JIS, Windows-1251, ...) are decoded into
+// proper text rather than mangled into mojibake. The encoding is found like browsers do: a byte
+// order mark, otherwise the charset of the Content-Type header, otherwise the charset of a
+// <meta> within the first 1024 bytes, otherwise UTF-8 if the payload is valid UTF-8, otherwise a
+// guess from the payload bytes.
+//
+// Some records store the payload as it was sent, still compressed with the Content-Encoding
+// (gzip, deflate or br) of its header, and it is decompressed before anything reads it. Likewise
+// payloads sent with `Transfer-Encoding: chunked` are de-chunked first, so the chunk-size lines
+// don't end up in the HTML.
+
+use std::borrow::Cow;
+use std::io::prelude::*;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+
+// Bytes of the payload searched for a <meta> charset, as in the HTML prescan
+const META_PRESCAN_BYTES: usize = 1024;
+
+// Bound on decompressed payloads, against decompression bombs
+const MAX_DECOMPRESSED_BYTES: u64 = 32 * 1024 * 1024;
+
+pub(crate) struct Response<'a> {
+    // The header block, one header per line
+    headers: String,
+    payload: Cow<'a, [u8]>,
+}
+
+impl<'a> Response<'a> {
+    // Split a record body into headers and payload, None if it has no header block
+    pub(crate) fn parse(body: &'a [u8]) -> Option<Response<'a>> {
+        let pos = body.windows(4).position(|w| w == b"\r\n\r\n")?;
+        let mut response = Response {
+            headers: String::from_utf8_lossy(&body[..pos]).to_string(),
+            payload: Cow::Borrowed(&body[pos + 4..]),
+        };
+        let chunked = response
+            .header("transfer-encoding")
+            .map_or(false, |codings| {
+                codings
+                    .split(',')
+                    .any(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+            });
+        if chunked {
+            if let Some(payload) = dechunk(&response.payload) {
+                response.payload = Cow::Owned(payload);
+            }
+        }
+        if let Some(codings) = response.header("content-encoding").map(String::from) {
+            // Codings are listed in the order they were applied
+            for coding in codings.rsplit(',') {
+                if let Some(payload) = decompress(coding.trim(), &response.payload) {
+                    response.payload = Cow::Owned(payload);
+                }
+            }
+        }
+        Some(response)
+    }
+
+    // Value of the first header of that name, names being case insensitive
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_at(line.find(':')?);
+            if key.trim().eq_ignore_ascii_case(name) {
+                Some(value[1..].trim())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub(crate) fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    // The payload as text, decoded from its encoding
+    pub(crate) fn text(&self) -> Cow<str> {
+        decode_html(self.header("content-type"), &self.payload)
+    }
+}
+
+// Payload of a chunked transfer coding without its chunk-size lines, None if it isn't chunked
+// (crawlers often de-chunk payloads but keep the header): every chunk needs a hexadecimal size
+// line and to end with a line break. Payloads truncated within a chunk are de-chunked as far as
+// they go.
+fn dechunk(payload: &[u8]) -> Option<Vec<u8>> {
+    let mut dechunked = Vec::with_capacity(payload.len());
+    let mut rest = payload;
+    loop {
+        let line_end = match rest.iter().position(|&byte| byte == b'\n') {
+            Some(line_end) => line_end,
+            None if dechunked.is_empty() => return None,
+            // Truncated within a size line
+            None => break,
+        };
+        // Chunk extensions follow the size after a semicolon
+        let line = &rest[..line_end];
+        let size = line.split(|&byte| byte == b';').next().unwrap_or_default();
+        let size = std::str::from_utf8(size).ok()?.trim();
+        if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+        let size = usize::from_str_radix(size, 16).ok()?;
+        if size == 0 {
+            break;
+        }
+        rest = &rest[line_end + 1..];
+        if rest.len() <= size {
+            // Truncated within the chunk data
+            dechunked.extend_from_slice(rest);
+            break;
+        }
+        dechunked.extend_from_slice(&rest[..size]);
+        rest = &rest[size..];
+        // The chunk data ends with a line break of its own
+        if rest.starts_with(b"\r\n") {
+            rest = &rest[2..];
+        } else if rest.starts_with(b"\n") {
+            rest = &rest[1..];
+        } else if rest != b"\r" {
+            return None;
+        }
+    }
+    Some(dechunked)
+}
+
+// Payload decompressed from a content coding, None for unknown codings and payloads that aren't
+// encoded with it after all (crawlers often decode them but keep the header). Truncated payloads
+// are decompressed as far as they go.
+fn decompress(coding: &str, payload: &[u8]) -> Option<Vec<u8>> {
+    // Markup is never compressed
+    if payload.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'<') {
+        return None;
+    }
+    let decoder: Box<dyn Read> = match coding.to_ascii_lowercase().as_str() {
+        "gzip" | "x-gzip" if payload.starts_with(b"\x1f\x8b") => {
+            Box::new(MultiGzDecoder::new(payload))
+        }
+        // Many servers send raw deflate streams instead of the zlib format of the standard
+        "deflate" if payload.first().map_or(false, |byte| byte & 0x0f == 8) => {
+            Box::new(ZlibDecoder::new(payload))
+        }
+        "deflate" => Box::new(DeflateDecoder::new(payload)),
+        "br" => Box::new(brotli_decompressor::Decompressor::new(payload, 4096)),
+        _ => return None,
+    };
+    let mut decompressed = Vec::new();
+    let result = decoder
+        .take(MAX_DECOMPRESSED_BYTES)
+        .read_to_end(&mut decompressed);
+    if result.is_err() && decompressed.is_empty() {
+        return None;
+    }
+    Some(decompressed)
+}
+
+// Charset parameter of a Content-Type value, e.g. text/html; charset="windows-1251"
+fn charset_parameter(content_type: &[u8]) -> Option<&'static Encoding> {
+    lazy_static! {
+        static ref CHARSET: Regex =
+            Regex::new(r#"(?i)charset\s*=\s*["']?([A-Za-z0-9._:-]+)"#).unwrap();
+    }
+    let label = CHARSET.captures(content_type)?.get(1)?.as_bytes();
+    Encoding::for_label(label)
+}
+
+// Decode an HTML payload, with the Content-Type header value if known
+pub(crate) fn decode_html<'a>(content_type: Option<&str>, payload: &'a [u8]) -> Cow<'a, str> {
+    let declared = content_type
+        .and_then(|content_type| charset_parameter(content_type.as_bytes()))
+        .or_else(|| {
+            let prescan = &payload[..payload.len().min(META_PRESCAN_BYTES)];
+            lazy_static! {
+                static ref META: Regex = Regex::new(r"(?i)<meta\s[^>]*charset").unwrap();
+            }
+            let meta = META.find(prescan)?;
+            // A UTF-16 payload can't declare itself in ASCII, such declarations mean UTF-8
+            charset_parameter(&prescan[meta.start()..]).map(|encoding| {
+                if encoding == UTF_16LE || encoding == UTF_16BE {
+                    UTF_8
+                } else {
+                    encoding
+                }
+            })
+        });
+    let encoding = match declared {
+        Some(encoding) => encoding,
+        None if std::str::from_utf8(payload).is_ok() => UTF_8,
+        None => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(payload, true);
+            detector.guess(None, true)
+        }
+    };
+    // A byte order mark takes precedence over any declaration
+    encoding.decode(payload).0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use encoding_rs::{GBK, SHIFT_JIS, WINDOWS_1251};
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    use super::*;
+
+    fn encoded(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+        encoding.encode(text).0.into_owned()
+    }
+
+    #[test]
+    fn byte_order_mark_overrides_declaration() {
+        let mut payload = b"\xEF\xBB\xBF".to_vec();
+        payload.extend_from_slice("<p>Café</p>".as_bytes());
+        let text = decode_html(Some("text/html; charset=windows-1252"), &payload);
+        assert_eq!(text, "<p>Café</p>");
+    }
+
+    #[test]
+    fn decodes_content_type_charset() {
+        let payload = encoded(WINDOWS_1251, "<p>Привет</p>");
+        assert_eq!(
+            decode_html(Some("text/html; charset=\"windows-1251\""), &payload),
+            "<p>Привет</p>"
+        );
+    }
+
+    #[test]
+    fn decodes_meta_charset_within_prescan() {
+        let html = "<html><head><meta charset=\"windows-1251\"></head><body>Привет</body></html>";
+        let payload = encoded(WINDOWS_1251, html);
+        assert_eq!(decode_html(None, &payload), html);
+        let html = "<html><head><meta http-equiv=\"Content-Type\" \
+                    content=\"text/html; charset=windows-1251\"></head><body>Привет</body></html>";
+        let payload = encoded(WINDOWS_1251, html);
+        assert_eq!(decode_html(None, &payload), html);
+    }
+
+    #[test]
+    fn ignores_meta_charset_beyond_prescan() {
+        // Valid UTF-8, which a late windows-1251 declaration would turn into mojibake
+        let html = format!(
+            "<html><head><!--{}--><meta charset=\"windows-1251\"></head><body>Café</body></html>",
+            " ".repeat(META_PRESCAN_BYTES)
+        );
+        assert_eq!(decode_html(None, html.as_bytes()), html);
+    }
+
+    #[test]
+    fn utf_16_meta_charset_means_utf_8() {
+        let html = "<html><head><meta charset=\"utf-16\"></head><body>Café</body></html>";
+        assert_eq!(decode_html(None, html.as_bytes()), html);
+    }
+
+    #[test]
+    fn guesses_undeclared_encodings() {
+        let chinese = "<html><body><p>我们的网站提供最新的新闻和信息，欢迎大家阅读和分享。\
+                       这个问题的答案是什么？请在下面留言告诉我们。</p></body></html>";
+        assert_eq!(decode_html(None, &encoded(GBK, chinese)), chinese);
+        let japanese = "<html><body><p>日本語のテキストです。この質問に答えてください。\
+                        よろしくお願いします。ありがとうございました。</p></body></html>";
+        assert_eq!(decode_html(None, &encoded(SHIFT_JIS, japanese)), japanese);
+    }
+
+    const HTML: &str = "<html><body><p>How do I repot a cactus?</p></body></html>";
+
+    fn gzip(data: &[u8], level: Compression) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // Brotli stream of a single uncompressed meta-block (up to 64 KiB): window bits 16 (0), not
+    // last (0), four length nibbles (00), the length minus one and uncompressed (1), the data from
+    // the next byte on, and an empty last meta-block
+    fn brotli_stored(data: &[u8]) -> Vec<u8> {
+        let header = ((data.len() as u32 - 1) << 4) | (1 << 20);
+        let mut stream = header.to_le_bytes()[..3].to_vec();
+        stream.extend_from_slice(data);
+        stream.push(0x03);
+        stream
+    }
+
+    #[test]
+    fn decompresses_every_gzip_member() {
+        let mut payload = gzip(b"<p>Hello ", Compression::default());
+        payload.extend(gzip(b"world</p>", Compression::default()));
+        assert_eq!(decompress("gzip", &payload).unwrap(), b"<p>Hello world</p>");
+        assert_eq!(
+            decompress("X-GZip", &payload).unwrap(),
+            b"<p>Hello world</p>"
+        );
+    }
+
+    #[test]
+    fn decompresses_zlib_and_raw_deflate() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(HTML.as_bytes()).unwrap();
+        let zlib = zlib.finish().unwrap();
+        assert_eq!(decompress("deflate", &zlib).unwrap(), HTML.as_bytes());
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(HTML.as_bytes()).unwrap();
+        let raw = raw.finish().unwrap();
+        assert_eq!(decompress("deflate", &raw).unwrap(), HTML.as_bytes());
+    }
+
+    #[test]
+    fn decompresses_brotli() {
+        let payload = brotli_stored(HTML.as_bytes());
+        assert_eq!(decompress("br", &payload).unwrap(), HTML.as_bytes());
+    }
+
+    #[test]
+    fn keeps_decoded_and_unknown_payloads() {
+        for coding in ["gzip", "deflate", "br"].iter() {
+            assert_eq!(decompress(coding, HTML.as_bytes()), None, "{}", coding);
+            assert_eq!(decompress(coding, b"\r\n  <p>Hi</p>"), None, "{}", coding);
+        }
+        assert_eq!(decompress("gzip", b"Hello"), None);
+        let payload = gzip(HTML.as_bytes(), Compression::default());
+        assert_eq!(decompress("compress", &payload), None);
+        assert_eq!(decompress("identity", &payload), None);
+    }
+
+    #[test]
+    fn decompresses_truncated_payloads_as_far_as_they_go() {
+        let html = HTML.repeat(100);
+        let payload = gzip(html.as_bytes(), Compression::none());
+        let decompressed = decompress("gzip", &payload[..payload.len() / 2]).unwrap();
+        assert!(!decompressed.is_empty());
+        assert!(decompressed.len() < html.len());
+        assert!(html.as_bytes().starts_with(&decompressed));
+    }
+
+    #[test]
+    fn undoes_stacked_codings_in_reverse() {
+        let mut body = b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip, br\r\n\r\n".to_vec();
+        body.extend(brotli_stored(&gzip(
+            HTML.as_bytes(),
+            Compression::default(),
+        )));
+        let response = Response::parse(&body).unwrap();
+        assert_eq!(response.payload(), HTML.as_bytes());
+    }
+
+    #[test]
+    fn caps_decompressed_payloads() {
+        let bomb = vec![b' '; MAX_DECOMPRESSED_BYTES as usize + 1024 * 1024];
+        let payload = gzip(&bomb, Compression::fast());
+        let decompressed = decompress("gzip", &payload).unwrap();
+        assert_eq!(decompressed.len() as u64, MAX_DECOMPRESSED_BYTES);
+    }
+
+    #[test]
+    fn dechunks_payloads() {
+        let payload = b"5;name=value\r\nHello\r\n6\r\n World\r\n0\r\n\r\n";
+        assert_eq!(dechunk(payload).unwrap(), b"Hello World");
+        let payload = b"5\nHello\n6 \n World\n0\n\n";
+        assert_eq!(dechunk(payload).unwrap(), b"Hello World");
+        let body = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n0\r\n\r\n";
+        assert_eq!(Response::parse(body).unwrap().payload(), b"Hello");
+    }
+
+    #[test]
+    fn dechunks_truncated_payloads_as_far_as_they_go() {
+        assert_eq!(dechunk(b"5\r\nHello\r\n10\r\n Wor").unwrap(), b"Hello Wor");
+        assert_eq!(dechunk(b"5\r\nHello\r\n1").unwrap(), b"Hello");
+        assert_eq!(dechunk(b"5\r\nHello\r").unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn keeps_payloads_that_are_not_chunked() {
+        assert_eq!(dechunk(HTML.as_bytes()), None);
+        assert_eq!(dechunk(b"Hello"), None);
+        // A hexadecimal first line, but not followed by a chunk of that size
+        assert_eq!(
+            dechunk(b"12\r\nDecember 2021 archive\r\nof the forum"),
+            None
+        );
+        assert_eq!(dechunk(b"cafe latte\r\nrecipes"), None);
+        let body = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{}",
+            HTML
+        );
+        assert_eq!(
+            Response::parse(body.as_bytes()).unwrap().payload(),
+            HTML.as_bytes()
+        );
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Offline network enrichment of records carrying a WARC-IP-Address, from local tables only (no
+// lookups during extraction):
+//
+// * ASN table (`--asn-table`): `range_start \t range_end \t AS number \t country \t AS name` rows,
+//   the layout of the iptoasn.com ip2asn dumps (IPv4, IPv6 or combined)
+// * Reverse DNS table (`--reverse-dns`): `ip \t hostname` rows, e.g. extracted from a PTR dump
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct NetworkInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) asn: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) as_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) hostname: Option<String>,
+}
+
+struct AsnRange {
+    start: u128,
+    end: u128,
+    asn: u32,
+    country: String,
+    as_name: String,
+}
+
+// IPv4 addresses are mapped into the IPv6 space, so both share one sorted range table
+fn ip_key(ip: &IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(*ip),
+    }
+}
+
+fn invalid_table(path: &str, line: usize, err: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Invalid network table {} on line {}: {}", path, line, err),
+    )
+}
+
+#[derive(Default)]
+pub(crate) struct NetworkTables {
+    asn_ranges: Vec<AsnRange>,
+    reverse_dns: HashMap<IpAddr, String>,
+}
+
+impl NetworkTables {
+    pub(crate) fn load(
+        asn_table: Option<&str>,
+        reverse_dns: Option<&str>,
+    ) -> std::io::Result<NetworkTables> {
+        let mut tables = NetworkTables::default();
+        if let Some(path) = asn_table {
+            for (idx, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+                let fields = line.split('\t').collect::<Vec<&str>>();
+                if fields.len() < 5 {
+                    return Err(invalid_table(path, idx + 1, "expected 5 fields"));
+                }
+                let parse_ip = |field: &str| {
+                    field
+                        .parse::<IpAddr>()
+                        .map_err(|err| invalid_table(path, idx + 1, &err.to_string()))
+                };
+                let asn = fields[2]
+                    .parse()
+                    .map_err(|_| invalid_table(path, idx + 1, "AS number is not an integer"))?;
+                // AS 0 marks ranges not routed by anyone
+                if asn == 0 {
+                    continue;
+                }
+                tables.asn_ranges.push(AsnRange {
+                    start: ip_key(&parse_ip(fields[0])?),
+                    end: ip_key(&parse_ip(fields[1])?),
+                    asn,
+                    country: fields[3].to_string(),
+                    as_name: fields[4].to_string(),
+                });
+            }
+            tables.asn_ranges.sort_by_key(|range| range.start);
+        }
+        if let Some(path) = reverse_dns {
+            for (idx, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+                let mut fields = line.splitn(2, '\t');
+                let ip = fields
+                    .next()
+                    .unwrap_or("")
+                    .parse::<IpAddr>()
+                    .map_err(|err| invalid_table(path, idx + 1, &err.to_string()))?;
+                if let Some(hostname) = fields.next() {
+                    tables.reverse_dns.insert(ip, hostname.trim().to_string());
+                }
+            }
+        }
+        Ok(tables)
+    }
+
+    fn asn_range(&self, key: u128) -> Option<&AsnRange> {
+        // Last range starting at or before the address
+        let idx = self.asn_ranges.partition_point(|range| range.start <= key);
+        if idx == 0 {
+            return None;
+        }
+        let range = &self.asn_ranges[idx - 1];
+        if key <= range.end {
+            Some(range)
+        } else {
+            None
+        }
+    }
+
+    // Network information for an IP address header value, None if nothing is known about it
+    pub(crate) fn lookup(&self, ip_address: &str) -> Option<NetworkInfo> {
+        let ip = ip_address.trim().parse::<IpAddr>().ok()?;
+        let mut info = NetworkInfo::default();
+        if let Some(range) = self.asn_range(ip_key(&ip)) {
+            info.asn = Some(range.asn);
+            info.as_name = Some(range.as_name.clone());
+            info.country = Some(range.country.clone());
+        }
+        info.hostname = self.reverse_dns.get(&ip).cloned();
+        if info == NetworkInfo::default() {
+            None
+        } else {
+            Some(info)
+        }
+    }
+}
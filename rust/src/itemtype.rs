@@ -0,0 +1,581 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use kuchiki::NodeRef;
+use lazy_static::lazy_static;
+use regex::{Regex, RegexSet};
+
+use crate::simd;
+
+pub(crate) const QUESTION_ITEMTYPE: &str = "https://schema.org/Question";
+
+// Part of the Question itemtype shared by all its spellings
+const QUESTION_PATH: &str = "schema.org/Question";
+
+// Containers of Question pages, with the Question (QAPage) or the Questions (FAQPage) as their
+// mainEntity
+const QAPAGE_PATH: &str = "schema.org/QAPage";
+const FAQPAGE_PATH: &str = "schema.org/FAQPage";
+
+// Kinds of Questions extracted (--schema)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuestionSchema {
+    All,
+    // Forum-style Questions, outside of FAQPage containers
+    Question,
+    // Question/acceptedAnswer pairs of FAQPage containers
+    FaqPage,
+}
+
+impl QuestionSchema {
+    pub fn from_name(name: &str) -> Option<QuestionSchema> {
+        match name {
+            "all" => Some(QuestionSchema::All),
+            "question" => Some(QuestionSchema::Question),
+            "faqpage" => Some(QuestionSchema::FaqPage),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn accepts(&self, in_faqpage: bool) -> bool {
+        match self {
+            QuestionSchema::All => true,
+            QuestionSchema::Question => !in_faqpage,
+            QuestionSchema::FaqPage => in_faqpage,
+        }
+    }
+}
+
+// Whether an itemtype attribute declares the schema.org Question type, in any of its common
+// spellings: https:// or http://, protocol-relative or without protocol, with www. or a trailing
+// slash. An itemtype may list several types separated by whitespace.
+pub(crate) fn is_question_itemtype(itemtype: &str) -> bool {
+    itemtype
+        .split_whitespace()
+        .any(|item| without_protocol(item) == QUESTION_PATH)
+}
+
+pub(crate) fn is_page_itemtype(itemtype: &str) -> bool {
+    itemtype.split_whitespace().any(|item| {
+        let path = without_protocol(item);
+        path == QAPAGE_PATH || path == FAQPAGE_PATH
+    })
+}
+
+pub(crate) fn is_faqpage_itemtype(itemtype: &str) -> bool {
+    itemtype
+        .split_whitespace()
+        .any(|item| without_protocol(item) == FAQPAGE_PATH)
+}
+
+// Whether an itemtype attribute declares the schema.org type `name`, in any spelling
+pub(crate) fn is_schema_type(itemtype: &str, name: &str) -> bool {
+    itemtype.split_whitespace().any(|item| {
+        without_protocol(item)
+            .strip_prefix("schema.org/")
+            .map_or(false, |item_name| item_name == name)
+    })
+}
+
+// Itemtypes whose items are extracted (--itemtype), the schema.org Question by default. Any
+// schema.org entity (Review, Recipe, ...) goes through the same minification.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Itemtypes {
+    // Without protocol, e.g. schema.org/Review
+    paths: Vec<String>,
+}
+
+impl Default for Itemtypes {
+    fn default() -> Itemtypes {
+        Itemtypes {
+            paths: vec![QUESTION_PATH.to_string()],
+        }
+    }
+}
+
+impl Itemtypes {
+    pub fn new<'a>(itemtypes: impl IntoIterator<Item = &'a str>) -> Itemtypes {
+        Itemtypes {
+            paths: itemtypes
+                .into_iter()
+                .map(|itemtype| without_protocol(itemtype.trim()).to_string())
+                .collect(),
+        }
+    }
+
+    // Whether the Question itemtype is one of them, which enables the Question specific handling
+    // (QAPage and FAQPage containers, misspelled and legacy itemtypes)
+    pub(crate) fn includes_question(&self) -> bool {
+        self.paths.iter().any(|path| path == QUESTION_PATH)
+    }
+
+    pub(crate) fn matches(&self, itemtype: &str) -> bool {
+        itemtype.split_whitespace().any(|item| {
+            let item = without_protocol(item);
+            self.paths.iter().any(|path| path == item)
+        })
+    }
+
+    // Prefilter of the raw page for any of the itemtypes
+    pub(crate) fn contains_any(&self, text: &str) -> bool {
+        (self.includes_question() && contains_question(text))
+            || self
+                .paths
+                .iter()
+                .any(|path| simd::find(text.as_bytes(), path.as_bytes()).is_some())
+    }
+}
+
+// A single itemtype URL without protocol, www. and trailing slash
+fn without_protocol(item: &str) -> &str {
+    item.trim_start_matches("https:")
+        .trim_start_matches("http:")
+        .trim_start_matches("//")
+        .trim_start_matches("www.")
+        .trim_end_matches('/')
+}
+
+// Prefilter of the raw page for any spelling of the Question itemtype, or of a QAPage or
+// FAQPage whose mainEntity may lack it
+pub(crate) fn contains_question(text: &str) -> bool {
+    [QUESTION_PATH, QAPAGE_PATH, FAQPAGE_PATH]
+        .iter()
+        .any(|path| simd::find(text.as_bytes(), path.as_bytes()).is_some())
+}
+
+// Prefilter of the raw page for a FAQPage, as microdata or JSON-LD type
+pub(crate) fn contains_faqpage(text: &str) -> bool {
+    simd::find(text.as_bytes(), b"FAQPage").is_some()
+}
+
+// Structured-data vocabulary predating schema.org whose Q&A markup is mapped onto schema.org
+// (--vocabulary), to recover the Questions of older pages
+#[derive(Debug, PartialEq)]
+pub struct Vocabulary {
+    pub(crate) name: &'static str,
+    // Question itemtype of the vocabulary, without protocol
+    question: &'static str,
+    // Itemtypes of the vocabulary, without protocol, and their schema.org equivalents
+    types: &'static [(&'static str, &'static str)],
+}
+
+pub(crate) static VOCABULARIES: [Vocabulary; 1] = [Vocabulary {
+    name: "data-vocabulary.org",
+    question: "data-vocabulary.org/Question",
+    types: &[
+        (
+            "data-vocabulary.org/Question",
+            "https://schema.org/Question",
+        ),
+        ("data-vocabulary.org/Answer", "https://schema.org/Answer"),
+        ("data-vocabulary.org/Person", "https://schema.org/Person"),
+    ],
+}];
+
+impl Vocabulary {
+    pub fn from_name(name: &str) -> Option<&'static Vocabulary> {
+        VOCABULARIES
+            .iter()
+            .find(|vocabulary| vocabulary.name == name)
+    }
+
+    pub(crate) fn contains_question(&self, text: &str) -> bool {
+        simd::find(text.as_bytes(), self.question.as_bytes()).is_some()
+    }
+
+    // The itemtype attribute with every type of the vocabulary replaced by its schema.org
+    // equivalent, if it has any
+    fn schema_itemtype(&self, itemtype: &str) -> Option<String> {
+        let mut mapped = false;
+        let items = itemtype
+            .split_whitespace()
+            .map(|item| {
+                let path = without_protocol(item);
+                match self.types.iter().find(|(name, _)| *name == path) {
+                    Some((_, schema_type)) => {
+                        mapped = true;
+                        *schema_type
+                    }
+                    None => item,
+                }
+            })
+            .collect::<Vec<&str>>();
+        if mapped {
+            Some(items.join(" "))
+        } else {
+            None
+        }
+    }
+}
+
+// Cheap confirmation of a prefilter hit for the Question itemtype of any of `vocabularies`, like
+// has_question_itemtype_attribute does for schema.org
+pub(crate) fn has_vocabulary_question_attribute(text: &str, vocabularies: &[&Vocabulary]) -> bool {
+    lazy_static! {
+        static ref ATTRIBUTES: Vec<Regex> = VOCABULARIES
+            .iter()
+            .map(|vocabulary| {
+                Regex::new(&format!(
+                    r#"itemtype\s*=\s*["']?[^"'>]*{}\b"#,
+                    regex::escape(vocabulary.question)
+                ))
+                .unwrap()
+            })
+            .collect();
+    }
+    VOCABULARIES
+        .iter()
+        .zip(ATTRIBUTES.iter())
+        .any(|(vocabulary, attribute)| {
+            vocabularies.contains(&vocabulary) && attribute.is_match(text)
+        })
+}
+
+// Prefilter of the raw page for the Question itemtype of schema.org or any of `vocabularies`
+pub(crate) fn contains_any_question(text: &str, vocabularies: &[&Vocabulary]) -> bool {
+    contains_question(text)
+        || vocabularies
+            .iter()
+            .any(|vocabulary| vocabulary.contains_question(text))
+}
+
+// Rewrite the itemtypes of `vocabularies` in a document to their schema.org equivalents, so the
+// extraction and every consumer of the output only deal with schema.org. Returns the number of
+// rewritten attributes.
+pub(crate) fn map_vocabularies(document: &NodeRef, vocabularies: &[&Vocabulary]) -> usize {
+    if vocabularies.is_empty() {
+        return 0;
+    }
+    let elements = match document.select("[itemtype]") {
+        Ok(elements) => elements,
+        Err(_) => return 0,
+    };
+    let mut mapped = 0;
+    for element in elements {
+        let mut attributes = element.attributes.borrow_mut();
+        let itemtype = match attributes.get("itemtype") {
+            Some(itemtype) => itemtype.to_string(),
+            None => continue,
+        };
+        let schema_itemtype = vocabularies
+            .iter()
+            .find_map(|vocabulary| vocabulary.schema_itemtype(&itemtype));
+        if let Some(schema_itemtype) = schema_itemtype {
+            attributes.insert("itemtype", schema_itemtype);
+            mapped += 1;
+        }
+    }
+    mapped
+}
+
+// Common misspellings of the Question itemtype found in the wild
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ItemtypeVariant {
+    // https://schema.og/Question
+    SchemaOg,
+    // https://scheme.org/Question
+    SchemeOrg,
+    // itemtype="Question" without any vocabulary URL
+    BareQuestion,
+}
+
+pub(crate) const ALL_VARIANTS: [ItemtypeVariant; 3] = [
+    ItemtypeVariant::SchemaOg,
+    ItemtypeVariant::SchemeOrg,
+    ItemtypeVariant::BareQuestion,
+];
+
+impl ItemtypeVariant {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ItemtypeVariant::SchemaOg => "schema.og/Question",
+            ItemtypeVariant::SchemeOrg => "scheme.org/Question",
+            ItemtypeVariant::BareQuestion => "Question",
+        }
+    }
+}
+
+// Classify an itemtype attribute value as one of the known misspellings
+pub(crate) fn question_variant(itemtype: &str) -> Option<ItemtypeVariant> {
+    let itemtype = itemtype.trim();
+    let path = itemtype
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_start_matches("www.");
+    if path == "schema.og/Question" {
+        Some(ItemtypeVariant::SchemaOg)
+    } else if path == "scheme.org/Question" {
+        Some(ItemtypeVariant::SchemeOrg)
+    } else if itemtype == "Question" {
+        Some(ItemtypeVariant::BareQuestion)
+    } else {
+        None
+    }
+}
+
+// Find all misspelled Question itemtypes in a raw document with a single pass
+pub(crate) fn find_variants(text: &str) -> Vec<ItemtypeVariant> {
+    lazy_static! {
+        static ref SET: RegexSet = RegexSet::new(&[
+            r"schema\.og/Question",
+            r"scheme\.org/Question",
+            r#"itemtype\s*=\s*["']?Question["'\s>]"#,
+        ])
+        .unwrap();
+    }
+    SET.matches(text)
+        .into_iter()
+        .map(|idx| ALL_VARIANTS[idx])
+        .collect()
+}
+
+// Cheap confirmation of a prefilter hit without building the DOM: the Question itemtype has to
+// appear as (one of the values of) an itemtype attribute, not just somewhere in the page
+pub(crate) fn has_question_itemtype_attribute(text: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r#"itemtype\s*=\s*["']?[^"'>]*schema\.org/Question\b"#).unwrap();
+    }
+    RE.is_match(text)
+}
+
+// Number of pages containing each misspelled itemtype, shared between the processing threads
+#[derive(Default)]
+pub(crate) struct VariantCounts {
+    counts: [AtomicU64; 3],
+}
+
+impl VariantCounts {
+    pub(crate) fn add(&self, variant: ItemtypeVariant) {
+        self.counts[variant as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn report(&self, lenient: bool) {
+        for variant in ALL_VARIANTS.iter() {
+            let count = self.counts[*variant as usize].load(Ordering::Relaxed);
+            if count > 0 {
+                eprintln!(
+                    "Found {} pages with itemtype variant {} ({})",
+                    count,
+                    variant.name(),
+                    if lenient { "accepted" } else { "ignored" }
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use super::*;
+    use crate::extract::CancellationToken;
+
+    const SPELLINGS: [&str; 7] = [
+        "https://schema.org/Question",
+        "http://schema.org/Question",
+        "//schema.org/Question",
+        "schema.org/Question",
+        "https://schema.org/Question/",
+        "http://www.schema.org/Question",
+        " https://schema.org/Question ",
+    ];
+
+    #[test]
+    fn matches_every_spelling() {
+        for itemtype in SPELLINGS.iter() {
+            assert!(is_question_itemtype(itemtype), "{}", itemtype);
+        }
+    }
+
+    #[test]
+    fn matches_one_of_several_types() {
+        assert!(is_question_itemtype(
+            "https://schema.org/Thing http://schema.org/Question"
+        ));
+    }
+
+    #[test]
+    fn rejects_other_types() {
+        for itemtype in [
+            "https://schema.org/Answer",
+            "https://schema.org/QuestionAnswer",
+            "https://example.org/schema.org/Question",
+            "https://schema.og/Question",
+            "Question",
+            "",
+        ]
+        .iter()
+        {
+            assert!(!is_question_itemtype(itemtype), "{}", itemtype);
+        }
+    }
+
+    #[test]
+    fn prefilter_finds_every_spelling() {
+        for itemtype in SPELLINGS.iter() {
+            let page = format!(r#"<div itemscope itemtype="{}"></div>"#, itemtype);
+            assert!(contains_question(&page), "{}", itemtype);
+            assert!(has_question_itemtype_attribute(&page), "{}", itemtype);
+        }
+    }
+
+    #[test]
+    fn extraction_finds_every_spelling() {
+        for itemtype in SPELLINGS.iter() {
+            let document = kuchiki::parse_html().one(format!(
+                r#"<html><body><div itemscope itemtype="{}"><span itemprop="name">Why?</span></div></body></html>"#,
+                itemtype
+            ));
+            let questions = crate::transform_outside(
+                document,
+                &Itemtypes::default(),
+                false,
+                &CancellationToken::new(),
+            );
+            assert_eq!(
+                questions.map(|questions| questions.len()),
+                Some(1),
+                "{}",
+                itemtype
+            );
+        }
+    }
+
+    #[test]
+    fn maps_vocabulary_itemtypes_to_schema_org() {
+        let document = kuchiki::parse_html().one(
+            r#"<html><body><div itemscope itemtype="http://data-vocabulary.org/Question">
+            <span itemprop="name">Why?</span>
+            <div itemprop="acceptedAnswer" itemscope itemtype="http://data-vocabulary.org/Answer">
+            <span itemprop="text">Because.</span></div></div></body></html>"#,
+        );
+        let vocabularies = [Vocabulary::from_name("data-vocabulary.org").unwrap()];
+        assert!(contains_any_question(&document.to_string(), &vocabularies));
+        assert!(has_vocabulary_question_attribute(
+            &document.to_string(),
+            &vocabularies
+        ));
+        assert_eq!(map_vocabularies(&document, &vocabularies), 2);
+        let itemtypes = document
+            .select("[itemtype]")
+            .unwrap()
+            .map(|element| {
+                element
+                    .attributes
+                    .borrow()
+                    .get("itemtype")
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            itemtypes,
+            vec!["https://schema.org/Question", "https://schema.org/Answer"]
+        );
+        assert_eq!(map_vocabularies(&document, &vocabularies), 0);
+    }
+
+    #[test]
+    fn ignores_vocabularies_not_selected() {
+        let page = r#"<div itemscope itemtype="http://data-vocabulary.org/Question"></div>"#;
+        assert!(!contains_any_question(page, &[]));
+        assert!(!has_vocabulary_question_attribute(page, &[]));
+        let document = kuchiki::parse_html().one(page);
+        assert_eq!(map_vocabularies(&document, &[]), 0);
+        assert_eq!(
+            crate::transform_outside(
+                document,
+                &Itemtypes::default(),
+                false,
+                &CancellationToken::new()
+            )
+            .map(|q| q.len()),
+            None
+        );
+    }
+
+    #[test]
+    fn extracts_the_main_entity_of_pages() {
+        let questions = [
+            r#"<div itemprop="mainEntity" itemscope itemtype="https://schema.org/Question">"#,
+            r#"<div itemprop="mainEntity" itemscope>"#,
+        ];
+        let pages = ["https://schema.org/QAPage", "https://schema.org/FAQPage"];
+        for (question, page) in questions
+            .iter()
+            .flat_map(|question| pages.iter().map(move |page| (question, page)))
+        {
+            let page = format!(
+                r#"<html><body><div itemscope itemtype="{}">
+                <h1>Site</h1>{}<span itemprop="name">Why?</span></div></div></body></html>"#,
+                page, question
+            );
+            assert!(contains_question(&page));
+            let document = kuchiki::parse_html().one(page);
+            let questions = crate::transform_outside(
+                document,
+                &Itemtypes::default(),
+                false,
+                &CancellationToken::new(),
+            )
+            .unwrap();
+            assert_eq!(questions.len(), 1);
+            let itemtype = questions[0].as_element().and_then(|element| {
+                element
+                    .attributes
+                    .borrow()
+                    .get("itemtype")
+                    .map(String::from)
+            });
+            assert_eq!(itemtype.as_deref(), Some(QUESTION_ITEMTYPE));
+        }
+    }
+
+    #[test]
+    fn selects_questions_by_schema() {
+        assert!(QuestionSchema::All.accepts(true) && QuestionSchema::All.accepts(false));
+        assert!(QuestionSchema::Question.accepts(false) && !QuestionSchema::Question.accepts(true));
+        assert!(QuestionSchema::FaqPage.accepts(true) && !QuestionSchema::FaqPage.accepts(false));
+        assert!(contains_faqpage(r#"{"@type": "FAQPage"}"#));
+    }
+
+    #[test]
+    fn ignores_main_entities_outside_of_pages() {
+        let document = kuchiki::parse_html().one(
+            r#"<html><body><div itemscope itemtype="https://schema.org/WebPage">
+            <div itemprop="mainEntity" itemscope><span itemprop="name">Why?</span></div>
+            </div></body></html>"#,
+        );
+        assert!(crate::transform_outside(
+            document,
+            &Itemtypes::default(),
+            false,
+            &CancellationToken::new()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn extracts_configured_itemtypes() {
+        let document = kuchiki::parse_html().one(
+            r#"<html><body><div itemscope itemtype="http://schema.org/Review">
+            <span itemprop="reviewBody">Great.</span></div>
+            <div itemscope itemtype="https://schema.org/Question"></div></body></html>"#,
+        );
+        let itemtypes = Itemtypes::new(vec!["https://schema.org/Review"]);
+        assert!(!itemtypes.includes_question());
+        assert!(itemtypes.contains_any(&document.to_string()));
+        let items =
+            crate::transform_outside(document, &itemtypes, false, &CancellationToken::new())
+                .unwrap();
+        assert_eq!(items.len(), 1);
+        assert!(items[0].text_contents().contains("Great."));
+    }
+}
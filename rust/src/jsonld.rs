@@ -0,0 +1,242 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Questions embedded as JSON-LD (`<script type="application/ld+json">`) instead of microdata.
+// They are rewritten into the equivalent microdata subtrees, so the cleaning and every output
+// format treat them exactly like Questions marked up in the page itself:
+//
+//     {"@type": "Question", "name": "...", "acceptedAnswer": {"@type": "Answer", "text": "..."}}
+//
+// becomes
+//
+//     <div itemscope itemtype="https://schema.org/Question"><div itemprop="name">...</div>
+//     <div itemprop="acceptedAnswer" itemscope itemtype="https://schema.org/Answer">
+//     <div itemprop="text">...</div></div></div>
+//
+// Questions are found anywhere in a block, including the mainEntity of QAPage and FAQPage
+// objects and @graph arrays. Questions of a FAQPage are wrapped into a FAQPage item.
+
+use kuchiki::traits::*;
+use kuchiki::NodeRef;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::itemtype;
+
+const ANSWER_ITEMTYPE: &str = "https://schema.org/Answer";
+const FAQPAGE_ITEMTYPE: &str = "https://schema.org/FAQPage";
+
+// Which markup Questions are extracted from (--question-source)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuestionSource {
+    Microdata,
+    JsonLd,
+    Both,
+}
+
+impl QuestionSource {
+    pub fn from_name(name: &str) -> Option<QuestionSource> {
+        match name {
+            "microdata" => Some(QuestionSource::Microdata),
+            "jsonld" => Some(QuestionSource::JsonLd),
+            "both" => Some(QuestionSource::Both),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn microdata(&self) -> bool {
+        *self != QuestionSource::JsonLd
+    }
+
+    pub(crate) fn jsonld(&self) -> bool {
+        *self != QuestionSource::Microdata
+    }
+}
+
+// Cheap check of the raw page for a JSON-LD Question, QAPage or FAQPage before building the DOM
+pub(crate) fn contains_question(text: &str) -> bool {
+    lazy_static! {
+        static ref TYPE: Regex = Regex::new(
+            r#""@type"\s*:\s*(\[[^\]]*)?"(https?://schema\.org/)?(Question|QAPage|FAQPage)""#
+        )
+        .unwrap();
+    }
+    text.contains("application/ld+json") && TYPE.is_match(text)
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    let matches = |value: &Value| {
+        value.as_str().map_or(false, |item| {
+            item == name || item.ends_with(&format!("schema.org/{}", name))
+        })
+    };
+    match value.get("@type") {
+        Some(Value::Array(types)) => types.iter().any(matches),
+        Some(item) => matches(item),
+        None => false,
+    }
+}
+
+// Questions of a block, and whether they are part of a FAQPage
+fn collect_questions<'a>(
+    value: &'a Value,
+    in_faqpage: bool,
+    questions: &mut Vec<(&'a Value, bool)>,
+) {
+    match value {
+        Value::Array(items) => {
+            for item in items.iter() {
+                collect_questions(item, in_faqpage, questions);
+            }
+        }
+        Value::Object(object) => {
+            if has_type(value, "Question") {
+                questions.push((value, in_faqpage));
+                return;
+            }
+            let in_faqpage = in_faqpage || has_type(value, "FAQPage");
+            for item in object.values() {
+                collect_questions(item, in_faqpage, questions);
+            }
+        }
+        _ => {}
+    }
+}
+
+// A single value or an array of them
+fn as_list(value: Option<&Value>) -> Vec<&Value> {
+    match value {
+        Some(Value::Array(items)) => items.iter().collect(),
+        Some(Value::Null) | None => Vec::new(),
+        Some(item) => vec![item],
+    }
+}
+
+fn as_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+// Balanced HTML of a text property. JSON-LD texts are plain text or HTML; parsing them on their
+// own makes sure stray closing tags can't break out of the generated subtree.
+fn balanced_html(text: &str) -> String {
+    let document = kuchiki::parse_html().one(text);
+    match document.select_first("body") {
+        Ok(body) => body
+            .as_node()
+            .children()
+            .map(|child| child.to_string())
+            .collect(),
+        Err(_) => String::new(),
+    }
+}
+
+fn push_properties(html: &mut String, item: &Value, texts: &[&str], counts: &[&str]) {
+    for prop in texts.iter() {
+        if let Some(text) = item.get(*prop).and_then(as_string) {
+            html.push_str(&format!(
+                r#"<div itemprop="{}">{}</div>"#,
+                prop,
+                balanced_html(&text)
+            ));
+        }
+    }
+    for prop in counts.iter() {
+        if let Some(count) = item.get(*prop).and_then(as_string) {
+            html.push_str(&format!(
+                r#"<meta itemprop="{}" content="{}">"#,
+                prop,
+                html_escape::encode_double_quoted_attribute(&count)
+            ));
+        }
+    }
+    for prop in ["dateCreated", "dateModified", "datePublished"].iter() {
+        if let Some(date) = item.get(*prop).and_then(as_string) {
+            let date = html_escape::encode_double_quoted_attribute(&date);
+            html.push_str(&format!(
+                r#"<time itemprop="{}" datetime="{}">{}</time>"#,
+                prop, date, date
+            ));
+        }
+    }
+}
+
+fn question_html(question: &Value) -> String {
+    let mut html = format!(
+        r#"<div itemscope itemtype="{}">"#,
+        itemtype::QUESTION_ITEMTYPE
+    );
+    push_properties(
+        &mut html,
+        question,
+        &["name", "text"],
+        &[
+            "upvoteCount",
+            "downvoteCount",
+            "commentCount",
+            "answerCount",
+        ],
+    );
+    for prop in ["acceptedAnswer", "suggestedAnswer"].iter() {
+        for answer in as_list(question.get(*prop)) {
+            html.push_str(&format!(
+                r#"<div itemprop="{}" itemscope itemtype="{}">"#,
+                prop, ANSWER_ITEMTYPE
+            ));
+            push_properties(
+                &mut html,
+                answer,
+                &["text"],
+                &["upvoteCount", "downvoteCount", "commentCount"],
+            );
+            html.push_str("</div>");
+        }
+    }
+    html.push_str("</div>");
+    html
+}
+
+// Microdata subtrees of all JSON-LD Questions of a document, in document order. Blocks that
+// aren't valid JSON are skipped.
+pub(crate) fn question_nodes(document: &NodeRef) -> Vec<NodeRef> {
+    let scripts = match document.select(r#"script[type="application/ld+json"]"#) {
+        Ok(scripts) => scripts,
+        Err(_) => return Vec::new(),
+    };
+    let mut html = String::new();
+    for script in scripts {
+        let block: Value = match serde_json::from_str(&script.as_node().text_contents()) {
+            Ok(block) => block,
+            Err(_) => continue,
+        };
+        let mut questions = Vec::new();
+        collect_questions(&block, false, &mut questions);
+        for (question, in_faqpage) in questions {
+            // FAQ entries keep their container, so --schema tells them apart
+            if in_faqpage {
+                html.push_str(&format!(
+                    r#"<div itemscope itemtype="{}">{}</div>"#,
+                    FAQPAGE_ITEMTYPE,
+                    question_html(question)
+                ));
+            } else {
+                html.push_str(&question_html(question));
+            }
+        }
+    }
+    if html.is_empty() {
+        return Vec::new();
+    }
+    let generated = kuchiki::parse_html().one(format!("<html><body>{}</body></html>", html));
+    match generated.select_first("body") {
+        Ok(body) => body.as_node().children().collect(),
+        Err(_) => Vec::new(),
+    }
+}
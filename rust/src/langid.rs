@@ -0,0 +1,271 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Language identification of the cleaned Question text (--detect-language), since the declared
+// lang attribute is wrong often enough ("en" on non-English sites) to pollute language-specific
+// corpora. Detected languages are given as ISO 639-1 codes, comparable to the primary subtag of
+// the declared language, and ISO 639-3 codes for the few languages without one.
+
+use kuchiki::NodeRef;
+
+use crate::langrules::Entities;
+use crate::plaintext;
+
+// Characters of text classified, plenty for a reliable guess
+const MAX_DETECTION_CHARS: usize = 2000;
+
+// ISO 639-3 codes of the languages whatlang identifies and their ISO 639-1 equivalents
+const ISO_639_1: [(&str, &str); 69] = [
+    ("afr", "af"),
+    ("aka", "ak"),
+    ("amh", "am"),
+    ("ara", "ar"),
+    ("aze", "az"),
+    ("bel", "be"),
+    ("ben", "bn"),
+    ("bul", "bg"),
+    ("cat", "ca"),
+    ("ces", "cs"),
+    ("cmn", "zh"),
+    ("dan", "da"),
+    ("deu", "de"),
+    ("ell", "el"),
+    ("eng", "en"),
+    ("epo", "eo"),
+    ("est", "et"),
+    ("fin", "fi"),
+    ("fra", "fr"),
+    ("guj", "gu"),
+    ("heb", "he"),
+    ("hin", "hi"),
+    ("hrv", "hr"),
+    ("hun", "hu"),
+    ("hye", "hy"),
+    ("ind", "id"),
+    ("ita", "it"),
+    ("jav", "jv"),
+    ("jpn", "ja"),
+    ("kan", "kn"),
+    ("kat", "ka"),
+    ("khm", "km"),
+    ("kor", "ko"),
+    ("lat", "la"),
+    ("lav", "lv"),
+    ("lit", "lt"),
+    ("mal", "ml"),
+    ("mar", "mr"),
+    ("mkd", "mk"),
+    ("mya", "my"),
+    ("nep", "ne"),
+    ("nld", "nl"),
+    ("nob", "nb"),
+    ("ori", "or"),
+    ("pan", "pa"),
+    ("pes", "fa"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("ron", "ro"),
+    ("rus", "ru"),
+    ("sin", "si"),
+    ("slk", "sk"),
+    ("slv", "sl"),
+    ("sna", "sn"),
+    ("spa", "es"),
+    ("srp", "sr"),
+    ("swe", "sv"),
+    ("tam", "ta"),
+    ("tel", "te"),
+    ("tgl", "tl"),
+    ("tha", "th"),
+    ("tuk", "tk"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    ("urd", "ur"),
+    ("uzb", "uz"),
+    ("vie", "vi"),
+    ("yid", "yi"),
+    ("zul", "zu"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Detection {
+    pub(crate) language: String,
+    // Between 0 and 1, rounded to two decimals
+    pub(crate) confidence: f64,
+}
+
+fn iso_639_1(code: &str) -> &str {
+    lookup(&ISO_639_1, code).unwrap_or(code)
+}
+
+// ISO 639-1 codes of three-letter codes not identified by whatlang, bibliographic ISO 639-2
+// codes and deprecated two-letter codes
+const OTHER_CODES: [(&str, &str); 27] = [
+    ("alb", "sq"),
+    ("arm", "hy"),
+    ("baq", "eu"),
+    ("bod", "bo"),
+    ("bur", "my"),
+    ("chi", "zh"),
+    ("cym", "cy"),
+    ("cze", "cs"),
+    ("dut", "nl"),
+    ("eus", "eu"),
+    ("fas", "fa"),
+    ("fre", "fr"),
+    ("geo", "ka"),
+    ("ger", "de"),
+    ("gre", "el"),
+    ("ice", "is"),
+    ("in", "id"),
+    ("isl", "is"),
+    ("iw", "he"),
+    ("ji", "yi"),
+    ("jw", "jv"),
+    ("mac", "mk"),
+    ("may", "ms"),
+    ("msa", "ms"),
+    ("per", "fa"),
+    ("rum", "ro"),
+    ("zho", "zh"),
+];
+
+// Languages spelled out instead of coded, in English or on their own
+const LANGUAGE_NAMES: [(&str, &str); 40] = [
+    ("arabic", "ar"),
+    ("chinese", "zh"),
+    ("czech", "cs"),
+    ("dansk", "da"),
+    ("danish", "da"),
+    ("deutsch", "de"),
+    ("dutch", "nl"),
+    ("english", "en"),
+    ("espanol", "es"),
+    ("español", "es"),
+    ("farsi", "fa"),
+    ("finnish", "fi"),
+    ("francais", "fr"),
+    ("français", "fr"),
+    ("french", "fr"),
+    ("german", "de"),
+    ("greek", "el"),
+    ("hebrew", "he"),
+    ("hindi", "hi"),
+    ("hungarian", "hu"),
+    ("indonesian", "id"),
+    ("italian", "it"),
+    ("italiano", "it"),
+    ("japanese", "ja"),
+    ("korean", "ko"),
+    ("magyar", "hu"),
+    ("nederlands", "nl"),
+    ("norwegian", "no"),
+    ("persian", "fa"),
+    ("polish", "pl"),
+    ("polski", "pl"),
+    ("portuguese", "pt"),
+    ("português", "pt"),
+    ("romanian", "ro"),
+    ("russian", "ru"),
+    ("spanish", "es"),
+    ("svenska", "sv"),
+    ("swedish", "sv"),
+    ("turkish", "tr"),
+    ("vietnamese", "vi"),
+];
+
+fn lookup<'a>(table: &[(&str, &'a str)], key: &str) -> Option<&'a str> {
+    table
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, code)| *code)
+}
+
+// Canonical BCP-47 form of a declared language: the lowercase primary subtag, as ISO 639-1 code
+// where there is one, and the uppercase region if any. Extended language, script and variant
+// subtags are dropped, e.g. EN-us and en_US become en-US, english becomes en and zh-cmn-Hans-CN
+// becomes zh-CN. None for values that aren't a language (x-default, *, ...).
+pub(crate) fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase().replace('_', "-");
+    if let Some(code) = lookup(&LANGUAGE_NAMES, &tag) {
+        return Some(code.to_string());
+    }
+    let mut subtags = tag.split('-');
+    let primary = subtags.next()?;
+    if !(2..=3).contains(&primary.len()) || !primary.chars().all(|c| c.is_ascii_lowercase()) {
+        return None;
+    }
+    let primary = lookup(&OTHER_CODES, primary)
+        .or_else(|| lookup(&ISO_639_1, primary))
+        .unwrap_or(primary);
+    let region = subtags
+        .take_while(|subtag| subtag.len() > 1)
+        .find(|subtag| {
+            (subtag.len() == 2 && subtag.chars().all(|c| c.is_ascii_lowercase()))
+                || (subtag.len() == 3 && subtag.chars().all(|c| c.is_ascii_digit()))
+        });
+    Some(match region {
+        Some(region) => format!("{}-{}", primary, region.to_uppercase()),
+        None => primary.to_string(),
+    })
+}
+
+// Primary subtag of a language tag: en-US, EN_us, english and en all become en
+pub(crate) fn primary_subtag(language: &str) -> String {
+    normalize_tag(language)
+        .and_then(|tag| tag.split('-').next().map(String::from))
+        .unwrap_or_default()
+}
+
+// Languages whose records are kept (--languages en,de,fr): the detected language of a record
+// decides where it was identified, the declared one otherwise
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LanguageSelection {
+    languages: Vec<String>,
+}
+
+impl LanguageSelection {
+    pub(crate) fn parse(list: &str) -> LanguageSelection {
+        LanguageSelection {
+            languages: list
+                .split(',')
+                .map(primary_subtag)
+                .filter(|language| !language.is_empty())
+                .collect(),
+        }
+    }
+
+    pub(crate) fn accepts(&self, declared: &str, detected: Option<&str>) -> bool {
+        let language = primary_subtag(detected.unwrap_or(declared));
+        self.languages.contains(&language)
+    }
+}
+
+// Language of a text, None if it has too little text to tell
+pub(crate) fn detect(text: &str) -> Option<Detection> {
+    let text = match text.char_indices().nth(MAX_DETECTION_CHARS) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    };
+    let info = whatlang::detect(text)?;
+    Some(Detection {
+        language: iso_639_1(info.lang().code()).to_string(),
+        confidence: (info.confidence() * 100.0).round() / 100.0,
+    })
+}
+
+// Language of the text of cleaned Question subtrees
+pub(crate) fn detect_questions(questions: &[NodeRef], entities: Entities) -> Option<Detection> {
+    let mut text = String::new();
+    for question in questions {
+        if text.chars().count() >= MAX_DETECTION_CHARS {
+            break;
+        }
+        text.push_str(&plaintext::to_text(question, entities));
+        text.push('\n');
+    }
+    detect(&text)
+}
@@ -0,0 +1,231 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Language-specific text cleaning rules, selected from the page language tag. The generic cleaning
+// is Latin-centric: it keeps a space for every whitespace run, which inserts spurious spaces into
+// CJK text wrapped over several lines, and it knows nothing about script-specific marks.
+//
+// Also holds the handling of Unicode bidirectional control characters, which survive the generic
+// cleaning and break downstream display and some tokenizers.
+
+use std::borrow::Cow;
+
+use crate::profile::CleaningProfile;
+
+const IDEOGRAPHIC_SPACE: char = '\u{3000}';
+const ARABIC_TATWEEL: char = '\u{0640}';
+const LEFT_TO_RIGHT_MARK: char = '\u{200E}';
+const RIGHT_TO_LEFT_MARK: char = '\u{200F}';
+const ARABIC_LETTER_MARK: char = '\u{061C}';
+
+// What to do with bidirectional control characters found in the extracted text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BidiControls {
+    // Remove them from the text
+    Strip,
+    // Keep them, but mark the records containing them
+    Flag,
+    // Keep them as they are
+    Keep,
+}
+
+impl BidiControls {
+    pub fn from_name(name: &str) -> Option<BidiControls> {
+        match name {
+            "strip" => Some(BidiControls::Strip),
+            "flag" => Some(BidiControls::Flag),
+            "keep" => Some(BidiControls::Keep),
+            _ => None,
+        }
+    }
+}
+
+// What to do with the HTML entities of the extracted text (--entities)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Entities {
+    // Escape the text once more, so "&amp;" in the page becomes "&amp;amp;" in the output
+    Encode,
+    // Resolve the entities left in the parsed text, e.g. those of double-escaped pages, into
+    // Unicode characters
+    Decode,
+    // Keep the text as parsed, only escaped by the serialization
+    Preserve,
+}
+
+impl Entities {
+    pub fn from_name(name: &str) -> Option<Entities> {
+        match name {
+            "encode" => Some(Entities::Encode),
+            "decode" => Some(Entities::Decode),
+            "preserve" => Some(Entities::Preserve),
+            _ => None,
+        }
+    }
+}
+
+// Directional marks, embeddings, overrides and isolates
+pub(crate) fn is_bidi_control(c: char) -> bool {
+    is_directional_mark(c)
+        || ('\u{202A}'..='\u{202E}').contains(&c)
+        || ('\u{2066}'..='\u{2069}').contains(&c)
+}
+
+pub(crate) fn contains_bidi_controls(text: &str) -> bool {
+    !text.is_ascii() && text.chars().any(is_bidi_control)
+}
+
+fn strip_bidi_controls(text: &str) -> Cow<str> {
+    if contains_bidi_controls(text) {
+        Cow::Owned(text.chars().filter(|c| !is_bidi_control(*c)).collect())
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+// Invisible characters fragmenting tokens of otherwise identical texts: soft hyphen, zero-width
+// space, word joiner and byte order mark
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{00AD}' | '\u{200B}' | '\u{2060}' | '\u{FEFF}')
+}
+
+// Zero-width non-joiner and joiner. They carry meaning in Persian and Indic scripts and in emoji
+// sequences, so they are only stripped on request.
+fn is_zero_width_joiner(c: char) -> bool {
+    c == '\u{200C}' || c == '\u{200D}'
+}
+
+fn strip_invisibles(text: &str, strip_joiners: bool) -> Cow<str> {
+    let is_stripped = |c: char| is_invisible(c) || (strip_joiners && is_zero_width_joiner(c));
+    if text.is_ascii() || !text.chars().any(is_stripped) {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(text.chars().filter(|c| !is_stripped(*c)).collect())
+    }
+}
+
+// Everything the text cleaning of one page depends on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CleaningRules {
+    pub(crate) language: LanguageRules,
+    pub(crate) bidi: BidiControls,
+    pub(crate) profile: CleaningProfile,
+    pub(crate) strip_joiners: bool,
+    pub(crate) entities: Entities,
+}
+
+impl CleaningRules {
+    pub(crate) fn prepare(&self, text: &str) -> String {
+        let prepared = self.language.prepare(text);
+        let prepared = strip_invisibles(&prepared, self.strip_joiners);
+        if self.bidi == BidiControls::Strip {
+            strip_bidi_controls(&prepared).into_owned()
+        } else {
+            prepared.into_owned()
+        }
+    }
+
+    pub(crate) fn trim<'a>(&self, text: &'a str) -> &'a str {
+        self.language.trim(text)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LanguageRules {
+    Default,
+    // Chinese and Japanese: no spaces between words
+    Cjk,
+    // Korean: spaces between words, but may use the ideographic space
+    Hangul,
+    // Arabic script languages: tatweel (kashida) stripping and RTL marks
+    Arabic,
+    // Hebrew script languages: RTL marks
+    Hebrew,
+}
+
+impl LanguageRules {
+    // Rules for a language tag such as "zh-CN", "ar" or "he_IL"
+    pub(crate) fn for_language(language: &str) -> LanguageRules {
+        let primary = language
+            .split(|c| c == '-' || c == '_')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_lowercase();
+        match primary.as_str() {
+            "zh" | "ja" | "yue" => LanguageRules::Cjk,
+            "ko" => LanguageRules::Hangul,
+            "ar" | "fa" | "ur" | "ps" | "ckb" | "sd" | "ug" => LanguageRules::Arabic,
+            "he" | "iw" | "yi" => LanguageRules::Hebrew,
+            _ => LanguageRules::Default,
+        }
+    }
+
+    // Applied to the raw text before the generic whitespace cleaning
+    pub(crate) fn prepare<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if text.is_ascii() {
+            return Cow::Borrowed(text);
+        }
+        match self {
+            LanguageRules::Default | LanguageRules::Hebrew => Cow::Borrowed(text),
+            LanguageRules::Cjk => Cow::Owned(join_cjk(text)),
+            LanguageRules::Hangul => Cow::Owned(text.replace(IDEOGRAPHIC_SPACE, " ")),
+            LanguageRules::Arabic => Cow::Owned(text.replace(ARABIC_TATWEEL, "")),
+        }
+    }
+
+    // Applied instead of a plain whitespace trim after the generic cleaning. Directional marks
+    // at the boundaries of right-to-left text carry no information and are trimmed as well.
+    pub(crate) fn trim<'a>(&self, text: &'a str) -> &'a str {
+        match self {
+            LanguageRules::Arabic | LanguageRules::Hebrew => {
+                text.trim_matches(|c: char| c.is_whitespace() || is_directional_mark(c))
+            }
+            _ => text.trim(),
+        }
+    }
+}
+
+fn is_directional_mark(c: char) -> bool {
+    c == LEFT_TO_RIGHT_MARK || c == RIGHT_TO_LEFT_MARK || c == ARABIC_LETTER_MARK
+}
+
+// Kana, CJK ideographs, CJK punctuation and fullwidth forms
+fn is_cjk(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x3001..=0x303F
+            | 0x3040..=0x30FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xF900..=0xFAFF
+            | 0xFF00..=0xFFEF
+            | 0x20000..=0x2FFFF
+    )
+}
+
+// Drop whitespace runs between two CJK characters (typically line wraps in the page source) and
+// turn the remaining ideographic spaces into plain spaces
+fn join_cjk(text: &str) -> String {
+    let mut joined = String::with_capacity(text.len());
+    let mut run = String::new();
+    let mut previous: Option<char> = None;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            run.push(if c == IDEOGRAPHIC_SPACE { ' ' } else { c });
+            continue;
+        }
+        if !run.is_empty() {
+            if !(previous.map_or(false, is_cjk) && is_cjk(c)) {
+                joined.push_str(&run);
+            }
+            run.clear();
+        }
+        joined.push(c);
+        previous = Some(c);
+    }
+    joined.push_str(&run);
+    joined
+}
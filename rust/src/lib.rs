@@ -0,0 +1,3344 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+extern crate clap;
+extern crate kuchiki;
+
+mod allocstats;
+mod amp;
+mod closedbook;
+mod columnar;
+mod confidence;
+mod dates;
+mod events;
+mod extract;
+mod hfexport;
+mod howto;
+mod http;
+mod ipinfo;
+mod itemtype;
+mod jsonld;
+mod langid;
+mod langrules;
+mod metrics;
+mod microdata;
+mod minhash;
+mod output;
+mod partition;
+mod passage;
+mod plaintext;
+mod profile;
+mod proto;
+mod qa;
+mod runstate;
+mod selftest;
+mod seq2seq;
+mod sidecar;
+mod simd;
+mod upload;
+mod validate;
+mod verify;
+mod whitelist;
+
+// Question extraction from single HTML documents for embedding applications, with the types of
+// its options. Everything else is only reachable through the command line (run).
+pub use allocstats::CountingAllocator;
+pub use extract::{
+    minify_html, CancellationToken, ExtractError, ExtractOptions, ExtractWarning, Extracted,
+};
+pub use itemtype::{Itemtypes, QuestionSchema, Vocabulary};
+pub use jsonld::QuestionSource;
+pub use langrules::{BidiControls, Entities};
+pub use plaintext::TextMode;
+pub use profile::CleaningProfile;
+pub use whitelist::DedupWhitelist;
+
+use kuchiki::traits::*;
+use kuchiki::NodeRef;
+use langrules::CleaningRules;
+
+use indicatif::ParallelProgressIterator;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::DateTime;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use rayon::iter::ParallelIterator;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use warc::header::WarcHeader;
+use warc::{RawRecord, WarcReader};
+use xxhash_rust::xxh3::{xxh3_128, xxh3_64};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct HTMLMinified {
+    id: String,
+    mhtml: String,
+    // The cleaned Questions of the page one by one, mhtml being their concatenation (preceded by
+    // separators with --question-separators)
+    #[serde(default)]
+    questions: Vec<String>,
+    #[serde(default)]
+    num_questions: usize,
+    // xxh3-128 of mhtml as hex, so identical records can be deduplicated without rehashing
+    #[serde(default)]
+    content_hash: String,
+    // Original HTML of the Question subtrees before cleaning (--keep-raw), for re-extraction
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    raw_questions: Vec<String>,
+    // The cleaned Questions as typed fields (--structured), sparing consumers the HTML parsing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    structured: Vec<qa::StructuredQuestion>,
+    // Declared by the page (see find_lang_tag), "-" if it declares none
+    language: String,
+    // Identified from the text of the Questions with its confidence (--detect-language)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detected_language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detected_language_confidence: Option<f64>,
+    // Missing WARC headers are emitted as null
+    uri: Option<String>,
+    // The page's <link rel="canonical">, otherwise WARC-Refers-To-Target-URI, otherwise uri. Pages
+    // reachable under several URLs share it, which makes it the better key for URL-based dedup.
+    #[serde(default)]
+    canonical_uri: Option<String>,
+    ip_address: Option<String>,
+    // WARC-Date as ISO-8601, for temporal filtering and splits
+    #[serde(default)]
+    crawl_date: Option<String>,
+    // WARC-Record-ID and WARC-Payload-Digest, to join records back to the source crawl
+    #[serde(default)]
+    record_id: Option<String>,
+    #[serde(default)]
+    payload_digest: Option<String>,
+    // Text of the page <title>, useful to disambiguate the questions
+    #[serde(default)]
+    title: Option<String>,
+    // OpenGraph og:title and og:description and <meta name="description"> (--page-meta), short
+    // page summaries for retrieval-augmented setups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    og_title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    og_description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    meta_description: Option<String>,
+    // Joined from the --sidecar file by URI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<sidecar::Metadata>,
+    // Keywords and tag-list topics of all Questions of the page, for topic-stratified splits
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    // Non-QA text of the same page (--context-passages), for context-grounded answer generation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    context: Vec<String>,
+    // Text contains bidirectional control characters (--bidi-controls flag)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    has_bidi_controls: bool,
+    // Combined quality signal: high, medium or low
+    #[serde(default)]
+    extraction_confidence: confidence::ExtractionConfidence,
+    // Hash of the output-affecting options the record was produced with
+    #[serde(default)]
+    config_hash: String,
+    // Offline ASN / reverse DNS enrichment of the IP address (--asn-table, --reverse-dns)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    network: Option<ipinfo::NetworkInfo>,
+}
+
+// Value of a WARC header, None if the record doesn't carry it. The WARC parser keeps a single
+// value per header name, so a duplicated header resolves to one of its values here.
+fn warc_header(record: &RawRecord, header: WarcHeader) -> Option<String> {
+    record
+        .headers
+        .get(&header)
+        .map(|value| String::from_utf8_lossy(value).to_string())
+}
+
+// WARC-Date normalized to ISO-8601 in UTC, e.g. 2021-05-06T12:34:56Z, None if it doesn't parse
+fn crawl_date(record: &RawRecord) -> Option<String> {
+    let date = warc_header(record, WarcHeader::Date)?;
+    DateTime::parse_from_rfc3339(date.trim())
+        .ok()
+        .map(dates::utc)
+}
+
+// WARC header fields carried over into the output records
+pub(crate) struct RecordHeaders {
+    uri: Option<String>,
+    // Target URI of the record a revisit record refers to
+    refers_to_uri: Option<String>,
+    ip_address: Option<String>,
+    crawl_date: Option<String>,
+    record_id: Option<String>,
+    payload_digest: Option<String>,
+}
+
+fn record_headers(record: &RawRecord) -> RecordHeaders {
+    RecordHeaders {
+        uri: warc_header(record, WarcHeader::TargetURI),
+        refers_to_uri: warc_header(record, WarcHeader::from("WARC-Refers-To-Target-URI")),
+        ip_address: warc_header(record, WarcHeader::IPAddress),
+        crawl_date: crawl_date(record),
+        record_id: warc_header(record, WarcHeader::RecordID),
+        payload_digest: warc_header(record, WarcHeader::PayloadDigest),
+    }
+}
+
+// Strip the crawler's HTTP response headers from a record body, returning only the page payload
+fn http_payload(body: &[u8]) -> &[u8] {
+    match body.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => &body[pos + 4..],
+        None => body,
+    }
+}
+
+// Leading bytes of binary payloads (gzip, PDF, PNG, JPEG, GIF, ZIP) which never hold a webpage
+const BINARY_SIGNATURES: &[&[u8]] = &[
+    b"\x1f\x8b",
+    b"%PDF",
+    b"\x89PNG",
+    b"\xff\xd8\xff",
+    b"GIF8",
+    b"PK\x03\x04",
+];
+
+// Cheap check on the first payload bytes, before any UTF-8 conversion or scanning
+fn is_binary_payload(payload: &[u8]) -> bool {
+    BINARY_SIGNATURES
+        .iter()
+        .any(|signature| payload.starts_with(signature))
+}
+
+// Number of itemprop occurrences in the raw page, counting no further than `limit`
+fn count_itemprops(text: &str, limit: usize) -> usize {
+    let bytes = text.as_bytes();
+    let mut count = 0;
+    let mut offset = 0;
+    while count < limit {
+        match simd::find(&bytes[offset..], b"itemprop") {
+            Some(position) => {
+                count += 1;
+                offset += position + "itemprop".len();
+            }
+            None => break,
+        }
+    }
+    count
+}
+
+pub fn is_emptyspace(c: char) -> bool {
+    c == ' ' || c == ' ' || c == '\t' || c == '\n'
+}
+
+// First bytes of the characters matched by find_emptyspace_run (space, tab, newline and the
+// lead byte shared by U+202F and U+2002)
+const EMPTYSPACE_LEAD_BYTES: &[u8] = b" \t\n\xE2";
+
+// Start of the first run of two or more characters out of [ \x{202F}\x{2002}\t\n]
+fn find_emptyspace_run(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    let mut pos = 0;
+    while let Some(offset) = simd::find_any(&bytes[pos..], EMPTYSPACE_LEAD_BYTES) {
+        // Candidates are ASCII or UTF-8 lead bytes, so always on a character boundary
+        let start = pos + offset;
+        let mut chars = input[start..].chars();
+        let is_run_char = |c: Option<char>| match c {
+            Some(c) => is_emptyspace(c) || c == '\u{2002}',
+            None => false,
+        };
+        if is_run_char(chars.next()) && is_run_char(chars.next()) {
+            return Some(start);
+        }
+        pos = start + 1;
+    }
+    None
+}
+
+// Borrowed and changed from https://github.com/lise-henry/crowbook-text-processing/blob/master/src/lib/clean.rs
+pub fn emptyspaces<'a, S: Into<Cow<'a, str>>>(input: S) -> Cow<'a, str> {
+    let input = input.into();
+    let first = find_emptyspace_run(&input);
+    if let Some(first) = first {
+        let mut new_s = String::with_capacity(input.len());
+        new_s.push_str(&input[0..first]);
+        let mut previous_space = false;
+        for c in input[first..].chars() {
+            if is_emptyspace(c) {
+                if previous_space {
+                    // previous char already a space, don't copy it
+                } else {
+                    new_s.push(c);
+                    previous_space = true;
+                }
+            } else {
+                previous_space = false;
+                new_s.push(c);
+            }
+        }
+        Cow::Owned(new_s)
+    } else {
+        input
+    }
+}
+
+fn reduce_tilde(input: String) -> String {
+    lazy_static! {
+        static ref RR: Regex = Regex::new(r"~+").unwrap();
+    }
+    let out = RR.replace_all(&input, "~");
+    return out.to_string();
+}
+
+fn reduce_breaks(input: String) -> String {
+    lazy_static! {
+        static ref RR: Regex = Regex::new(r"(<br>)+").unwrap();
+    }
+    let out = RR.replace_all(&input, "<br>");
+    return out.to_string();
+}
+
+// Non-empty attribute of the <html> element
+fn find_html_attribute(node: NodeRef, name: &str) -> Option<String> {
+    if let kuchiki::NodeData::Element(x) = node.data() {
+        if x.name.local == "html".to_string() {
+            let x_attr = (x.attributes).clone().into_inner();
+            return x_attr
+                .get(name)
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty());
+        }
+    }
+    for child in node.children() {
+        let result = find_html_attribute(child.clone(), name);
+        if let Some(_) = result {
+            return result;
+        }
+    }
+    return None;
+}
+
+// Language of a document in BCP-47 (see langid::normalize_tag): the lang attribute of <html>,
+// otherwise the Content-Language given as <meta http-equiv>, the og:locale or the xml:lang
+// attribute of <html>. Values that aren't a language count as missing.
+fn find_lang_tag(node: NodeRef) -> Option<String> {
+    let normalize = |tag: String| langid::normalize_tag(&tag);
+    find_html_attribute(node.clone(), "lang")
+        .and_then(normalize)
+        .or_else(|| {
+            // The header may list several languages, the first one is the main one
+            find_meta(&node, &["content-language"])
+                .and_then(|languages| languages.split(',').next().and_then(langid::normalize_tag))
+        })
+        .or_else(|| find_meta(&node, &["og:locale"]).and_then(normalize))
+        .or_else(|| find_html_attribute(node, "xml:lang").and_then(normalize))
+}
+
+// Marker of the comments preceding every Question with --question-separators
+const QUESTION_SEPARATOR: &str = "ccqa-question";
+
+#[derive(Serialize)]
+struct QuestionHeader<'a> {
+    index: usize,
+    count: usize,
+    // Length of the Question HTML following the separator, in UTF-8 bytes
+    bytes: usize,
+    itemtype: Option<&'a str>,
+    // Index of the Question this one was nested in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+}
+
+// Split the Questions of a page into the FAQ entries and the others (--split-schemas). Parent
+// indices are remapped within each part, a parent in the other part is dropped.
+fn split_faqpage(
+    extracted: Vec<extract::Extracted>,
+) -> (Vec<extract::Extracted>, Vec<extract::Extracted>) {
+    let mut faqpage = Vec::new();
+    let mut others = Vec::new();
+    // Part and position within it of every Question
+    let mut positions = Vec::new();
+    for question in extracted {
+        let part = if question.faqpage {
+            &mut faqpage
+        } else {
+            &mut others
+        };
+        positions.push((question.faqpage, part.len()));
+        part.push(question);
+    }
+    for question in faqpage.iter_mut().chain(others.iter_mut()) {
+        let in_faqpage = question.faqpage;
+        question.parent = question.parent.and_then(|parent| match positions[parent] {
+            (part, position) if part == in_faqpage => Some(position),
+            _ => None,
+        });
+    }
+    (faqpage, others)
+}
+
+// Concatenate the Questions, each preceded by a comment with a JSON header for splitting them
+// apart again without guessing at itemtype boundaries:
+// <!--ccqa-question {"index":0,"count":2,"bytes":812,"itemtype":"https://schema.org/Question"}-->
+// Questions nested in another one carry its index as "parent".
+fn separated_questions(
+    questions: &[String],
+    itemtypes: &[Option<String>],
+    parents: &[Option<usize>],
+) -> String {
+    let mut separated = String::new();
+    for (index, (question, (itemtype, parent))) in questions
+        .iter()
+        .zip(itemtypes.iter().zip(parents.iter()))
+        .enumerate()
+    {
+        let header = serde_json::to_string(&QuestionHeader {
+            index,
+            count: questions.len(),
+            bytes: question.len(),
+            itemtype: itemtype.as_deref(),
+            parent: *parent,
+        })
+        .unwrap()
+        // "--" may not appear inside a comment, and only ever does inside a JSON string
+        .replace("--", "-\\u002d");
+        separated.push_str(&format!("<!--{} {}-->", QUESTION_SEPARATOR, header));
+        separated.push_str(question);
+    }
+    separated
+}
+
+// Reference resolved against the URI of the page it was found on. Absolute, scheme-relative
+// (//host/path), root-relative (/path) and path-relative references are supported.
+fn resolve_uri(base: &str, reference: &str) -> Option<String> {
+    lazy_static! {
+        static ref ABSOLUTE: Regex = Regex::new(r"^(?i)https?://").unwrap();
+        static ref ORIGIN: Regex = Regex::new(r"^(?i)(https?:)//[^/?#]*").unwrap();
+    }
+    let reference = reference.trim();
+    if ABSOLUTE.is_match(reference) {
+        return Some(reference.to_string());
+    }
+    let origin = ORIGIN.captures(base)?;
+    if reference.starts_with("//") {
+        Some(format!("{}{}", &origin[1], reference))
+    } else if reference.starts_with('/') {
+        Some(format!("{}{}", &origin[0], reference))
+    } else {
+        // Relative to the directory of the base path, without its query and fragment
+        let path = &base[origin[0].len()..];
+        let path = &path[..path.find(|c| c == '?' || c == '#').unwrap_or(path.len())];
+        let directory = &path[..path.rfind('/').map_or(0, |slash| slash + 1)];
+        let directory = if directory.is_empty() { "/" } else { directory };
+        Some(format!("{}{}{}", &origin[0], directory, reference))
+    }
+}
+
+// Target of the document's <link rel="canonical">, without fragment, None if missing or empty
+fn find_canonical_uri(document: &NodeRef, uri: Option<&str>) -> Option<String> {
+    let href = document
+        .select("link[rel][href]")
+        .ok()?
+        .find(|link| {
+            link.attributes.borrow().get("rel").map_or(false, |rel| {
+                rel.split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+            })
+        })?
+        .attributes
+        .borrow()
+        .get("href")?
+        .to_string();
+    let href = href[..href.find('#').unwrap_or(href.len())].trim();
+    if href.is_empty() {
+        return None;
+    }
+    // Relative references can't be resolved without the URI of the page
+    resolve_uri(uri.unwrap_or(""), href)
+}
+
+// Content of the first <meta> whose property, name or http-equiv attribute is one of the given
+// keys, whitespace collapsed, None if missing or empty
+fn find_meta(document: &NodeRef, keys: &[&str]) -> Option<String> {
+    document
+        .select("meta[content]")
+        .ok()?
+        .filter(|meta| {
+            let attributes = meta.attributes.borrow();
+            ["property", "name", "http-equiv"].iter().any(|name| {
+                attributes.get(*name).map_or(false, |key| {
+                    keys.iter()
+                        .any(|wanted| key.trim().eq_ignore_ascii_case(wanted))
+                })
+            })
+        })
+        .filter_map(|meta| {
+            let content = meta.attributes.borrow().get("content")?.replace("\n", " ");
+            let content = emptyspaces(html_escape::decode_html_entities(&content).to_string())
+                .trim()
+                .to_string();
+            if content.is_empty() {
+                None
+            } else {
+                Some(content)
+            }
+        })
+        .next()
+}
+
+// Text of the document's <title>, whitespace collapsed, None if missing or empty
+fn find_title(document: &NodeRef) -> Option<String> {
+    let title = document.select_first("title").ok()?;
+    let text = emptyspaces(title.as_node().text_contents().replace("\n", " "))
+        .trim()
+        .to_string();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn transform_outside(
+    node: NodeRef,
+    itemtypes: &itemtype::Itemtypes,
+    lenient: bool,
+    cancellation: &extract::CancellationToken,
+) -> Option<Vec<NodeRef>> {
+    // Pre order traversal
+    if let kuchiki::NodeData::Element(x) = node.data() {
+        let x_attr = (x.attributes).clone().into_inner();
+        if x_attr.contains("itemtype") {
+            let itemtype = x_attr.get("itemtype").unwrap();
+            if itemtypes.matches(itemtype)
+                || (lenient
+                    && itemtypes.includes_question()
+                    && itemtype::question_variant(itemtype).is_some())
+            {
+                let mut vec = Vec::new();
+                vec.push(node.clone());
+                return Some(vec);
+            }
+        } else if itemtypes.includes_question() && is_main_entity_question(&node) {
+            // Extracted like any other Question from here on
+            x.attributes
+                .borrow_mut()
+                .insert("itemtype", itemtype::QUESTION_ITEMTYPE.to_string());
+            return Some(vec![node.clone()]);
+        }
+    }
+    let mut vec = Vec::new();
+    for child in node.children() {
+        if cancellation.is_cancelled() {
+            break;
+        }
+        let tmp_vec = transform_outside(child.clone(), itemtypes, lenient, cancellation);
+        if let Some(x) = tmp_vec {
+            vec.extend(x);
+        }
+    }
+    if vec.len() > 0 {
+        return Some(vec);
+    } else {
+        return None;
+    }
+}
+
+// Copy of a subtree that shares no nodes with the original
+fn deep_clone(node: &NodeRef) -> Option<NodeRef> {
+    let clone = match node.data() {
+        kuchiki::NodeData::Element(element) => NodeRef::new_element(
+            element.name.clone(),
+            element.attributes.borrow().map.clone(),
+        ),
+        kuchiki::NodeData::Text(text) => NodeRef::new_text(text.borrow().clone()),
+        kuchiki::NodeData::Comment(comment) => NodeRef::new_comment(comment.borrow().clone()),
+        _ => return None,
+    };
+    for child in node.children() {
+        if let Some(child) = deep_clone(&child) {
+            clone.append(child);
+        }
+    }
+    Some(clone)
+}
+
+// Graft copies of the elements referenced through itemref attributes into the items of an
+// extracted subtree, since the properties they hold are lost once everything outside of the
+// subtree is removed. References to elements already inside the item, or enclosing it, are
+// skipped.
+fn resolve_itemrefs(document: &NodeRef, item: &NodeRef) {
+    let referencing = item
+        .inclusive_descendants()
+        .filter_map(|node| {
+            let itemref = node
+                .as_element()?
+                .attributes
+                .borrow()
+                .get("itemref")
+                .map(String::from)?;
+            Some((node, itemref))
+        })
+        .collect::<Vec<(NodeRef, String)>>();
+    if referencing.is_empty() {
+        return;
+    }
+    let mut ids = HashMap::new();
+    for node in document.descendants() {
+        if let Some(element) = node.as_element() {
+            if let Some(id) = element.attributes.borrow().get("id") {
+                ids.entry(id.to_string()).or_insert_with(|| node.clone());
+            }
+        }
+    }
+    for (node, itemref) in referencing {
+        for id in itemref.split_whitespace() {
+            let referenced = match ids.get(id) {
+                Some(referenced) => referenced,
+                None => continue,
+            };
+            let is_inside = referenced
+                .inclusive_ancestors()
+                .any(|ancestor| ancestor == node);
+            let is_enclosing = node.ancestors().any(|ancestor| ancestor == *referenced);
+            if is_inside || is_enclosing {
+                continue;
+            }
+            if let Some(copy) = deep_clone(referenced) {
+                node.append(copy);
+            }
+        }
+    }
+}
+
+fn is_question_scope(node: &NodeRef, itemtypes: &itemtype::Itemtypes, lenient: bool) -> bool {
+    if let Some(element) = node.as_element() {
+        if let Some(itemtype) = element.attributes.borrow().get("itemtype") {
+            return itemtypes.matches(itemtype)
+                || (lenient
+                    && itemtypes.includes_question()
+                    && itemtype::question_variant(itemtype).is_some());
+        }
+    }
+    itemtypes.includes_question() && is_main_entity_question(node)
+}
+
+// The mainEntity of a QAPage or FAQPage container is a Question, even when marked up as a bare
+// itemscope without itemtype
+fn is_main_entity_question(node: &NodeRef) -> bool {
+    let is_main_entity = node.as_element().map_or(false, |element| {
+        let attributes = element.attributes.borrow();
+        attributes.contains("itemscope")
+            && !attributes.contains("itemtype")
+            && attributes.get("itemprop").map_or(false, |itemprop| {
+                itemprop.split_whitespace().any(|prop| prop == "mainEntity")
+            })
+    });
+    if !is_main_entity {
+        return false;
+    }
+    // The property belongs to the closest enclosing item
+    let scope = node.ancestors().find(|ancestor| {
+        ancestor.as_element().map_or(false, |element| {
+            element.attributes.borrow().contains("itemscope")
+        })
+    });
+    scope
+        .and_then(|scope| {
+            scope.as_element().and_then(|element| {
+                element
+                    .attributes
+                    .borrow()
+                    .get("itemtype")
+                    .map(String::from)
+            })
+        })
+        .map_or(false, |itemtype| itemtype::is_page_itemtype(&itemtype))
+}
+
+// Minimum number of characters for a paragraph to count as a context passage
+const MIN_CONTEXT_PASSAGE_LENGTH: usize = 40;
+
+// Collect the text of up to `max_passages` paragraphs outside of any Question (intro paragraphs,
+// product descriptions, ...) as grounding context. Needs to run before the Question subtrees are
+// transformed, since it reads the untouched document.
+fn extract_context_passages(
+    document: &NodeRef,
+    max_passages: usize,
+    itemtypes: &itemtype::Itemtypes,
+    lenient: bool,
+) -> Vec<String> {
+    let mut passages = Vec::new();
+    let paragraphs = match document.select("p") {
+        Ok(paragraphs) => paragraphs,
+        Err(_) => return passages,
+    };
+    for paragraph in paragraphs {
+        if passages.len() >= max_passages {
+            break;
+        }
+        let node = paragraph.as_node();
+        if node
+            .ancestors()
+            .any(|ancestor| is_question_scope(&ancestor, itemtypes, lenient))
+        {
+            continue;
+        }
+        let text = emptyspaces(node.text_contents().replace("\n", " "))
+            .trim()
+            .to_string();
+        if text.chars().count() >= MIN_CONTEXT_PASSAGE_LENGTH {
+            passages.push(text);
+        }
+    }
+    passages
+}
+
+fn inside_props(node: NodeRef, rules: CleaningRules, cancellation: &extract::CancellationToken) {
+    let display = rules.profile == CleaningProfile::Display;
+    if display {
+        if let Some(element) = node.as_element() {
+            if profile::is_unsafe_element(&element.name.local) {
+                node.detach();
+                return;
+            }
+        }
+    }
+    // Post order traversal
+    for child in node.children() {
+        if cancellation.is_cancelled() {
+            return;
+        }
+        inside_props(child.clone(), rules, cancellation);
+    }
+    if let kuchiki::NodeData::Element(x) = node.data() {
+        let mut x_attr = (x.attributes).borrow_mut();
+
+        // Remove empty and not item-related attributes, the display profile also keeps web links
+        for (key, value) in x_attr.clone().map.into_iter() {
+            let is_link = display
+                && &*x.name.local == "a"
+                && &*key.local == "href"
+                && profile::is_safe_href(&value.value);
+            if !(key.local.starts_with("item")
+                || key.local.starts_with("content")
+                || key.local.starts_with("date")
+                || is_link)
+            {
+                x_attr.remove(key.local);
+            } else {
+                if value.value.len() < 1 {
+                    x_attr.remove(key.local);
+                }
+            }
+        }
+
+        // Remove media tags, but keep <link> properties such as interactionType
+        let is_link_property =
+            &*x.name.local == "link" && x_attr.contains("itemprop") && x_attr.contains("content");
+        if x.name.local.contains("svg")
+            || x.name.local.contains("img")
+            || x.name.local.contains("hatul")
+            || x.name.local.contains("input")
+            || x.name.local.contains("button")
+            || (x.name.local.contains("link") && !is_link_property)
+        {
+            for child in node.children() {
+                node.insert_after(child)
+            }
+            node.detach();
+        // Unwrap all formatting the display profile doesn't keep
+        } else if display
+            && !x_attr.contains("itemprop")
+            && !x_attr.contains("itemtype")
+            && !profile::is_display_element(&x.name.local)
+        {
+            for child in node.children() {
+                node.insert_before(child)
+            }
+            node.detach();
+        }
+
+    // Clean the text elements
+    } else if let kuchiki::NodeData::Text(x) = node.data() {
+        let mut clean: String = x.borrow().to_string();
+        clean = clean_text(clean, rules);
+        x.replace(clean.clone());
+    }
+}
+
+fn clean_text(mut clean: String, rules: CleaningRules) -> String {
+    if rules.entities == Entities::Decode {
+        clean = html_escape::decode_html_entities(&clean).into();
+    }
+    clean = rules.prepare(&clean);
+    clean = clean.replace("\n", "~");
+    clean = emptyspaces(clean).into();
+    clean = rules.trim(&clean).to_string();
+    if rules.entities == Entities::Encode {
+        clean = html_escape::encode_text(&clean).into();
+    }
+    return clean;
+}
+
+// Remove all nodes recusively bottom-up if the don't contain textual information
+fn remove_empty_nodes(node: NodeRef) -> bool {
+    // Post order traversal
+    for child in node.children() {
+        remove_empty_nodes(child.clone());
+    }
+    // Remove nodes without children that are not part of the item* family
+    if let kuchiki::NodeData::Element(x) = node.data() {
+        let local_attrs = x.clone().attributes.into_inner();
+        if &node.children().count() == &0
+            // If no content inside, it needs a content attribute with data or be a <br> tag
+            && !(local_attrs.contains("itemprop") && local_attrs.contains("content"))
+            && !(local_attrs.contains("itemtype") && local_attrs.contains("content"))
+            && !(x.name.local == "br".to_string())
+        {
+            node.detach();
+            return false;
+        }
+    } else if let kuchiki::NodeData::Text(x) = node.data() {
+        let text: String = x.borrow().to_string();
+        if &text.len() < &1 || &text == &"~" || &text == &" " {
+            node.detach();
+            return false;
+        }
+    }
+    return true;
+}
+
+fn transform_inside(
+    node: NodeRef,
+    rules: CleaningRules,
+    cancellation: &extract::CancellationToken,
+) {
+    let local_attrs: kuchiki::Attributes;
+    if let kuchiki::NodeData::Element(x) = node.data() {
+        // The value of a <link> property is its href, which is removed below
+        if &*x.name.local == "link" {
+            let mut x_attr = (x.attributes).borrow_mut();
+            if x_attr.contains("itemprop") && !x_attr.contains("content") {
+                if let Some(href) = x_attr.get("href").map(String::from) {
+                    x_attr.insert("content", href);
+                }
+            }
+        }
+        local_attrs = x.clone().attributes.into_inner();
+        {
+            let mut x_attr = (x.attributes).borrow_mut();
+            for (key, value) in x_attr.clone().map.into_iter() {
+                // Remove all parameters that are not schema.org related
+                if !(key.local.starts_with("item")
+                    || key.local.starts_with("content")
+                    || key.local.starts_with("date"))
+                {
+                    x_attr.remove(key.local);
+                } else {
+                    if value.value.len() < 1 {
+                        x_attr.remove(key.local);
+                    }
+                }
+            }
+        }
+        // Clean indide schema.org/Question tags
+        if local_attrs.contains("itemprop") && !local_attrs.contains("itemtype") {
+            if local_attrs.get("itemprop").unwrap() == "url" {
+                node.detach();
+            } else {
+                inside_props(node.clone(), rules, cancellation);
+                remove_empty_nodes(node.clone());
+                return;
+            }
+        }
+    }
+    // Post order traversal
+    for child in node.children() {
+        if cancellation.is_cancelled() {
+            return;
+        }
+        transform_inside(child.clone(), rules, cancellation);
+    }
+    if let kuchiki::NodeData::Element(x) = node.data() {
+        let x_attr = x.clone().attributes.into_inner();
+        if !x_attr.contains("itemtype") && !x_attr.contains("itemprop") {
+            for child in node.children() {
+                node.insert_after(child)
+            }
+            node.detach();
+        }
+    } else {
+        node.detach();
+    }
+}
+
+// Options controlling a single minification run
+struct MinifyConfig {
+    id_namespace: Option<String>,
+    dedup_bodies: bool,
+    // Drop Questions repeated within a page (--keep-duplicate-questions to keep them)
+    dedup_questions: bool,
+    // Records no dedup stage may remove (--dedup-whitelist)
+    dedup_whitelist: Option<Arc<whitelist::DedupWhitelist>>,
+    // Itemtypes whose items are extracted (--itemtype)
+    itemtypes: itemtype::Itemtypes,
+    lenient_itemtypes: bool,
+    // Vocabularies mapped onto schema.org (--vocabulary)
+    vocabularies: Vec<&'static itemtype::Vocabulary>,
+    // Kinds of Questions extracted (--schema)
+    schema: itemtype::QuestionSchema,
+    sidecar: Option<HashMap<String, sidecar::Metadata>>,
+    event_log: Option<events::EventLog>,
+    metrics: Option<metrics::RunMetrics>,
+    // Report the throughput and allocations of every worker thread (--worker-stats)
+    worker_stats: bool,
+    context_passages: usize,
+    // Capture the OpenGraph title and description and the meta description (--page-meta)
+    page_meta: bool,
+    // Identify the language of the cleaned Question text (--detect-language)
+    detect_language: bool,
+    // Records in other languages are dropped (--languages)
+    languages: Option<langid::LanguageSelection>,
+    // Pages with fewer itemprop occurrences are skipped before parsing (--min-itemprops)
+    min_itemprops: usize,
+    // Questions with fewer answers are dropped during the extraction (--min-answers)
+    min_answers: usize,
+    keep_raw: bool,
+    // Also write the Questions as typed fields (--structured)
+    structured: bool,
+    // What the typed fields keep of the authors (--authors)
+    authors: qa::AuthorPolicy,
+    // Also write the comments of the answers into the typed fields (--comments)
+    comments: bool,
+    // Precede every Question in mhtml with a separator comment (--question-separators)
+    question_separators: bool,
+    question_source: jsonld::QuestionSource,
+    keep_direction: bool,
+    bidi_controls: BidiControls,
+    cleaning_profile: CleaningProfile,
+    strip_joiners: bool,
+    entities: Entities,
+    // Markup or plain text Questions (--text-mode)
+    text_mode: plaintext::TextMode,
+    rejected: Option<output::RejectionLog>,
+    // Stream of the HowTos of all webpages (--write-howto)
+    howto: Option<howto::HowToLog>,
+    // Stream of the records of FAQPage entries, kept out of the main output (--split-schemas)
+    faqpage: Option<output::RecordLog>,
+    config_hash: String,
+    deterministic: bool,
+    // Hashes of the URIs already in the output (--append)
+    existing_uris: Option<HashSet<u64>>,
+    network_tables: Option<ipinfo::NetworkTables>,
+    // Stops the processing of the remaining records once cancelled
+    cancellation: extract::CancellationToken,
+}
+
+// Build a record id from the (optional) crawl namespace, the WARC file name and the record index,
+// e.g. CC-MAIN-2021-21:CC-MAIN-20210506...-00000.warc:1234
+fn generate_id(namespace: &Option<String>, file_path: &str, record_index: usize) -> String {
+    let file_name = Path::new(file_path)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_path.to_string());
+    match namespace {
+        Some(ns) => format!("{}:{}:{}", ns, file_name, record_index),
+        None => format!("{}:{}", file_name, record_index),
+    }
+}
+
+// Why and where a webpage was dropped from the output
+struct Rejection {
+    stage: events::Stage,
+    reason: &'static str,
+    language: String,
+}
+
+impl Rejection {
+    fn new(stage: events::Stage, reason: &'static str) -> Rejection {
+        Rejection {
+            stage,
+            reason,
+            language: "-".to_string(),
+        }
+    }
+}
+
+// Records of a kept webpage: the one of the main output and the one of its FAQ entries
+// (--split-schemas), at least one of them present
+struct Processed {
+    main: Option<HTMLMinified>,
+    faqpage: Option<HTMLMinified>,
+}
+
+// URI of a record as a string, empty if the header is missing
+fn record_uri(record: &RawRecord) -> String {
+    warc_header(record, WarcHeader::TargetURI).unwrap_or_default()
+}
+
+// Number of emitted records lacking a WARC-Target-URI or WARC-IP-Address header
+#[derive(Default)]
+struct MissingHeaderCounts {
+    uri: AtomicU64,
+    ip_address: AtomicU64,
+}
+
+impl MissingHeaderCounts {
+    fn add(&self, record: &HTMLMinified) {
+        if record.uri.is_none() {
+            self.uri.fetch_add(1, Ordering::Relaxed);
+        }
+        if record.ip_address.is_none() {
+            self.ip_address.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn report(&self) {
+        let uri = self.uri.load(Ordering::Relaxed);
+        let ip_address = self.ip_address.load(Ordering::Relaxed);
+        if uri > 0 || ip_address > 0 {
+            eprintln!(
+                "Emitted {} records without URI and {} without IP address",
+                uri, ip_address
+            );
+        }
+    }
+}
+
+// Records, payload bytes, processing time and allocated bytes of a single worker thread
+#[derive(Default)]
+struct WorkerCounts {
+    records: AtomicU64,
+    bytes: AtomicU64,
+    busy_micros: AtomicU64,
+    allocated: AtomicU64,
+    slowest_micros: AtomicU64,
+    slowest_bytes: AtomicU64,
+}
+
+// Throughput and allocations of every worker of the record loop, to make skew visible: a few
+// workers stuck on huge documents while the others are idle
+struct WorkerStats {
+    workers: Vec<WorkerCounts>,
+}
+
+impl WorkerStats {
+    fn new() -> WorkerStats {
+        WorkerStats {
+            workers: (0..rayon::current_num_threads())
+                .map(|_| WorkerCounts::default())
+                .collect(),
+        }
+    }
+
+    // Account a record to the calling worker, skipped on threads outside of the pool
+    fn add(&self, bytes: usize, allocated: u64, elapsed: Duration) {
+        let worker = match rayon::current_thread_index().and_then(|index| self.workers.get(index)) {
+            Some(worker) => worker,
+            None => return,
+        };
+        let micros = elapsed.as_micros() as u64;
+        worker.records.fetch_add(1, Ordering::Relaxed);
+        worker.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        worker.busy_micros.fetch_add(micros, Ordering::Relaxed);
+        worker.allocated.fetch_add(allocated, Ordering::Relaxed);
+        // Only the worker itself updates its slowest record
+        if micros > worker.slowest_micros.load(Ordering::Relaxed) {
+            worker.slowest_micros.store(micros, Ordering::Relaxed);
+            worker.slowest_bytes.store(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut busy = Vec::new();
+        let mut allocated = 0;
+        for (index, worker) in self.workers.iter().enumerate() {
+            let records = worker.records.load(Ordering::Relaxed);
+            let busy_micros = worker.busy_micros.load(Ordering::Relaxed);
+            let worker_allocated = worker.allocated.load(Ordering::Relaxed);
+            lines.push(format!(
+                "Worker {}: {} records, {} KB, busy {} ms, allocated {} KB, slowest record {} ms ({} KB)",
+                index,
+                records,
+                worker.bytes.load(Ordering::Relaxed) / 1024,
+                busy_micros / 1000,
+                worker_allocated / 1024,
+                worker.slowest_micros.load(Ordering::Relaxed) / 1000,
+                worker.slowest_bytes.load(Ordering::Relaxed) / 1024
+            ));
+            busy.push(busy_micros);
+            allocated += worker_allocated;
+        }
+        let mean = busy.iter().sum::<u64>() as f64 / busy.len() as f64;
+        if mean > 0.0 {
+            lines.push(format!(
+                "Busiest worker took {:.2}x the mean busy time",
+                *busy.iter().max().unwrap() as f64 / mean
+            ));
+        }
+        if allocated == 0 {
+            lines.push(
+                "Allocations are only counted with ccqa_rust::CountingAllocator as global allocator"
+                    .to_string(),
+            );
+        }
+        lines
+    }
+
+    fn report(&self) {
+        for line in self.summary() {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+// Minify all webpages of a WARC file, sending every non-empty result to the writer as soon as it is done
+fn minify(
+    file_path: &str,
+    config: &MinifyConfig,
+    sender: SyncSender<output::Sequenced>,
+) -> std::io::Result<()> {
+    // First failed write to a side stream, which cancels the run and is returned once the
+    // workers stopped
+    let stream_error: Mutex<Option<std::io::Error>> = Mutex::new(None);
+    let check_stream_write = |written: std::io::Result<()>| {
+        if let Err(err) = written {
+            stream_error.lock().unwrap().get_or_insert(err);
+            config.cancellation.cancel();
+        }
+    };
+    let variant_counts = itemtype::VariantCounts::default();
+    let missing_headers = MissingHeaderCounts::default();
+    let worker_stats = if config.worker_stats {
+        Some(WorkerStats::new())
+    } else {
+        None
+    };
+    let log_event = |record_index: usize,
+                     record: &RawRecord,
+                     stage: events::Stage,
+                     outcome: events::Outcome,
+                     reason: &str,
+                     language: &str| {
+        if let Some(event_log) = &config.event_log {
+            event_log.log(&events::Event {
+                record_index: record_index as u64,
+                stage,
+                outcome,
+                reason,
+                language,
+                uri: &record_uri(record),
+            });
+        }
+        if let (Some(metrics), events::Outcome::Dropped) = (&config.metrics, outcome) {
+            metrics.dropped(stage, reason);
+        }
+        if let (Some(rejected), events::Outcome::Dropped) = (&config.rejected, outcome) {
+            let uri = warc_header(record, WarcHeader::TargetURI);
+            check_stream_write(rejected.log(&output::RejectedRecord {
+                id: &generate_id(&config.id_namespace, file_path, record_index),
+                uri: uri.as_deref(),
+                stage: stage.name(),
+                reason,
+                language,
+            }));
+        }
+    };
+    // Processing a single webpage
+    let single_record_processor = |record_index: usize,
+                                   record: &RawRecord|
+     -> Result<Processed, Rejection> {
+        let response = http::Response::parse(&record.body)
+            .ok_or_else(|| Rejection::new(events::Stage::Parse, "malformed HTTP response"))?;
+        if is_binary_payload(response.payload()) {
+            return Err(Rejection::new(events::Stage::Prefilter, "binary body"));
+        }
+        // Remove all documents without the Question schema before generating the DOM to speed up processing
+        let doc_string = response.text();
+        // HowTo pages rarely hold Questions as well, so they get their own DOM rather than passing
+        // the Question prefilter
+        if let Some(howto_log) = &config.howto {
+            if howto::contains_howto(&doc_string) {
+                let document = kuchiki::parse_html().one(doc_string.as_ref());
+                let howtos = howto::extract(&document);
+                if !howtos.is_empty() {
+                    let language = find_lang_tag(document)
+                        .unwrap_or_else(|| extract::UNKNOWN_LANGUAGE.to_string());
+                    check_stream_write(howto_log.log(
+                        &generate_id(&config.id_namespace, file_path, record_index),
+                        warc_header(record, WarcHeader::TargetURI).as_deref(),
+                        &language,
+                        &howtos,
+                    ));
+                }
+            }
+        }
+        let variants = itemtype::find_variants(&doc_string);
+        for variant in variants.iter() {
+            variant_counts.add(*variant);
+        }
+        let has_microdata = config.itemtypes.contains_any(&doc_string)
+            || (config.itemtypes.includes_question()
+                && itemtype::contains_any_question(&doc_string, &config.vocabularies))
+            || (config.lenient_itemtypes && !variants.is_empty());
+        // AMP pages are searched for JSON-LD Questions in any case
+        let has_jsonld = (config.question_source.jsonld() || amp::is_amp(&doc_string))
+            && jsonld::contains_question(&doc_string);
+        if !(has_microdata && config.question_source.microdata()) && !has_jsonld {
+            return Err(Rejection::new(
+                events::Stage::Prefilter,
+                "no Question schema",
+            ));
+        }
+        if config.schema == itemtype::QuestionSchema::FaqPage
+            && !itemtype::contains_faqpage(&doc_string)
+        {
+            return Err(Rejection::new(
+                events::Stage::Prefilter,
+                "no FAQPage schema",
+            ));
+        }
+        // A Question itemtype without properties rarely yields usable output. JSON-LD Questions
+        // have no itemprops on the page.
+        if !has_jsonld && count_itemprops(&doc_string, config.min_itemprops) < config.min_itemprops
+        {
+            return Err(Rejection::new(
+                events::Stage::Prefilter,
+                "too few itemprops",
+            ));
+        }
+        // Generate DOM, retrieve URI and ip-address
+        let document = kuchiki::parse_html().one(doc_string.as_ref());
+        let RecordHeaders {
+            uri,
+            refers_to_uri,
+            ip_address: ip,
+            crawl_date,
+            record_id,
+            payload_digest,
+        } = record_headers(record);
+        // Find language
+        let mut language: String = extract::UNKNOWN_LANGUAGE.to_string();
+        if let Some(x) = find_lang_tag(document.clone()) {
+            language = x;
+        }
+        // Without identification, the declared language decides before the costly extraction
+        if let Some(languages) = &config.languages {
+            if !config.detect_language && !languages.accepts(&language, None) {
+                return Err(Rejection {
+                    stage: events::Stage::Parse,
+                    reason: "language not selected",
+                    language,
+                });
+            }
+        }
+        // The title and the canonical link are lost once everything outside of the Questions is
+        // removed
+        let title = find_title(&document);
+        let canonical_uri = find_canonical_uri(&document, uri.as_deref())
+            .or(refers_to_uri)
+            .or_else(|| uri.clone());
+        let (og_title, og_description, meta_description) = if config.page_meta {
+            (
+                find_meta(&document, &["og:title"]),
+                find_meta(&document, &["og:description"]),
+                find_meta(&document, &["description"]),
+            )
+        } else {
+            (None, None, None)
+        };
+        // Collect grounding context from the untouched document, where only the itemtypes of
+        // other vocabularies are already mapped to tell the Questions apart
+        let context = if config.context_passages > 0 {
+            itemtype::map_vocabularies(&document, &config.vocabularies);
+            extract_context_passages(
+                &document,
+                config.context_passages,
+                &config.itemtypes,
+                config.lenient_itemtypes,
+            )
+        } else {
+            Vec::new()
+        };
+        // Remove everything outside of Question and clean the inside
+        let options = extract::ExtractOptions {
+            itemtypes: config.itemtypes.clone(),
+            lenient_itemtypes: config.lenient_itemtypes,
+            vocabularies: config.vocabularies.clone(),
+            schema: config.schema,
+            bidi_controls: config.bidi_controls,
+            cleaning_profile: config.cleaning_profile,
+            strip_joiners: config.strip_joiners,
+            entities: config.entities,
+            text_mode: config.text_mode,
+            keep_raw: config.keep_raw,
+            question_source: config.question_source,
+            keep_direction: config.keep_direction,
+            min_answers: config.min_answers,
+            dedup_questions: config.dedup_questions,
+            dedup_whitelist: config.dedup_whitelist.clone(),
+        };
+        let id = generate_id(&config.id_namespace, file_path, record_index);
+        let page_ids = [Some(id.as_str()), uri.as_deref(), canonical_uri.as_deref()]
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<&str>>();
+        let mut extracted = match extract::minify_document(
+            document,
+            &language,
+            &page_ids,
+            &options,
+            &config.cancellation,
+        ) {
+            Ok(extracted) => extracted,
+            Err(err) => {
+                return Err(Rejection {
+                    stage: err.stage(),
+                    reason: err.reason(),
+                    language,
+                })
+            }
+        };
+        // Cut off by the crawler's size limit (the WARC-Truncated header), or before </html>
+        if warc_header(record, WarcHeader::from("WARC-Truncated")).is_some()
+            || extract::is_truncated(&doc_string)
+        {
+            for question in extracted.iter_mut() {
+                question.warnings.push(extract::ExtractWarning::Truncated);
+            }
+        }
+        // Builds the record of a part of the Questions of the page
+        let to_record = |extracted: Vec<extract::Extracted>| -> HTMLMinified {
+            let nodes = extracted
+                .iter()
+                .map(|question| question.node.clone())
+                .collect::<Vec<NodeRef>>();
+            let extraction_confidence =
+                confidence::assess(&extracted, uri.is_some() && ip.is_some());
+            let detection = if config.detect_language {
+                langid::detect_questions(&nodes, config.entities)
+            } else {
+                None
+            };
+            let structured = if config.structured {
+                extracted
+                    .iter()
+                    .map(|question| qa::StructuredQuestion {
+                        tags: question.tags.clone(),
+                        ..qa::structure_question(&question.node, config.authors, config.comments)
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let mut cleaned_questions = Vec::new();
+            let mut raw_questions = Vec::new();
+            let mut itemtypes = Vec::new();
+            let mut parents = Vec::new();
+            let mut tags: Vec<String> = Vec::new();
+            for question in extracted {
+                parents.push(question.parent);
+                for tag in question.tags {
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                }
+                itemtypes.push(question.node.as_element().and_then(|element| {
+                    element
+                        .attributes
+                        .borrow()
+                        .get("itemtype")
+                        .map(String::from)
+                }));
+                cleaned_questions.push(question.html);
+                raw_questions.extend(question.raw_html);
+            }
+            let all_questions: String = if config.question_separators {
+                separated_questions(&cleaned_questions, &itemtypes, &parents)
+            } else {
+                cleaned_questions.concat()
+            };
+            let metadata = config
+                .sidecar
+                .as_ref()
+                .zip(uri.as_ref())
+                .and_then(|(sidecar, uri)| sidecar.get(uri).cloned());
+            let network = config
+                .network_tables
+                .as_ref()
+                .zip(ip.as_ref())
+                .and_then(|(tables, ip)| tables.lookup(ip));
+            let has_bidi_controls = config.bidi_controls == BidiControls::Flag
+                && langrules::contains_bidi_controls(&all_questions);
+            // Return a minified mhtml object
+            HTMLMinified {
+                id: id.clone(),
+                content_hash: format!("{:032x}", xxh3_128(all_questions.as_bytes())),
+                mhtml: all_questions,
+                num_questions: cleaned_questions.len(),
+                questions: cleaned_questions,
+                raw_questions,
+                structured,
+                language: language.clone(),
+                detected_language: detection
+                    .as_ref()
+                    .map(|detection| detection.language.clone()),
+                detected_language_confidence: detection.map(|detection| detection.confidence),
+                uri: uri.clone(),
+                canonical_uri: canonical_uri.clone(),
+                ip_address: ip.clone(),
+                crawl_date: crawl_date.clone(),
+                record_id: record_id.clone(),
+                payload_digest: payload_digest.clone(),
+                title: title.clone(),
+                og_title: og_title.clone(),
+                og_description: og_description.clone(),
+                meta_description: meta_description.clone(),
+                metadata,
+                tags,
+                context: context.clone(),
+                has_bidi_controls,
+                extraction_confidence,
+                config_hash: config.config_hash.clone(),
+                network,
+            }
+        };
+        let is_selected = |record: &HTMLMinified| {
+            config.languages.as_ref().map_or(true, |languages| {
+                languages.accepts(&record.language, record.detected_language.as_deref())
+            })
+        };
+        // FAQ entries go to their own stream, the main output keeps the other Questions
+        let (faqpage, extracted) = if config.faqpage.is_some() {
+            split_faqpage(extracted)
+        } else {
+            (Vec::new(), extracted)
+        };
+        let to_selected_record = |extracted: Vec<extract::Extracted>| {
+            if extracted.is_empty() {
+                return None;
+            }
+            let record = to_record(extracted);
+            if is_selected(&record) {
+                Some(record)
+            } else {
+                None
+            }
+        };
+        let processed = Processed {
+            faqpage: to_selected_record(faqpage),
+            main: to_selected_record(extracted),
+        };
+        if processed.main.is_none() && processed.faqpage.is_none() {
+            return Err(Rejection {
+                stage: events::Stage::Extract,
+                reason: "detected language not selected",
+                language,
+            });
+        }
+        Ok(processed)
+    };
+
+    eprintln!("Using {} byte scanning", simd::implementation());
+    let from_start = Instant::now();
+    let file = WarcReader::from_path(file_path).unwrap();
+    let file_output = file.collect::<Vec<Result<RawRecord, warc::Error>>>();
+    // Read WARC file and collect all well formatted webpages, keeping their position in the file
+    let mut file_error_filter_out = file_output
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.is_ok())
+        .map(|(idx, x)| (idx, x.as_ref().unwrap()))
+        .collect::<Vec<(usize, &RawRecord)>>();
+    eprintln!(
+        "Finished Reading in {} ms",
+        from_start.elapsed().as_millis()
+    );
+    if let Some(metrics) = &config.metrics {
+        metrics.set_records(
+            file_output.len() as u64,
+            (file_output.len() - file_error_filter_out.len()) as u64,
+        );
+    }
+
+    // Skip byte-identical page bodies (mirrors, retries) before the expensive DOM parsing
+    if config.dedup_bodies {
+        let mut seen_bodies = HashSet::new();
+        let records_before = file_error_filter_out.len();
+        file_error_filter_out.retain(|(record_index, record)| {
+            let first_seen = seen_bodies.insert(xxh3_64(http_payload(&record.body)));
+            let whitelisted = config.dedup_whitelist.as_ref().map_or(false, |whitelist| {
+                whitelist.contains(
+                    &generate_id(&config.id_namespace, file_path, *record_index),
+                    warc_header(record, WarcHeader::TargetURI).as_deref(),
+                )
+            });
+            if !first_seen && !whitelisted {
+                log_event(
+                    *record_index,
+                    *record,
+                    events::Stage::Dedup,
+                    events::Outcome::Dropped,
+                    "duplicate body",
+                    "-",
+                );
+            }
+            first_seen || whitelisted
+        });
+        eprintln!(
+            "Skipped {} duplicate page bodies",
+            records_before - file_error_filter_out.len()
+        );
+    }
+
+    // Skip webpages already present in the output being appended to. Whitelisted webpages are
+    // skipped as well, they are kept by the output itself.
+    if let Some(existing_uris) = &config.existing_uris {
+        let records_before = file_error_filter_out.len();
+        file_error_filter_out.retain(|(record_index, record)| {
+            let is_new = warc_header(record, WarcHeader::TargetURI).map_or(true, |uri| {
+                !existing_uris.contains(&xxh3_64(uri.as_bytes()))
+            });
+            if !is_new {
+                log_event(
+                    *record_index,
+                    *record,
+                    events::Stage::Dedup,
+                    events::Outcome::Dropped,
+                    "already in output",
+                    "-",
+                );
+            }
+            is_new
+        });
+        eprintln!(
+            "Skipped {} webpages already in the output",
+            records_before - file_error_filter_out.len()
+        );
+    }
+
+    // Parallel process WARC file
+    let from_process = Instant::now();
+    let file_output_length = file_output.len() as u64;
+    eprintln!("{}", file_output_length);
+    file_error_filter_out
+        .into_par_iter()
+        .enumerate()
+        .progress_count(file_output_length)
+        .for_each_with(sender, |sender, (sequence, (record_index, record))| {
+            if config.cancellation.is_cancelled() {
+                return;
+            }
+            let from_record = Instant::now();
+            let allocated_before = allocstats::allocated_bytes();
+            let processed = single_record_processor(record_index, record);
+            if let Some(worker_stats) = &worker_stats {
+                worker_stats.add(
+                    record.body.len(),
+                    allocstats::allocated_bytes() - allocated_before,
+                    from_record.elapsed(),
+                );
+            }
+            match processed {
+                Ok(Processed { main, faqpage }) => {
+                    // The webpage counts as kept once, with the Questions of both streams
+                    if let Some(metrics) = &config.metrics {
+                        let num_questions =
+                            main.iter().chain(faqpage.iter()).map(|x| x.num_questions);
+                        let language = &main.as_ref().or(faqpage.as_ref()).unwrap().language;
+                        metrics.kept(language, num_questions.sum());
+                    }
+                    if let (Some(faqpage_log), Some(faqpage)) = (&config.faqpage, faqpage) {
+                        missing_headers.add(&faqpage);
+                        log_event(
+                            record_index,
+                            record,
+                            events::Stage::Output,
+                            events::Outcome::Kept,
+                            "routed to the FAQPage stream",
+                            &faqpage.language,
+                        );
+                        check_stream_write(faqpage_log.log(&faqpage));
+                    }
+                    match main {
+                        Some(minified) => {
+                            missing_headers.add(&minified);
+                            log_event(
+                                record_index,
+                                record,
+                                events::Stage::Output,
+                                events::Outcome::Kept,
+                                "written",
+                                &minified.language,
+                            );
+                            // A failing writer reports its error once joined, there's no point
+                            // in processing the remaining records until then
+                            if sender.send((sequence, Some(minified))).is_err() {
+                                config.cancellation.cancel();
+                            }
+                        }
+                        None => {
+                            if config.deterministic {
+                                let _ = sender.send((sequence, None));
+                            }
+                        }
+                    }
+                }
+                Err(rejection) => {
+                    log_event(
+                        record_index,
+                        record,
+                        rejection.stage,
+                        events::Outcome::Dropped,
+                        rejection.reason,
+                        &rejection.language,
+                    );
+                    // The ordered writer needs to know it doesn't have to wait for this one
+                    if config.deterministic {
+                        let _ = sender.send((sequence, None));
+                    }
+                }
+            }
+        });
+    if config.cancellation.is_cancelled() {
+        eprintln!("Cancelled, the remaining records were not processed");
+    }
+    variant_counts.report(config.lenient_itemtypes);
+    missing_headers.report();
+    if let Some(worker_stats) = &worker_stats {
+        worker_stats.report();
+    }
+    if let Some(event_log) = &config.event_log {
+        event_log.flush()?;
+    }
+    // The unfinished side streams are removed once dropped
+    if let Some(err) = stream_error.into_inner().unwrap() {
+        return Err(err);
+    }
+    if let Some(rejected) = &config.rejected {
+        rejected.finish()?;
+    }
+    if let Some(howto_log) = &config.howto {
+        howto_log.finish()?;
+    }
+    if let Some(faqpage_log) = &config.faqpage {
+        faqpage_log.finish()?;
+        eprintln!(
+            "Routed {} records of FAQPage entries to their own stream",
+            faqpage_log.records()
+        );
+    }
+    eprintln!(
+        "Finished Processing in {} ms for a throughput of {} per ms",
+        from_process.elapsed().as_millis(),
+        (file_output_length as u128) / from_process.elapsed().as_millis().max(1)
+    );
+    eprintln!(
+        "Finished End to End in {} ms, for a throughput of {} per ms",
+        from_start.elapsed().as_millis(),
+        (file_output_length as u128) / from_start.elapsed().as_millis().max(1)
+    );
+    Ok(())
+}
+
+// Yield estimate of a WARC file without any transformation or output: the number of records, of
+// prefilter hits and of hits confirmed to carry a Question itemtype attribute
+fn count_questions(
+    file_path: &str,
+    lenient: bool,
+    vocabularies: &[&itemtype::Vocabulary],
+) -> (u64, u64, u64) {
+    let records = WarcReader::from_path(file_path)
+        .unwrap()
+        .filter_map(|record| record.ok())
+        .collect::<Vec<RawRecord>>();
+    let prefiltered = AtomicU64::new(0);
+    let confirmed = AtomicU64::new(0);
+    records.par_iter().for_each(|record| {
+        let response = match http::Response::parse(&record.body) {
+            Some(response) if !is_binary_payload(response.payload()) => response,
+            _ => return,
+        };
+        let payload = response.text();
+        let variants = lenient && !itemtype::find_variants(&payload).is_empty();
+        if !itemtype::contains_any_question(&payload, vocabularies) && !variants {
+            return;
+        }
+        prefiltered.fetch_add(1, Ordering::Relaxed);
+        if itemtype::has_question_itemtype_attribute(&payload)
+            || itemtype::has_vocabulary_question_attribute(&payload, vocabularies)
+            || variants
+        {
+            confirmed.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    (
+        records.len() as u64,
+        prefiltered.into_inner(),
+        confirmed.into_inner(),
+    )
+}
+
+// Check whether the input path has already been recorded as completed in the skip-list
+fn in_skip_list(skip_list_path: &str, file_path: &str) -> std::io::Result<bool> {
+    let content = match std::fs::read_to_string(skip_list_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    Ok(content.lines().any(|line| line.trim_end() == file_path))
+}
+
+// Record a completed input path. The entry is written with a single append so that concurrent
+// jobs sharing the same skip-list never interleave partial lines.
+fn append_to_skip_list(skip_list_path: &str, file_path: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(skip_list_path)?;
+    file.write_all(format!("{}\n", file_path).as_bytes())?;
+    file.sync_data()
+}
+
+// A cleaned Question as printed by extract-one
+#[derive(Serialize)]
+struct ExtractedQuestion<'a> {
+    html: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_html: Option<&'a str>,
+    warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct ExtractOneResult<'a> {
+    questions: Vec<ExtractedQuestion<'a>>,
+    // Why nothing was extracted, and in which stage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stage: Option<&'static str>,
+}
+
+// Extract the Questions of a single HTML document from standard input and print them as JSON,
+// for debugging and as an oracle for the Python tests. Returns whether any Question was found.
+fn extract_one(options: &extract::ExtractOptions) -> std::io::Result<bool> {
+    let mut body = Vec::new();
+    std::io::stdin().read_to_end(&mut body)?;
+    let html = http::decode_html(None, &body);
+    let extracted = extract::minify_html(&html, options, &extract::CancellationToken::new());
+    let result = match &extracted {
+        Ok(questions) => ExtractOneResult {
+            questions: questions
+                .iter()
+                .map(|question| ExtractedQuestion {
+                    html: &question.html,
+                    raw_html: question.raw_html.as_deref(),
+                    warnings: question
+                        .warnings
+                        .iter()
+                        .map(|warning| warning.to_string())
+                        .collect(),
+                    parent: question.parent,
+                })
+                .collect(),
+            error: None,
+            stage: None,
+        },
+        Err(err) => ExtractOneResult {
+            questions: Vec::new(),
+            error: Some(err.reason()),
+            stage: Some(err.stage().name()),
+        },
+    };
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(extracted.is_ok())
+}
+
+// Options that change the produced output, and those among them naming files whose content
+// matters as well. Logging and bookkeeping options are left out.
+const OUTPUT_OPTIONS: &[&str] = &[
+    "format",
+    "compress",
+    "compress_level",
+    "zstd_dict",
+    "max_records_per_shard",
+    "max_shard_bytes",
+    "max_record_bytes",
+    "id_namespace",
+    "keep_duplicate_bodies",
+    "keep_duplicate_questions",
+    "dedup_whitelist",
+    "itemtype",
+    "lenient_itemtypes",
+    "vocabulary",
+    "schema",
+    "sidecar",
+    "context_passages",
+    "page_meta",
+    "detect_language",
+    "languages",
+    "min_itemprops",
+    "min_answers",
+    "keep_raw",
+    "structured",
+    "authors",
+    "comments",
+    "question_separators",
+    "question_source",
+    "keep_direction",
+    "cleaning_profile",
+    "bidi_controls",
+    "strip_zero_width_joiners",
+    "entities",
+    "text_mode",
+    "write_rejected",
+    "write_howto",
+    "split_schemas",
+    "deterministic",
+    "append",
+    "partition_by",
+    "asn_table",
+    "reverse_dns",
+];
+const OUTPUT_OPTION_FILES: &[&str] = &[
+    "zstd_dict",
+    "sidecar",
+    "asn_table",
+    "reverse_dns",
+    "dedup_whitelist",
+];
+
+// Hash of the program version and every output-affecting option, identifying the configuration
+// an output was produced with
+fn config_hash(matches: &ArgMatches) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("version={}\n", env!("CARGO_PKG_VERSION")));
+    for option in OUTPUT_OPTIONS {
+        let value = match matches.values_of(option) {
+            Some(values) => values.collect::<Vec<&str>>().join(","),
+            None => matches.is_present(option).to_string(),
+        };
+        hasher.update(format!("{}={}\n", option, value));
+        if OUTPUT_OPTION_FILES.contains(option) {
+            if let Some(path) = matches.value_of(option) {
+                let digest = verify::sha256_file(Path::new(path))?;
+                hasher.update(format!("{}_sha256={}\n", option, digest));
+            }
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize())[..16].to_string())
+}
+
+// Itemtypes selected with --itemtype, the Question itemtype if none
+fn selected_itemtypes(matches: &ArgMatches) -> itemtype::Itemtypes {
+    matches
+        .values_of("itemtype")
+        .map_or_else(itemtype::Itemtypes::default, itemtype::Itemtypes::new)
+}
+
+// Vocabularies selected with --vocabulary, in the order given
+fn selected_vocabularies(matches: &ArgMatches) -> Vec<&'static itemtype::Vocabulary> {
+    matches
+        .values_of("vocabulary")
+        .map_or_else(Vec::new, |names| {
+            names
+                .map(|name| itemtype::Vocabulary::from_name(name).unwrap())
+                .collect()
+        })
+}
+
+// Configuration of a run from its command line, with the side streams (--write-rejected, ...)
+// opened next to the output
+fn minify_config(
+    matches: &ArgMatches,
+    output_file_path: &str,
+    format: output::OutputFormat,
+    compression: &output::Compression,
+    config_hash: String,
+) -> std::io::Result<MinifyConfig> {
+    let append = matches.is_present("append");
+    let split_schemas = matches.is_present("split_schemas");
+    Ok(MinifyConfig {
+        id_namespace: matches.value_of("id_namespace").map(String::from),
+        dedup_bodies: !matches.is_present("keep_duplicate_bodies"),
+        dedup_questions: !matches.is_present("keep_duplicate_questions"),
+        dedup_whitelist: match matches.value_of("dedup_whitelist") {
+            Some(path) => Some(Arc::new(whitelist::DedupWhitelist::load(path)?)),
+            None => None,
+        },
+        itemtypes: selected_itemtypes(matches),
+        lenient_itemtypes: matches.is_present("lenient_itemtypes"),
+        vocabularies: selected_vocabularies(matches),
+        schema: itemtype::QuestionSchema::from_name(matches.value_of("schema").unwrap()).unwrap(),
+        sidecar: match matches.value_of("sidecar") {
+            Some(path) => Some(sidecar::load_sidecar(path)?),
+            None => None,
+        },
+        context_passages: matches
+            .value_of("context_passages")
+            .unwrap()
+            .parse()
+            .expect("Number of context passages must be an integer"),
+        page_meta: matches.is_present("page_meta"),
+        detect_language: matches.is_present("detect_language"),
+        languages: matches
+            .value_of("languages")
+            .map(langid::LanguageSelection::parse),
+        min_itemprops: matches
+            .value_of("min_itemprops")
+            .unwrap()
+            .parse()
+            .expect("Minimum number of itemprops must be an integer"),
+        min_answers: matches
+            .value_of("min_answers")
+            .unwrap()
+            .parse()
+            .expect("Minimum number of answers must be an integer"),
+        keep_raw: matches.is_present("keep_raw"),
+        structured: matches.is_present("structured"),
+        authors: qa::AuthorPolicy::from_name(matches.value_of("authors").unwrap()).unwrap(),
+        comments: matches.is_present("comments"),
+        question_separators: matches.is_present("question_separators"),
+        keep_direction: matches.is_present("keep_direction"),
+        question_source: jsonld::QuestionSource::from_name(
+            matches.value_of("question_source").unwrap(),
+        )
+        .unwrap(),
+        strip_joiners: matches.is_present("strip_zero_width_joiners"),
+        entities: Entities::from_name(matches.value_of("entities").unwrap()).unwrap(),
+        text_mode: plaintext::TextMode::from_name(matches.value_of("text_mode").unwrap()).unwrap(),
+        cleaning_profile: CleaningProfile::from_name(matches.value_of("cleaning_profile").unwrap())
+            .unwrap(),
+        bidi_controls: BidiControls::from_name(matches.value_of("bidi_controls").unwrap()).unwrap(),
+        event_log: match matches.value_of("event_log") {
+            Some(path) => Some(events::EventLog::open(path)?),
+            None => None,
+        },
+        metrics: matches.value_of("metrics").map(metrics::RunMetrics::new),
+        worker_stats: matches.is_present("worker_stats"),
+        cancellation: extract::CancellationToken::new(),
+        rejected: if matches.is_present("write_rejected") {
+            if output_file_path == output::STDOUT_PATH {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--write-rejected needs an output file to place the rejection stream next to",
+                ));
+            }
+            Some(output::RejectionLog::create(&output::rejected_path(
+                output_file_path,
+            ))?)
+        } else {
+            None
+        },
+        howto: if matches.is_present("write_howto") || split_schemas {
+            if output_file_path == output::STDOUT_PATH {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--write-howto needs an output file to place the HowTo stream next to",
+                ));
+            }
+            Some(howto::HowToLog::create(&output::howto_path(
+                output_file_path,
+            ))?)
+        } else {
+            None
+        },
+        faqpage: if split_schemas {
+            if output_file_path == output::STDOUT_PATH {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--split-schemas needs an output file to place the schema streams next to",
+                ));
+            }
+            Some(output::RecordLog::create(&output::faqpage_path(
+                output_file_path,
+            ))?)
+        } else {
+            None
+        },
+        config_hash,
+        deterministic: matches.is_present("deterministic"),
+        network_tables: if matches.is_present("asn_table") || matches.is_present("reverse_dns") {
+            Some(ipinfo::NetworkTables::load(
+                matches.value_of("asn_table"),
+                matches.value_of("reverse_dns"),
+            )?)
+        } else {
+            None
+        },
+        existing_uris: if append {
+            let existing_uris = output::prepare_append(output_file_path, format, compression)?;
+            eprintln!(
+                "Appending to {} with {} URIs",
+                output_file_path,
+                existing_uris.len()
+            );
+            Some(existing_uris)
+        } else {
+            None
+        },
+    })
+}
+
+// Command line interface
+fn cli() -> App<'static, 'static> {
+    App::new("CCQA WARC Processor")
+        .version("1.0")
+        .author("Patrick Huber <huberpat@cs.ubc.ca> and Armen Aghajanyan <armenag@fb.com>")
+        .about("Common Crawl Question Answering (CCQA) WARC processor for in-domain pre-training corpora")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("summarize-events")
+                .about("Aggregate an --event-log file into per stage, outcome and reason counts")
+                .arg(
+                    Arg::with_name("event_log")
+                        .help("Event log written with --event-log")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("by_language")
+                        .long("by-language")
+                        .help("Also group the counts by page language"),
+                )
+                .arg(
+                    Arg::with_name("by_domain")
+                        .long("by-domain")
+                        .help("Also group the counts by URI host"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("train-zstd-dict")
+                .about("Train a shared zstd dictionary on JSON Lines outputs for use with --zstd-dict")
+                .arg(
+                    Arg::with_name("dictionary")
+                        .help("Path of the dictionary to write")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("samples")
+                        .help("JSON Lines output files to sample records from")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("max_size")
+                        .long("max-size")
+                        .value_name("BYTES")
+                        .takes_value(true)
+                        .default_value("112640")
+                        .help("Maximum dictionary size in bytes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("closed-book")
+                .about("Generate closed-book (sequence-to-sequence) training files from minified outputs")
+                .arg(
+                    Arg::with_name("output")
+                        .help("Output path, <output>.source and <output>.target are written")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("inputs")
+                        .help("Minified outputs (json, jsonl or msgpack)")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("answers")
+                        .long("answers")
+                        .takes_value(true)
+                        .possible_values(&["all", "best"])
+                        .default_value("all")
+                        .help("Which answers become targets: all of them, or the first accepted one, otherwise the most upvoted one"),
+                )
+                .arg(
+                    Arg::with_name("question_part")
+                        .long("question-part")
+                        .takes_value(true)
+                        .possible_values(&["name", "text", "both"])
+                        .default_value("both")
+                        .help("Which part of the questions becomes the source: the title (name), the body (text) or both"),
+                )
+                .arg(
+                    Arg::with_name("drop_back_references")
+                        .long("drop-back-references")
+                        .help("Drop answers starting with an unresolved reference, e.g. \"It is\", \"Yes.\" or \"As above\" (English only)"),
+                )
+                .arg(
+                    Arg::with_name("language")
+                        .long("language")
+                        .takes_value(true)
+                        .help("Only use records of this language, e.g. en"),
+                )
+                .arg(
+                    Arg::with_name("keep_markup")
+                        .long("keep-markup")
+                        .help("Keep the HTML markup of questions and answers"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("count")
+                .about("Quickly estimate the Question yield of WARC files, without extraction or output")
+                .arg(
+                    Arg::with_name("input_files")
+                        .help("WARC input files")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("lenient_itemtypes")
+                        .long("lenient-itemtypes")
+                        .help("Also count Questions marked up with common itemtype typos"),
+                )
+                .arg(
+                    Arg::with_name("vocabulary")
+                        .long("vocabulary")
+                        .value_name("VOCABULARY")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .possible_values(&["data-vocabulary.org"])
+                        .help("Also count Questions marked up with a vocabulary predating schema.org"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-hf")
+                .about("Export minified outputs as a HuggingFace datasets directory with dataset_infos.json")
+                .arg(
+                    Arg::with_name("dir")
+                        .help("Directory to create the dataset in")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("inputs")
+                        .help("Minified outputs (json, jsonl or msgpack)")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("split")
+                        .long("split")
+                        .value_name("NAME")
+                        .takes_value(true)
+                        .default_value("train")
+                        .help("Name of the split the inputs make up"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("extract-one")
+                .about("Extract the Questions of a single HTML document read from standard input and print them as JSON")
+                .arg(
+                    Arg::with_name("itemtype")
+                        .long("itemtype")
+                        .value_name("URI")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Extract items of this itemtype instead of schema.org Questions, e.g. https://schema.org/Review (repeatable)"),
+                )
+                .arg(
+                    Arg::with_name("min_answers")
+                        .long("min-answers")
+                        .value_name("N")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("Drop Questions with fewer than N answers"),
+                )
+                .arg(
+                    Arg::with_name("lenient_itemtypes")
+                        .long("lenient-itemtypes")
+                        .help("Also extract Questions marked up with common itemtype typos"),
+                )
+                .arg(
+                    Arg::with_name("vocabulary")
+                        .long("vocabulary")
+                        .value_name("VOCABULARY")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .possible_values(&["data-vocabulary.org"])
+                        .help("Also extract Questions marked up with a vocabulary predating schema.org, mapped onto schema.org"),
+                )
+                .arg(
+                    Arg::with_name("schema")
+                        .long("schema")
+                        .value_name("SCHEMA")
+                        .takes_value(true)
+                        .possible_values(&["all", "question", "faqpage"])
+                        .default_value("all")
+                        .help("Extract every Question, only forum-style ones, or only the entries of FAQPage containers"),
+                )
+                .arg(
+                    Arg::with_name("keep_raw")
+                        .long("keep-raw")
+                        .help("Also print the original HTML of every Question subtree"),
+                )
+                .arg(
+                    Arg::with_name("keep_direction")
+                        .long("keep-direction")
+                        .help("Keep the text direction of Questions, answers and their properties as dir attributes"),
+                )
+                .arg(
+                    Arg::with_name("keep_duplicate_questions")
+                        .long("keep-duplicate-questions")
+                        .help("Keep Questions identical to an earlier one of the same page"),
+                )
+                .arg(
+                    Arg::with_name("question_source")
+                        .long("question-source")
+                        .value_name("SOURCE")
+                        .takes_value(true)
+                        .possible_values(&["microdata", "jsonld", "both"])
+                        .default_value("microdata")
+                        .help("Extract Questions from microdata, from JSON-LD <script> blocks, or from both"),
+                )
+                .arg(
+                    Arg::with_name("cleaning_profile")
+                        .long("cleaning-profile")
+                        .value_name("PROFILE")
+                        .takes_value(true)
+                        .possible_values(&["pretraining", "display"])
+                        .default_value("pretraining")
+                        .help("Keep only schema.org markup (pretraining) or also semantic formatting (display)"),
+                )
+                .arg(
+                    Arg::with_name("entities")
+                        .long("entities")
+                        .value_name("MODE")
+                        .takes_value(true)
+                        .possible_values(&["encode", "decode", "preserve"])
+                        .default_value("encode")
+                        .help("Escape the HTML entities of the text once more (encode), resolve them (decode) or keep the text as parsed (preserve)"),
+                )
+                .arg(
+                    Arg::with_name("text_mode")
+                        .long("text-mode")
+                        .value_name("MODE")
+                        .takes_value(true)
+                        .possible_values(&["markup", "plain"])
+                        .default_value("markup")
+                        .help("Print the cleaned Questions as microdata HTML (markup) or as normalized text (plain)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("minhash-dedup")
+                .about("Find near-duplicate records of minified outputs with MinHash LSH, keeping the buckets on disk")
+                .arg(
+                    Arg::with_name("work_dir")
+                        .help("Directory for the bucket files and the resulting duplicates.tsv")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("inputs")
+                        .help("Minified outputs (json, jsonl or msgpack)")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("bands")
+                        .long("bands")
+                        .takes_value(true)
+                        .default_value("16")
+                        .help("Number of LSH bands"),
+                )
+                .arg(
+                    Arg::with_name("rows")
+                        .long("rows")
+                        .takes_value(true)
+                        .default_value("8")
+                        .help("MinHash values per band"),
+                )
+                .arg(
+                    Arg::with_name("shingle_size")
+                        .long("shingle-size")
+                        .takes_value(true)
+                        .default_value("5")
+                        .help("Words per shingle"),
+                )
+                .arg(
+                    Arg::with_name("partitions")
+                        .long("partitions")
+                        .takes_value(true)
+                        .default_value("256")
+                        .help("Number of bucket files; more partitions lower the memory needed per file"),
+                )
+                .arg(
+                    Arg::with_name("dedup_whitelist")
+                        .long("dedup-whitelist")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("File of record ids or URIs, one per line, never reported as duplicates"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("passage-retrieval")
+                .about("Generate passage retrieval (DPR) training files from minified outputs")
+                .arg(
+                    Arg::with_name("output")
+                        .help("Output path, <output>.jsonl is written")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("inputs")
+                        .help("Minified outputs (json, jsonl or msgpack)")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("language")
+                        .long("language")
+                        .takes_value(true)
+                        .help("Only use records of this language, e.g. en"),
+                )
+                .arg(
+                    Arg::with_name("keep_markup")
+                        .long("keep-markup")
+                        .help("Keep the HTML markup of questions and answers"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("selftest")
+                .about("Process a bundled fixture WARC with different thread counts and check the outputs are identical"),
+        )
+        .subcommand(
+            SubCommand::with_name("seq2seq")
+                .about("Linearize the questions and answers of minified outputs into sequence-to-sequence pre-training text with special tokens")
+                .arg(
+                    Arg::with_name("output")
+                        .help("Output path, <output>.txt is written")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("inputs")
+                        .help("Minified outputs (json, jsonl or msgpack)")
+                        .required(true)
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("question_token")
+                        .long("question-token")
+                        .value_name("TOKEN")
+                        .takes_value(true)
+                        .default_value("<question>")
+                        .help("Token preceding the question"),
+                )
+                .arg(
+                    Arg::with_name("best_answer_token")
+                        .long("best-answer-token")
+                        .value_name("TOKEN")
+                        .takes_value(true)
+                        .default_value("<best_answer>")
+                        .help("Token preceding the best answer"),
+                )
+                .arg(
+                    Arg::with_name("answer_token")
+                        .long("answer-token")
+                        .value_name("TOKEN")
+                        .takes_value(true)
+                        .default_value("<answer>")
+                        .help("Token preceding every other answer"),
+                )
+                .arg(
+                    Arg::with_name("end_token")
+                        .long("end-token")
+                        .value_name("TOKEN")
+                        .takes_value(true)
+                        .help("Token appended to every sequence, e.g. </s>"),
+                )
+                .arg(
+                    Arg::with_name("language")
+                        .long("language")
+                        .takes_value(true)
+                        .help("Only use records of this language, e.g. en"),
+                )
+                .arg(
+                    Arg::with_name("keep_markup")
+                        .long("keep-markup")
+                        .help("Keep the HTML markup of questions and answers"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify-release")
+                .about("Check manifest, shard checksums, record schema, id uniqueness and counts of a published dataset")
+                .arg(
+                    Arg::with_name("dir")
+                        .help("Release directory containing manifest.json")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .arg(
+            Arg::with_name("input_file")
+                .help("WARC input file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("output_file")
+                .help("Minified HTML (mhtml) output file path, s3://bucket/key to upload it while written, or - for standard output")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .possible_values(&["json", "jsonl", "tsv", "msgpack", "proto", "parquet", "arrow", "feather"])
+                .default_value("json")
+                .help("Output format: a single JSON array, JSON Lines, TSV, MessagePack, length-delimited protobuf, Apache Parquet or Arrow IPC (Feather v2)"),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .long("compress")
+                .value_name("CODEC[:LEVEL]")
+                .takes_value(true)
+                .default_value("none")
+                .help("Compress the output file on the fly: none, gzip[:0-9] or zstd[:1-22] (Parquet: the codec of the column chunks, without a level)"),
+        )
+        .arg(
+            Arg::with_name("compress_level")
+                .long("compress-level")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .help("Compression level if not given with --compress (defaults: gzip 6, zstd 3)"),
+        )
+        .arg(
+            Arg::with_name("zstd_dict")
+                .long("zstd-dict")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Dictionary for zstd compression, trained with the train-zstd-dict subcommand"),
+        )
+        .arg(
+            Arg::with_name("max_records_per_shard")
+                .long("max-records-per-shard")
+                .value_name("N")
+                .takes_value(true)
+                .help("Roll over to a new numbered shard (output.00001.jsonl, ...) after N records"),
+        )
+        .arg(
+            Arg::with_name("max_shard_bytes")
+                .long("max-shard-bytes")
+                .value_name("BYTES")
+                .takes_value(true)
+                .help("Roll over to a new numbered shard once BYTES of the current one are written out"),
+        )
+        .arg(
+            Arg::with_name("max_record_bytes")
+                .long("max-record-bytes")
+                .value_name("BYTES")
+                .takes_value(true)
+                .help("Write records whose JSON exceeds BYTES to oversize-<output>.jsonl next to the output instead"),
+        )
+        .arg(
+            Arg::with_name("id_namespace")
+                .long("id-namespace")
+                .value_name("NAMESPACE")
+                .takes_value(true)
+                .help("Prefix for generated record ids, e.g. the crawl release CC-MAIN-2021-21"),
+        )
+        .arg(
+            Arg::with_name("keep_duplicate_bodies")
+                .long("keep-duplicate-bodies")
+                .help("Process byte-identical page bodies again instead of skipping them"),
+        )
+        .arg(
+            Arg::with_name("keep_duplicate_questions")
+                .long("keep-duplicate-questions")
+                .help("Keep Questions identical to an earlier one of the same page"),
+        )
+        .arg(
+            Arg::with_name("dedup_whitelist")
+                .long("dedup-whitelist")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("File of record ids or URIs, one per line, that deduplication must never remove"),
+        )
+        .arg(
+            Arg::with_name("itemtype")
+                .long("itemtype")
+                .value_name("URI")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Extract items of this itemtype instead of schema.org Questions, e.g. https://schema.org/Review (repeatable)"),
+        )
+        .arg(
+            Arg::with_name("lenient_itemtypes")
+                .long("lenient-itemtypes")
+                .help("Also extract Questions marked up with common itemtype typos (schema.og, scheme.org, bare \"Question\")"),
+        )
+        .arg(
+            Arg::with_name("vocabulary")
+                .long("vocabulary")
+                .value_name("VOCABULARY")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&["data-vocabulary.org"])
+                .help("Also extract Questions marked up with a vocabulary predating schema.org, mapped onto schema.org"),
+        )
+        .arg(
+            Arg::with_name("schema")
+                .long("schema")
+                .value_name("SCHEMA")
+                .takes_value(true)
+                .possible_values(&["all", "question", "faqpage"])
+                .default_value("all")
+                .help("Extract every Question, only forum-style ones, or only the entries of FAQPage containers"),
+        )
+        .arg(
+            Arg::with_name("sidecar")
+                .long("sidecar")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("TSV (URL in the first column) or JSON (object keyed by URL) metadata joined onto records by URI"),
+        )
+        .arg(
+            Arg::with_name("asn_table")
+                .long("asn-table")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Offline IP to ASN table (iptoasn.com ip2asn TSV) to enrich records with AS number, name and country"),
+        )
+        .arg(
+            Arg::with_name("reverse_dns")
+                .long("reverse-dns")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Offline reverse DNS table (ip<TAB>hostname) to enrich records with the host name of their IP address"),
+        )
+        .arg(
+            Arg::with_name("context_passages")
+                .long("context-passages")
+                .value_name("N")
+                .takes_value(true)
+                .default_value("0")
+                .help("Keep up to N paragraphs from outside the Questions of each page as grounding context"),
+        )
+        .arg(
+            Arg::with_name("page_meta")
+                .long("page-meta")
+                .help("Capture og:title, og:description and <meta name=\"description\"> of each page as context fields"),
+        )
+        .arg(
+            Arg::with_name("detect_language")
+                .long("detect-language")
+                .help("Identify the language of the cleaned Question text, next to the language declared by the page"),
+        )
+        .arg(
+            Arg::with_name("languages")
+                .long("languages")
+                .value_name("LANGUAGES")
+                .takes_value(true)
+                .help("Only keep records in these comma separated languages (e.g. en,de,fr), identified with --detect-language, otherwise declared by the page"),
+        )
+        .arg(
+            Arg::with_name("min_itemprops")
+                .long("min-itemprops")
+                .value_name("N")
+                .takes_value(true)
+                .default_value("0")
+                .help("Skip pages with fewer than N itemprop attributes before parsing them"),
+        )
+        .arg(
+            Arg::with_name("min_answers")
+                .long("min-answers")
+                .value_name("N")
+                .takes_value(true)
+                .default_value("0")
+                .help("Drop Questions with fewer than N answers during the extraction"),
+        )
+        .arg(
+            Arg::with_name("keep_raw")
+                .long("keep-raw")
+                .help("Also keep the original HTML of every Question subtree, to re-run the cleaning later"),
+        )
+        .arg(
+            Arg::with_name("structured")
+                .long("structured")
+                .help("Also write every Question as typed fields: name, text, votes, date and answers"),
+        )
+        .arg(
+            Arg::with_name("authors")
+                .long("authors")
+                .value_name("POLICY")
+                .takes_value(true)
+                .possible_values(&["keep", "hash", "drop"])
+                .default_value("keep")
+                .help("Keep the author names of the --structured fields, replace them with their SHA-256 or drop them"),
+        )
+        .arg(
+            Arg::with_name("comments")
+                .long("comments")
+                .requires("structured")
+                .help("Also write the schema.org Comments beneath every answer into the --structured fields"),
+        )
+        .arg(
+            Arg::with_name("question_source")
+                .long("question-source")
+                .value_name("SOURCE")
+                .takes_value(true)
+                .possible_values(&["microdata", "jsonld", "both"])
+                .default_value("microdata")
+                .help("Extract Questions from microdata, from JSON-LD <script> blocks, or from both"),
+        )
+        .arg(
+            Arg::with_name("keep_direction")
+                .long("keep-direction")
+                .help("Keep the text direction of Questions, answers and their properties as dir attributes"),
+        )
+        .arg(
+            Arg::with_name("question_separators")
+                .long("question-separators")
+                .help("Precede every Question in mhtml with a comment holding its index, length and itemtype"),
+        )
+        .arg(
+            Arg::with_name("bidi_controls")
+                .long("bidi-controls")
+                .value_name("MODE")
+                .takes_value(true)
+                .possible_values(&["strip", "flag", "keep"])
+                .default_value("strip")
+                .help("Strip bidirectional control characters (LRM/RLM, embeddings, isolates) from the text, flag records containing them, or keep them"),
+        )
+        .arg(
+            Arg::with_name("cleaning_profile")
+                .long("cleaning-profile")
+                .value_name("PROFILE")
+                .takes_value(true)
+                .possible_values(&["pretraining", "display"])
+                .default_value("pretraining")
+                .help("Keep only schema.org markup (pretraining) or also semantic formatting such as bold, lists, code and links (display)"),
+        )
+        .arg(
+            Arg::with_name("entities")
+                .long("entities")
+                .value_name("MODE")
+                .takes_value(true)
+                .possible_values(&["encode", "decode", "preserve"])
+                .default_value("encode")
+                .help("Escape the HTML entities of the text once more (encode), resolve them into Unicode characters (decode) or keep the text as parsed (preserve)"),
+        )
+        .arg(
+            Arg::with_name("text_mode")
+                .long("text-mode")
+                .value_name("MODE")
+                .takes_value(true)
+                .possible_values(&["markup", "plain"])
+                .default_value("markup")
+                .help("Write the cleaned Questions as microdata HTML (markup) or as normalized text without any markup (plain), sparing the Python extraction"),
+        )
+        .arg(
+            Arg::with_name("append")
+                .long("append")
+                .help("Append to an existing jsonl or msgpack output, skipping webpages whose URI it already contains"),
+        )
+        .arg(
+            Arg::with_name("partition_by")
+                .long("partition-by")
+                .value_name("KEY")
+                .takes_value(true)
+                .possible_values(&["domain"])
+                .help("Write the records of every registered domain (eTLD+1) into a subdirectory of that name"),
+        )
+        .arg(
+            Arg::with_name("deterministic")
+                .long("deterministic")
+                .help("Write the records in input order, so repeated runs on the same input produce byte-identical outputs"),
+        )
+        .arg(
+            Arg::with_name("strip_zero_width_joiners")
+                .long("strip-zero-width-joiners")
+                .help("Also strip zero-width (non-)joiners in addition to soft hyphens, zero-width spaces and BOMs"),
+        )
+        .arg(
+            Arg::with_name("write_rejected")
+                .long("write-rejected")
+                .help("Record every dropped webpage with its URI, stage and reason in <output>.rejected.jsonl"),
+        )
+        .arg(
+            Arg::with_name("write_howto")
+                .long("write-howto")
+                .help("Also extract schema.org HowTo markup (name, description, steps) into <output>.howto.jsonl"),
+        )
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .help("Write a report of the markup problems of every page's Questions (missing texts, malformed counts and dates, ...) to the output file instead of the corpus"),
+        )
+        .arg(
+            Arg::with_name("split_schemas")
+                .long("split-schemas")
+                .help("Extract Questions, FAQPage entries and HowTo markup in a single pass, writing the FAQPage entries to <output>.faqpage.jsonl and the HowTos to <output>.howto.jsonl"),
+        )
+        .arg(
+            Arg::with_name("event_log")
+                .long("event-log")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Append a compact binary log of per-record pipeline decisions to FILE"),
+        )
+        .arg(
+            Arg::with_name("metrics")
+                .long("metrics")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Append one JSON line of run metrics (yield, language mix, dedup and filter rates) to FILE"),
+        )
+        .arg(
+            Arg::with_name("worker_stats")
+                .long("worker-stats")
+                .help("Report records, bytes, busy time and allocated bytes of every worker thread, to spot skew between workers"),
+        )
+        .arg(
+            Arg::with_name("run_state")
+                .long("run-state")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Run-state file of completed runs; the input is skipped if its content was already processed with the same configuration"),
+        )
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .help("Process the input even if the run-state file lists an up-to-date output, and overwrite existing outputs"),
+        )
+        .arg(
+            Arg::with_name("skip_list")
+                .long("skip-list")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("File of already processed input paths; the input is skipped if listed and appended once completed"),
+        )
+}
+
+// Entry point of the command line (main.rs)
+pub fn run() -> std::io::Result<()> {
+    let matches = cli().get_matches();
+
+    if let Some(sub_matches) = matches.subcommand_matches("summarize-events") {
+        return events::summarize(
+            sub_matches.value_of("event_log").unwrap(),
+            sub_matches.is_present("by_language"),
+            sub_matches.is_present("by_domain"),
+        );
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("train-zstd-dict") {
+        return output::train_zstd_dictionary(
+            &sub_matches
+                .values_of("samples")
+                .unwrap()
+                .collect::<Vec<&str>>(),
+            sub_matches.value_of("dictionary").unwrap(),
+            sub_matches
+                .value_of("max_size")
+                .unwrap()
+                .parse()
+                .expect("Dictionary size must be an integer"),
+        );
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("closed-book") {
+        return closedbook::generate(
+            &sub_matches
+                .values_of("inputs")
+                .unwrap()
+                .collect::<Vec<&str>>(),
+            sub_matches.value_of("output").unwrap(),
+            &qa::QaOptions {
+                keep_markup: sub_matches.is_present("keep_markup"),
+                language: sub_matches.value_of("language").map(String::from),
+            },
+            qa::AnswerSelection::from_name(sub_matches.value_of("answers").unwrap()).unwrap(),
+            qa::QuestionPart::from_name(sub_matches.value_of("question_part").unwrap()).unwrap(),
+            sub_matches.is_present("drop_back_references"),
+        );
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("count") {
+        println!("file\trecords\tprefiltered\tconfirmed");
+        let vocabularies = selected_vocabularies(sub_matches);
+        for input_file in sub_matches.values_of("input_files").unwrap() {
+            let (records, prefiltered, confirmed) = count_questions(
+                input_file,
+                sub_matches.is_present("lenient_itemtypes"),
+                &vocabularies,
+            );
+            println!(
+                "{}\t{}\t{}\t{}",
+                input_file, records, prefiltered, confirmed
+            );
+        }
+        return Ok(());
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("export-hf") {
+        return hfexport::export(
+            &sub_matches
+                .values_of("inputs")
+                .unwrap()
+                .collect::<Vec<&str>>(),
+            sub_matches.value_of("dir").unwrap(),
+            sub_matches.value_of("split").unwrap(),
+        );
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("extract-one") {
+        let options = extract::ExtractOptions {
+            itemtypes: selected_itemtypes(sub_matches),
+            lenient_itemtypes: sub_matches.is_present("lenient_itemtypes"),
+            vocabularies: selected_vocabularies(sub_matches),
+            schema: itemtype::QuestionSchema::from_name(sub_matches.value_of("schema").unwrap())
+                .unwrap(),
+            bidi_controls: BidiControls::Strip,
+            cleaning_profile: CleaningProfile::from_name(
+                sub_matches.value_of("cleaning_profile").unwrap(),
+            )
+            .unwrap(),
+            strip_joiners: false,
+            entities: Entities::from_name(sub_matches.value_of("entities").unwrap()).unwrap(),
+            text_mode: plaintext::TextMode::from_name(sub_matches.value_of("text_mode").unwrap())
+                .unwrap(),
+            keep_raw: sub_matches.is_present("keep_raw"),
+            question_source: jsonld::QuestionSource::from_name(
+                sub_matches.value_of("question_source").unwrap(),
+            )
+            .unwrap(),
+            keep_direction: sub_matches.is_present("keep_direction"),
+            min_answers: sub_matches
+                .value_of("min_answers")
+                .unwrap()
+                .parse()
+                .expect("Minimum number of answers must be an integer"),
+            dedup_questions: !sub_matches.is_present("keep_duplicate_questions"),
+            dedup_whitelist: None,
+        };
+        if !extract_one(&options)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("minhash-dedup") {
+        let parse = |name: &str| -> usize {
+            sub_matches
+                .value_of(name)
+                .unwrap()
+                .parse()
+                .unwrap_or_else(|_| panic!("--{} must be an integer", name.replace('_', "-")))
+        };
+        let whitelist = match sub_matches.value_of("dedup_whitelist") {
+            Some(path) => Some(whitelist::DedupWhitelist::load(path)?),
+            None => None,
+        };
+        return minhash::dedup(
+            &sub_matches
+                .values_of("inputs")
+                .unwrap()
+                .collect::<Vec<&str>>(),
+            sub_matches.value_of("work_dir").unwrap(),
+            &minhash::MinHashOptions {
+                bands: parse("bands"),
+                rows: parse("rows"),
+                shingle_size: parse("shingle_size"),
+                partitions: parse("partitions"),
+            },
+            whitelist.as_ref(),
+        );
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("passage-retrieval") {
+        return passage::generate(
+            &sub_matches
+                .values_of("inputs")
+                .unwrap()
+                .collect::<Vec<&str>>(),
+            sub_matches.value_of("output").unwrap(),
+            &qa::QaOptions {
+                keep_markup: sub_matches.is_present("keep_markup"),
+                language: sub_matches.value_of("language").map(String::from),
+            },
+        );
+    }
+    if matches.subcommand_matches("selftest").is_some() {
+        if !selftest::selftest()? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("seq2seq") {
+        let token = |name: &str| sub_matches.value_of(name).unwrap().to_string();
+        return seq2seq::export(
+            &sub_matches
+                .values_of("inputs")
+                .unwrap()
+                .collect::<Vec<&str>>(),
+            sub_matches.value_of("output").unwrap(),
+            &qa::QaOptions {
+                keep_markup: sub_matches.is_present("keep_markup"),
+                language: sub_matches.value_of("language").map(String::from),
+            },
+            &seq2seq::SpecialTokens {
+                question: token("question_token"),
+                best_answer: token("best_answer_token"),
+                answer: token("answer_token"),
+                end: sub_matches
+                    .value_of("end_token")
+                    .unwrap_or_default()
+                    .to_string(),
+            },
+        );
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("verify-release") {
+        if !verify::verify_release(sub_matches.value_of("dir").unwrap())? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let file_path = matches.value_of("input_file").unwrap();
+    let output_file_path = matches.value_of("output_file").unwrap();
+    if matches.is_present("validate") {
+        let mut report: Box<dyn Write> = if output_file_path == output::STDOUT_PATH {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(std::io::BufWriter::new(output::open_output(
+                output_file_path,
+            )?))
+        };
+        writeln!(report, "{}", validate::REPORT_HEADER)?;
+        validate::validate_warc(
+            file_path,
+            &matches.value_of("id_namespace").map(String::from),
+            &selected_itemtypes(&matches),
+            matches.is_present("lenient_itemtypes"),
+            &selected_vocabularies(&matches),
+            &mut report,
+        )?;
+        return report.flush();
+    }
+    // S3 outputs are uploaded while written, see upload.rs
+    let s3_location = upload::S3Location::parse(output_file_path);
+    let format = output::OutputFormat::from_name(matches.value_of("format").unwrap()).unwrap();
+    if format == output::OutputFormat::Parquet && s3_location.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--format parquet seeks back into its file, it can only be written to a local file",
+        ));
+    }
+    let zstd_dictionary = match matches.value_of("zstd_dict") {
+        Some(path) => Some(std::fs::read(path)?),
+        None => None,
+    };
+    // Parquet compresses its column chunks with the codec's own default level
+    if format == output::OutputFormat::Parquet
+        && (matches.value_of("compress").unwrap().contains(':')
+            || matches.is_present("compress_level")
+            || zstd_dictionary.is_some())
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--format parquet only takes the codec of --compress, without a level or --zstd-dict",
+        ));
+    }
+    let compression = output::Compression::parse(
+        matches.value_of("compress").unwrap(),
+        matches.value_of("compress_level"),
+        zstd_dictionary,
+    )
+    .unwrap_or_else(|err| panic!("{}", err));
+    let shard_limits = output::ShardLimits {
+        max_records: matches.value_of("max_records_per_shard").map(|value| {
+            value
+                .parse()
+                .expect("Number of records per shard must be an integer")
+        }),
+        max_bytes: matches
+            .value_of("max_shard_bytes")
+            .map(|value| value.parse().expect("Shard size must be an integer")),
+    };
+    let append = matches.is_present("append");
+    // Frames compressed with a dictionary can't be read back to find the URIs already present
+    if append && matches.is_present("zstd_dict") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--append can't be combined with --zstd-dict",
+        ));
+    }
+    if append
+        && (shard_limits.is_sharded()
+            || s3_location.is_some()
+            || output_file_path == output::STDOUT_PATH
+            || !matches!(
+                format,
+                output::OutputFormat::Jsonl | output::OutputFormat::Msgpack
+            ))
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--append needs a single local jsonl or msgpack output file",
+        ));
+    }
+    let max_record_bytes: Option<u64> = matches
+        .value_of("max_record_bytes")
+        .map(|value| value.parse().expect("Record size limit must be an integer"));
+    if max_record_bytes.is_some() && output_file_path == output::STDOUT_PATH {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--max-record-bytes needs an output file to place the spillover file next to",
+        ));
+    }
+    let partition_by_domain = matches.value_of("partition_by") == Some("domain");
+    if partition_by_domain
+        && (append || output_file_path == output::STDOUT_PATH || s3_location.is_some())
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--partition-by needs a local output path, without --append",
+        ));
+    }
+    let skip_list = matches.value_of("skip_list");
+    if let Some(skip_list_path) = skip_list {
+        if in_skip_list(skip_list_path, file_path)? {
+            eprintln!(
+                "Skipping {}, already listed in {}",
+                file_path, skip_list_path
+            );
+            return Ok(());
+        }
+    }
+    let config_hash = config_hash(&matches)?;
+    // Outputs on standard output can't be found again, so they are never cached
+    let run_state = match matches.value_of("run_state") {
+        Some(run_state_path) if output_file_path != output::STDOUT_PATH => {
+            Some((run_state_path, verify::sha256_file(Path::new(file_path))?))
+        }
+        _ => None,
+    };
+    if let Some((run_state_path, input_sha256)) = &run_state {
+        if let Some(run) = runstate::find_run(run_state_path, input_sha256, &config_hash)? {
+            // Outputs uploaded to S3 are trusted, local ones have to be still around
+            let output_exists = s3_location.is_some()
+                || Path::new(&run.output).exists()
+                || Path::new(&output::shard_path(&run.output, 1)).exists();
+            if output_exists && !matches.is_present("force") {
+                eprintln!(
+                    "Skipping {}, up-to-date output {} listed in {}",
+                    file_path, run.output, run_state_path
+                );
+                return Ok(());
+            }
+        }
+    }
+    // Outputs and their side streams are renamed into place once complete, so an existing one is
+    // a finished earlier run
+    if !append && output_file_path != output::STDOUT_PATH && !matches.is_present("force") {
+        let mut paths = vec![
+            output_file_path.to_string(),
+            output::shard_path(output_file_path, 1),
+        ];
+        if matches.is_present("write_rejected") {
+            paths.push(output::rejected_path(output_file_path));
+        }
+        if matches.is_present("write_howto") || matches.is_present("split_schemas") {
+            paths.push(output::howto_path(output_file_path));
+        }
+        if matches.is_present("split_schemas") {
+            paths.push(output::faqpage_path(output_file_path));
+        }
+        if max_record_bytes.is_some() {
+            paths.push(output::oversize_path(output_file_path));
+        }
+        if let Some(path) = paths.iter().find(|path| Path::new(path).exists()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!(
+                    "Output {} already exists, pass --force to overwrite it",
+                    path
+                ),
+            ));
+        }
+    }
+    // Every schema is extracted in the same pass and routed to its own stream
+    let split_schemas = matches.is_present("split_schemas");
+    if split_schemas && matches.value_of("schema") != Some("all") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--split-schemas extracts every schema, it can't be combined with --schema",
+        ));
+    }
+    let config = minify_config(
+        &matches,
+        output_file_path,
+        format,
+        &compression,
+        config_hash,
+    )?;
+    // Records are serialized on a dedicated thread while the WARC file is being processed
+    let (sender, receiver) = sync_channel(output::CHANNEL_CAPACITY);
+    let writer_path = output_file_path.to_string();
+    let writer = output::spawn_writer(
+        move || {
+            let writer: Box<dyn output::RecordWriter> = if append {
+                output::create_append_writer(&writer_path, format, &compression)?
+            } else if partition_by_domain {
+                Box::new(partition::PartitionedWriter::new(
+                    &writer_path,
+                    format,
+                    &compression,
+                    shard_limits,
+                ))
+            } else {
+                output::create_writer(&writer_path, format, &compression, shard_limits)?
+            };
+            let writer: Box<dyn output::RecordWriter> = match max_record_bytes {
+                Some(max_record_bytes) => Box::new(output::SpilloverWriter::new(
+                    writer,
+                    max_record_bytes,
+                    &writer_path,
+                    append,
+                )),
+                None => writer,
+            };
+            Ok(writer)
+        },
+        receiver,
+        config.deterministic,
+        config.cancellation.clone(),
+    )?;
+    // Main function of the script called here
+    let minified = minify(file_path, &config, sender);
+    let written = writer.join().expect("Output writer panicked");
+    // A failed side stream cancels the output writer as well, its own error comes first
+    minified?;
+    let written = written?;
+    eprintln!("Wrote {} records to {}", written, output_file_path);
+    if let Some(metrics) = &config.metrics {
+        metrics.write(file_path, &config.config_hash)?;
+    }
+    // Only mark the input as done once the output has been fully written
+    if let Some(skip_list_path) = skip_list {
+        append_to_skip_list(skip_list_path, file_path)?;
+    }
+    if let Some((run_state_path, input_sha256)) = run_state {
+        runstate::record_run(
+            run_state_path,
+            &runstate::RunEntry {
+                input: file_path.to_string(),
+                input_sha256,
+                config_hash: config.config_hash.clone(),
+                output: output_file_path.to_string(),
+            },
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use super::*;
+
+    #[test]
+    fn grafts_itemref_properties() {
+        let document = kuchiki::parse_html().one(
+            r#"<html><body><div itemscope itemtype="https://schema.org/Question" itemref="a1 q">
+            <span itemprop="name" id="q">Why?</span></div>
+            <div id="a1" itemprop="acceptedAnswer" itemscope itemtype="https://schema.org/Answer">
+            <span itemprop="text">Because.</span></div></body></html>"#,
+        );
+        let questions = transform_outside(
+            document.clone(),
+            &itemtype::Itemtypes::default(),
+            false,
+            &extract::CancellationToken::new(),
+        )
+        .unwrap();
+        resolve_itemrefs(&document, &questions[0]);
+        let text = questions[0].text_contents();
+        assert!(text.contains("Because."));
+        assert_eq!(text.matches("Why?").count(), 1);
+    }
+
+    fn rules(entities: Entities) -> CleaningRules {
+        CleaningRules {
+            language: langrules::LanguageRules::Default,
+            bidi: BidiControls::Strip,
+            profile: CleaningProfile::Pretraining,
+            strip_joiners: false,
+            entities,
+        }
+    }
+
+    #[test]
+    fn cleans_entities() {
+        // Parsed text of a page holding "AT&amp;T &amp;lt;b&amp;gt;"
+        let text = "AT&T &lt;b&gt;";
+        let cases = [
+            (Entities::Encode, "AT&amp;T &amp;lt;b&amp;gt;"),
+            (Entities::Decode, "AT&T <b>"),
+            (Entities::Preserve, "AT&T &lt;b&gt;"),
+        ];
+        for (entities, expected) in cases.iter() {
+            assert_eq!(
+                clean_text(text.to_string(), rules(*entities)),
+                *expected,
+                "{:?}",
+                entities
+            );
+        }
+    }
+
+    #[test]
+    fn sums_worker_stats_per_worker() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let stats = pool.install(WorkerStats::new);
+        let worker = pool.install(|| {
+            stats.add(2048, 4096, Duration::from_millis(3));
+            stats.add(1024, 1024, Duration::from_millis(5));
+            rayon::current_thread_index().unwrap()
+        });
+        // Outside of the pool
+        stats.add(1024, 1024, Duration::from_millis(100));
+        let summary = stats.summary();
+        assert_eq!(
+            summary[worker],
+            format!(
+                "Worker {}: 2 records, 3 KB, busy 8 ms, allocated 5 KB, slowest record 5 ms (1 KB)",
+                worker
+            )
+        );
+        assert_eq!(summary[2], "Busiest worker took 2.00x the mean busy time");
+        assert_eq!(summary.len(), 3);
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("ccqa-lib-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    // WARC file holding a single response record with the given HTML page
+    fn warc_with_page(html: &str) -> String {
+        let http = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+            html.len(),
+            html
+        );
+        format!(
+            "WARC/1.0\r\nWARC-Type: response\r\nWARC-Date: 2021-05-06T12:00:00Z\r\n\
+             WARC-Record-ID: <urn:uuid:00000000-0000-4000-8000-000000000000>\r\n\
+             WARC-Target-URI: https://example.com/faq\r\nWARC-IP-Address: 93.184.216.34\r\n\
+             Content-Type: application/http; msgtype=response\r\nContent-Length: {}\r\n\r\n{}\r\n\r\n",
+            http.len(),
+            http
+        )
+    }
+
+    #[test]
+    fn counts_routed_faqpage_records_as_kept() {
+        let warc_path = temp_path("faqpage.warc");
+        let output_path = temp_path("faqpage.jsonl");
+        let event_log_path = temp_path("faqpage.events");
+        let metrics_path = temp_path("faqpage.metrics.jsonl");
+        std::fs::write(
+            &warc_path,
+            warc_with_page(
+                r#"<!DOCTYPE html><html lang="en"><head><title>FAQ</title></head><body>
+                <div itemscope itemtype="https://schema.org/FAQPage">
+                <div itemprop="mainEntity" itemscope itemtype="https://schema.org/Question">
+                <h2 itemprop="name">Do you ship abroad?</h2>
+                <div itemprop="acceptedAnswer" itemscope itemtype="https://schema.org/Answer">
+                <div itemprop="text"><p>Yes, to most countries in Europe.</p></div></div></div>
+                </div></body></html>"#,
+            ),
+        )
+        .unwrap();
+        let matches = cli().get_matches_from(&[
+            "ccqa",
+            &warc_path,
+            &output_path,
+            "--split-schemas",
+            "--event-log",
+            &event_log_path,
+            "--metrics",
+            &metrics_path,
+        ]);
+        let config = minify_config(
+            &matches,
+            &output_path,
+            output::OutputFormat::Jsonl,
+            &output::Compression::None,
+            "test".to_string(),
+        )
+        .unwrap();
+        let (sender, receiver) = sync_channel(output::CHANNEL_CAPACITY);
+        minify(&warc_path, &config, sender).unwrap();
+        config
+            .metrics
+            .as_ref()
+            .unwrap()
+            .write(&warc_path, "test")
+            .unwrap();
+
+        // Nothing for the main output, the record went to the FAQPage stream
+        assert_eq!(receiver.try_iter().count(), 0);
+        let faqpage = std::fs::read_to_string(output::faqpage_path(&output_path)).unwrap();
+        assert_eq!(faqpage.lines().count(), 1);
+        // A single event: u64 record index, u8 stage, u8 outcome, u8 len + reason
+        let events = std::fs::read(&event_log_path).unwrap();
+        let event = &events[8..];
+        assert_eq!(event[8], events::Stage::Output as u8);
+        assert_eq!(event[9], events::Outcome::Kept as u8);
+        let reason = &event[11..11 + event[10] as usize];
+        assert_eq!(reason, b"routed to the FAQPage stream");
+        let metrics: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&metrics_path).unwrap()).unwrap();
+        assert_eq!(metrics["kept"], 1);
+        assert_eq!(metrics["questions"], 1);
+        assert_eq!(metrics["reasons"], serde_json::json!({}));
+
+        for path in [
+            &warc_path,
+            &output::faqpage_path(&output_path),
+            &output::howto_path(&output_path),
+            &event_log_path,
+            &metrics_path,
+        ]
+        .iter()
+        {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
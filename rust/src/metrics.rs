@@ -0,0 +1,181 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Compact per-run metrics (--metrics FILE), appended as one JSON line per processed WARC file.
+// The schema is fixed, so the lines of monthly crawls can be charted side by side to follow the
+// extractor's behavior and catch upstream markup shifts early:
+//
+//     {"schema_version":1,"timestamp":"2021-05-03T10:00:00Z","crawl":"CC-MAIN-2021-04",
+//      "input":"...","config_hash":"...","records":41235,"malformed":2,"kept":1187,
+//      "questions":2872,"yield_rate":0.0288,"dedup_rate":0.0012,
+//      "removal_rates":{"dedup":0.0012,"prefilter":0.9623,...},
+//      "reasons":{"prefilter/no Question schema":39680,...},"languages":{"en":0.61,...}}
+//
+// Rates are relative to the number of records of the input, language shares to the kept ones.
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::events::Stage;
+
+const SCHEMA_VERSION: u32 = 1;
+
+const STAGES: [Stage; 5] = [
+    Stage::Dedup,
+    Stage::Prefilter,
+    Stage::Parse,
+    Stage::Extract,
+    Stage::Clean,
+];
+
+#[derive(Default)]
+struct Counts {
+    records: u64,
+    malformed: u64,
+    kept: u64,
+    questions: u64,
+    dropped: BTreeMap<(&'static str, String), u64>,
+    languages: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+struct MetricsLine<'a> {
+    schema_version: u32,
+    timestamp: String,
+    // Common Crawl crawl id found in the input path, e.g. CC-MAIN-2021-04
+    crawl: Option<&'a str>,
+    input: &'a str,
+    config_hash: &'a str,
+    records: u64,
+    malformed: u64,
+    kept: u64,
+    questions: u64,
+    yield_rate: f64,
+    dedup_rate: f64,
+    removal_rates: BTreeMap<&'static str, f64>,
+    reasons: BTreeMap<String, u64>,
+    languages: BTreeMap<String, f64>,
+}
+
+pub(crate) struct RunMetrics {
+    path: String,
+    counts: Mutex<Counts>,
+}
+
+fn rate(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+// Primary language subtag, so "en-US" and "en_GB" count as "en"
+fn primary_language(language: &str) -> String {
+    language
+        .split(|c| c == '-' || c == '_')
+        .next()
+        .unwrap_or(language)
+        .trim()
+        .to_lowercase()
+}
+
+fn crawl_id(input: &str) -> Option<&str> {
+    lazy_static! {
+        static ref CRAWL: Regex = Regex::new(r"CC-MAIN-\d{4}-\d{2}").unwrap();
+    }
+    CRAWL.find(input).map(|found| found.as_str())
+}
+
+impl RunMetrics {
+    pub(crate) fn new(path: &str) -> RunMetrics {
+        RunMetrics {
+            path: path.to_string(),
+            counts: Mutex::new(Counts::default()),
+        }
+    }
+
+    // Records of the input, and how many of them couldn't be read as WARC records
+    pub(crate) fn set_records(&self, records: u64, malformed: u64) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.records = records;
+        counts.malformed = malformed;
+    }
+
+    pub(crate) fn kept(&self, language: &str, questions: usize) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.kept += 1;
+        counts.questions += questions as u64;
+        *counts
+            .languages
+            .entry(primary_language(language))
+            .or_insert(0) += 1;
+    }
+
+    pub(crate) fn dropped(&self, stage: Stage, reason: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        *counts
+            .dropped
+            .entry((stage.name(), reason.to_string()))
+            .or_insert(0) += 1;
+    }
+
+    // Append the metrics of the run to the metrics file
+    pub(crate) fn write(&self, input: &str, config_hash: &str) -> std::io::Result<()> {
+        let counts = self.counts.lock().unwrap();
+        let removal_rates = STAGES
+            .iter()
+            .map(|stage| {
+                let dropped: u64 = counts
+                    .dropped
+                    .iter()
+                    .filter(|((name, _), _)| *name == stage.name())
+                    .map(|(_, count)| count)
+                    .sum();
+                (stage.name(), rate(dropped, counts.records))
+            })
+            .collect::<BTreeMap<&'static str, f64>>();
+        let line = MetricsLine {
+            schema_version: SCHEMA_VERSION,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            crawl: crawl_id(input),
+            input,
+            config_hash,
+            records: counts.records,
+            malformed: counts.malformed,
+            kept: counts.kept,
+            questions: counts.questions,
+            yield_rate: rate(counts.kept, counts.records),
+            dedup_rate: removal_rates[Stage::Dedup.name()],
+            removal_rates,
+            reasons: counts
+                .dropped
+                .iter()
+                .map(|((stage, reason), count)| (format!("{}/{}", stage, reason), *count))
+                .collect(),
+            languages: counts
+                .languages
+                .iter()
+                .map(|(language, count)| (language.clone(), rate(*count, counts.kept)))
+                .collect(),
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut json = serde_json::to_vec(&line)?;
+        json.push(b'\n');
+        // A single write, so runs appending to the same file concurrently don't interleave
+        file.write_all(&json)
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Microdata helpers shared by the modules reading items out of the DOM: the markup report
+// (--validate), HowTos, the structured Questions and the confidence signals.
+
+use kuchiki::NodeRef;
+
+use crate::itemtype;
+
+pub(crate) fn attribute(node: &NodeRef, name: &str) -> Option<String> {
+    node.as_element()
+        .and_then(|element| element.attributes.borrow().get(name).map(String::from))
+}
+
+pub(crate) fn has_itemprop(node: &NodeRef, prop: &str) -> bool {
+    attribute(node, "itemprop").map_or(false, |props| props.split_whitespace().any(|p| p == prop))
+}
+
+// Whether the node carries microdata, as a property or an item
+pub(crate) fn has_microdata(node: &NodeRef) -> bool {
+    node.as_element().map_or(false, |element| {
+        let attributes = element.attributes.borrow();
+        attributes.contains("itemprop") || attributes.contains("itemtype")
+    })
+}
+
+// Whether one of the itemtypes of the node ends with `item`, e.g. "/Answer", in any vocabulary
+pub(crate) fn is_item(node: &NodeRef, item: &str) -> bool {
+    attribute(node, "itemtype").map_or(false, |itemtype| {
+        itemtype
+            .split_whitespace()
+            .any(|itemtype| itemtype.ends_with(item))
+    })
+}
+
+// Whether the node is an item of the schema.org type `name`
+pub(crate) fn is_type(node: &NodeRef, name: &str) -> bool {
+    attribute(node, "itemtype").map_or(false, |itemtype| itemtype::is_schema_type(&itemtype, name))
+}
+
+// The closest enclosing item of a node, not counting the node itself
+pub(crate) fn scope(node: &NodeRef) -> Option<NodeRef> {
+    node.ancestors()
+        .find(|ancestor| attribute(ancestor, "itemscope").is_some())
+}
+
+pub(crate) fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use super::*;
+
+    #[test]
+    fn finds_the_enclosing_item() {
+        let document = kuchiki::parse_html().one(
+            r#"<div itemscope itemtype="https://schema.org/Question">
+            <div itemprop="acceptedAnswer suggestedAnswer" itemscope itemtype="http://schema.org/Answer">
+            <span itemprop="text">Because.</span></div></div>"#,
+        );
+        let answer = document.select_first("[itemprop~=acceptedAnswer]").unwrap();
+        let text = document.select_first("span").unwrap();
+        assert!(has_itemprop(answer.as_node(), "suggestedAnswer"));
+        assert!(is_item(answer.as_node(), "/Answer"));
+        assert!(is_type(answer.as_node(), "Answer"));
+        assert!(!has_microdata(&text.as_node().first_child().unwrap()));
+        assert_eq!(scope(text.as_node()).as_ref(), Some(answer.as_node()));
+        assert!(is_type(&scope(answer.as_node()).unwrap(), "Question"));
+    }
+}
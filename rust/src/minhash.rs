@@ -0,0 +1,279 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Near-duplicate detection over minified outputs with MinHash and LSH banding, sized for full
+// crawls on a single node. Memory stays bounded by keeping the LSH buckets on disk:
+//
+// 1. Every record's text is shingled into word n-grams and MinHashed. Each band of the signature
+//    is hashed into a bucket key, and (bucket key, record ordinal) pairs are appended to one of
+//    `partitions` bucket files in the work directory, chosen by the bucket key.
+// 2. The bucket files are loaded, sorted and grouped in parallel, a few at a time. Records
+//    sharing a bucket are joined in a union-find over the record ordinals.
+// 3. Every record but the first of its cluster is reported as a duplicate, unless whitelisted
+//    (`--dedup-whitelist`).
+//
+// The result is written to `<work_dir>/duplicates.tsv` as `removed id \t kept id` rows.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use regex::Regex;
+use xxhash_rust::xxh3::{xxh3_64, xxh3_64_with_seed};
+
+use crate::output;
+use crate::whitelist::DedupWhitelist;
+use crate::HTMLMinified;
+
+// Mersenne prime 2^61 - 1 the permutations are computed modulo
+const MERSENNE_PRIME: u64 = (1 << 61) - 1;
+
+// Bytes of one (bucket key, record ordinal) entry in a bucket file
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MinHashOptions {
+    pub(crate) bands: usize,
+    pub(crate) rows: usize,
+    // Words per shingle
+    pub(crate) shingle_size: usize,
+    // Number of bucket files
+    pub(crate) partitions: usize,
+}
+
+// Universal hash permutations h -> (a * h + b) mod p, seeded deterministically
+struct Permutations {
+    coefficients: Vec<(u64, u64)>,
+}
+
+impl Permutations {
+    fn new(count: usize) -> Permutations {
+        Permutations {
+            coefficients: (0..count as u64)
+                .map(|idx| {
+                    let a = xxh3_64_with_seed(b"a", idx) % (MERSENNE_PRIME - 1) + 1;
+                    let b = xxh3_64_with_seed(b"b", idx) % MERSENNE_PRIME;
+                    (a, b)
+                })
+                .collect(),
+        }
+    }
+
+    fn apply(&self, idx: usize, hash: u64) -> u64 {
+        let (a, b) = self.coefficients[idx];
+        ((a as u128 * (hash % MERSENNE_PRIME) as u128 + b as u128) % MERSENNE_PRIME as u128) as u64
+    }
+}
+
+// Lowercased words of the Questions, without markup
+fn words(record: &HTMLMinified) -> Vec<String> {
+    lazy_static! {
+        static ref TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+    }
+    TAG.replace_all(&record.mhtml, " ")
+        .replace('~', " ")
+        .to_lowercase()
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+// Bucket keys of a record, one per band; empty records have none
+fn bucket_keys(
+    record: &HTMLMinified,
+    permutations: &Permutations,
+    options: &MinHashOptions,
+) -> Vec<u64> {
+    let words = words(record);
+    if words.is_empty() {
+        return Vec::new();
+    }
+    let shingle_size = options.shingle_size.min(words.len());
+    let mut signature = vec![u64::MAX; options.bands * options.rows];
+    for shingle in words.windows(shingle_size) {
+        let hash = xxh3_64(shingle.join(" ").as_bytes());
+        for (idx, minimum) in signature.iter_mut().enumerate() {
+            *minimum = (*minimum).min(permutations.apply(idx, hash));
+        }
+    }
+    signature
+        .chunks(options.rows)
+        .enumerate()
+        .map(|(band, rows)| {
+            let bytes = rows
+                .iter()
+                .flat_map(|row| row.to_le_bytes().to_vec())
+                .collect::<Vec<u8>>();
+            // Seeded by the band, so equal rows in different bands don't share a bucket
+            xxh3_64_with_seed(&bytes, band as u64)
+        })
+        .collect()
+}
+
+fn bucket_path(work_dir: &Path, partition: usize) -> PathBuf {
+    work_dir.join(format!("buckets-{:05}.bin", partition))
+}
+
+// Union-find over record ordinals, the smallest ordinal being the root of each cluster
+struct Clusters {
+    parents: Vec<u64>,
+}
+
+impl Clusters {
+    fn find(&mut self, mut node: u64) -> u64 {
+        while self.parents[node as usize] != node {
+            let grandparent = self.parents[self.parents[node as usize] as usize];
+            self.parents[node as usize] = grandparent;
+            node = grandparent;
+        }
+        node
+    }
+
+    fn union(&mut self, a: u64, b: u64) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a < b {
+            self.parents[b as usize] = a;
+        } else if b < a {
+            self.parents[a as usize] = b;
+        }
+    }
+}
+
+// Pairs of records sharing a bucket of one bucket file, as (record, first record of the bucket)
+fn bucket_edges(path: &Path) -> std::io::Result<Vec<(u64, u64)>> {
+    let mut content = Vec::new();
+    File::open(path)?.read_to_end(&mut content)?;
+    let mut entries = content
+        .chunks_exact(ENTRY_SIZE)
+        .map(|entry| {
+            let mut key = [0u8; 8];
+            let mut ordinal = [0u8; 8];
+            key.copy_from_slice(&entry[..8]);
+            ordinal.copy_from_slice(&entry[8..]);
+            (u64::from_le_bytes(key), u64::from_le_bytes(ordinal))
+        })
+        .collect::<Vec<(u64, u64)>>();
+    drop(content);
+    entries.par_sort_unstable();
+    let mut edges = Vec::new();
+    // Runs of equal bucket keys
+    let mut start = 0;
+    for idx in 1..=entries.len() {
+        if idx == entries.len() || entries[idx].0 != entries[start].0 {
+            for (_, ordinal) in entries[start + 1..idx].iter() {
+                edges.push((*ordinal, entries[start].1));
+            }
+            start = idx;
+        }
+    }
+    Ok(edges)
+}
+
+pub(crate) fn dedup(
+    inputs: &[&str],
+    work_dir: &str,
+    options: &MinHashOptions,
+    whitelist: Option<&DedupWhitelist>,
+) -> std::io::Result<()> {
+    let work_dir = Path::new(work_dir);
+    std::fs::create_dir_all(work_dir)?;
+    let permutations = Permutations::new(options.bands * options.rows);
+
+    // 1. Signatures into the bucket files, the record ids into the ids file. Records are numbered
+    // by their position over all inputs.
+    let mut bucket_files = (0..options.partitions)
+        .map(|partition| {
+            Ok(BufWriter::new(File::create(bucket_path(
+                work_dir, partition,
+            ))?))
+        })
+        .collect::<std::io::Result<Vec<BufWriter<File>>>>()?;
+    let ids_path = work_dir.join("ids.txt");
+    let mut ids_file = BufWriter::new(File::create(&ids_path)?);
+    let mut whitelisted = Vec::new();
+    let mut num_records = 0;
+    for input in inputs.iter() {
+        let records = output::read_records(Path::new(input))?;
+        let keys = records
+            .par_iter()
+            .map(|record| bucket_keys(record, &permutations, options))
+            .collect::<Vec<Vec<u64>>>();
+        for (record, keys) in records.iter().zip(keys.iter()) {
+            for key in keys.iter() {
+                let file = &mut bucket_files[(*key % options.partitions as u64) as usize];
+                file.write_all(&key.to_le_bytes())?;
+                file.write_all(&(num_records as u64).to_le_bytes())?;
+            }
+            num_records += 1;
+            writeln!(ids_file, "{}", record.id)?;
+            whitelisted.push(whitelist.map_or(false, |whitelist| {
+                whitelist.contains(&record.id, record.uri.as_deref())
+            }));
+        }
+        eprintln!("Hashed {} records of {}", records.len(), input);
+    }
+    for file in bucket_files.iter_mut() {
+        file.flush()?;
+    }
+    drop(bucket_files);
+    ids_file.flush()?;
+    drop(ids_file);
+
+    // 2. Clusters from the buckets, as many bucket files at once as there are threads
+    let mut clusters = Clusters {
+        parents: (0..num_records as u64).collect(),
+    };
+    let partitions = (0..options.partitions).collect::<Vec<usize>>();
+    for batch in partitions.chunks(rayon::current_num_threads()) {
+        let edges = batch
+            .par_iter()
+            .map(|partition| bucket_edges(&bucket_path(work_dir, *partition)))
+            .collect::<std::io::Result<Vec<Vec<(u64, u64)>>>>()?;
+        for (a, b) in edges.into_iter().flatten() {
+            clusters.union(a, b);
+        }
+    }
+    for partition in partitions.iter() {
+        std::fs::remove_file(bucket_path(work_dir, *partition))?;
+    }
+
+    // 3. Everything but the first record of a cluster is a duplicate. The first record always
+    // comes first in the ids file, so only the ids of clusters' first records are kept around.
+    let mut has_duplicates = vec![false; num_records];
+    for ordinal in 0..num_records as u64 {
+        let root = clusters.find(ordinal);
+        if root != ordinal {
+            has_duplicates[root as usize] = true;
+        }
+    }
+    let mut kept_ids = HashMap::new();
+    let duplicates_path = work_dir.join("duplicates.tsv");
+    let mut duplicates = BufWriter::new(File::create(&duplicates_path)?);
+    let mut removed = 0;
+    for (ordinal, id) in BufReader::new(File::open(&ids_path)?).lines().enumerate() {
+        let id = id?;
+        let root = clusters.find(ordinal as u64) as usize;
+        if has_duplicates[ordinal] {
+            kept_ids.insert(ordinal, id);
+        } else if root != ordinal && !whitelisted[ordinal] {
+            writeln!(duplicates, "{}\t{}", id, kept_ids[&root])?;
+            removed += 1;
+        }
+    }
+    duplicates.flush()?;
+    std::fs::remove_file(&ids_path)?;
+    println!(
+        "Found {} near-duplicates among {} records, listed in {}",
+        removed,
+        num_records,
+        duplicates_path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,1407 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+use arrow::ipc::writer::FileWriter;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression as GzCompression;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression as ParquetCompression;
+use parquet::file::properties::WriterProperties;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+use crate::columnar;
+use crate::extract;
+use crate::proto;
+use crate::upload;
+use crate::HTMLMinified;
+
+// Number of finished records buffered between the processing threads and the writer
+pub(crate) const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum OutputFormat {
+    // A single pretty-printed JSON array
+    Json,
+    // One compact JSON object per line
+    Jsonl,
+    // Apache Parquet file with one column per field
+    Parquet,
+    // Arrow IPC file (Feather v2) of record batches
+    Arrow,
+    // Tab-separated values with a header row
+    Tsv,
+    // Concatenated MessagePack maps, one per record
+    Msgpack,
+    // Length-delimited protobuf messages, see proto/ccqa.proto
+    Proto,
+}
+
+impl OutputFormat {
+    pub(crate) fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "json" => Some(OutputFormat::Json),
+            "jsonl" => Some(OutputFormat::Jsonl),
+            "parquet" => Some(OutputFormat::Parquet),
+            "arrow" | "feather" => Some(OutputFormat::Arrow),
+            "tsv" => Some(OutputFormat::Tsv),
+            "msgpack" => Some(OutputFormat::Msgpack),
+            "proto" => Some(OutputFormat::Proto),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    // Gzip with the given level (0-9)
+    Gzip(u32),
+    // Zstandard with the given level (1-22) and an optional pre-trained dictionary
+    Zstd(i32, Option<Vec<u8>>),
+}
+
+impl Compression {
+    // Parse a `codec[:level]` specification such as `gzip`, `gzip:9` or `zstd:19`.
+    // A level given as part of the codec takes precedence over `default_level`.
+    pub(crate) fn parse(
+        spec: &str,
+        default_level: Option<&str>,
+        zstd_dictionary: Option<Vec<u8>>,
+    ) -> Result<Compression, String> {
+        let mut parts = spec.splitn(2, ':');
+        let codec = parts.next().unwrap_or("");
+        let level = parts.next().or(default_level);
+        let invalid_level = |level: &str| format!("Invalid {} compression level {}", codec, level);
+        match codec {
+            "none" => Ok(Compression::None),
+            "gzip" => match level {
+                Some(level) => match level.parse() {
+                    Ok(parsed) if parsed <= 9 => Ok(Compression::Gzip(parsed)),
+                    _ => Err(invalid_level(level)),
+                },
+                None => Ok(Compression::Gzip(6)),
+            },
+            "zstd" => match level {
+                Some(level) => match level.parse() {
+                    Ok(parsed) if (1..=22).contains(&parsed) => {
+                        Ok(Compression::Zstd(parsed, zstd_dictionary))
+                    }
+                    _ => Err(invalid_level(level)),
+                },
+                None => Ok(Compression::Zstd(3, zstd_dictionary)),
+            },
+            _ => Err(format!("Unknown compression codec {}", codec)),
+        }
+    }
+}
+
+// Output path selecting standard output instead of a file
+pub(crate) const STDOUT_PATH: &str = "-";
+
+// Output file, standard output (for composing the extraction with other tools in a pipeline) or
+// S3 upload
+pub(crate) enum Destination {
+    File(File),
+    Stdout(std::io::Stdout),
+    S3(upload::S3Writer),
+}
+
+impl Write for Destination {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Destination::File(file) => file.write(buf),
+            Destination::Stdout(stdout) => stdout.write(buf),
+            Destination::S3(upload) => upload.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Destination::File(file) => file.flush(),
+            Destination::Stdout(stdout) => stdout.flush(),
+            Destination::S3(upload) => upload.flush(),
+        }
+    }
+}
+
+// Destination the serialized records end up in, optionally compressed on the fly
+pub(crate) enum Sink {
+    Plain(Destination),
+    Gzip(GzEncoder<Destination>),
+    Zstd(ZstdEncoder<'static, Destination>),
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Sink::Plain(file) => file.write(buf),
+            Sink::Gzip(encoder) => encoder.write(buf),
+            Sink::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(file) => file.flush(),
+            Sink::Gzip(encoder) => encoder.flush(),
+            Sink::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl Sink {
+    fn new(file: Destination, compression: &Compression) -> std::io::Result<Sink> {
+        Ok(match compression {
+            Compression::None => Sink::Plain(file),
+            Compression::Gzip(level) => {
+                Sink::Gzip(GzEncoder::new(file, GzCompression::new(*level)))
+            }
+            Compression::Zstd(level, None) => Sink::Zstd(ZstdEncoder::new(file, *level)?),
+            Compression::Zstd(level, Some(dictionary)) => {
+                Sink::Zstd(ZstdEncoder::with_dictionary(file, *level, dictionary)?)
+            }
+        })
+    }
+
+    fn is_compressed(&self) -> bool {
+        !matches!(self, Sink::Plain(_))
+    }
+
+    // Write the compression trailer (if any) and flush everything to the file
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(file) => file.flush(),
+            Sink::Gzip(encoder) => encoder.try_finish(),
+            Sink::Zstd(encoder) => encoder.do_finish(),
+        }
+    }
+}
+
+// Serializes records one at a time, so the whole corpus never has to be held in memory
+pub(crate) trait RecordWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()>;
+    // Write any trailing data and flush the underlying file
+    fn finish(&mut self) -> std::io::Result<()>;
+    // Bytes that reached the file or upload so far, known to the writers of create_file_writer
+    fn written_bytes(&self) -> std::io::Result<u64> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "The size written by this writer is unknown",
+        ))
+    }
+}
+
+// Streams a pretty-printed JSON array, byte-identical to serializing the full Vec at once
+struct JsonArrayWriter {
+    writer: BufWriter<Sink>,
+    records: u64,
+}
+
+impl RecordWriter for JsonArrayWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        self.writer
+            .write_all(if self.records == 0 { b"[\n" } else { b",\n" })?;
+        let json_val = serde_json::to_string_pretty(record)?;
+        // Nest the object one level into the array. Newlines inside strings are escaped,
+        // so every line break here belongs to the JSON structure.
+        for (idx, line) in json_val.split('\n').enumerate() {
+            if idx > 0 {
+                self.writer.write_all(b"\n")?;
+            }
+            self.writer.write_all(b"  ")?;
+            self.writer.write_all(line.as_bytes())?;
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer
+            .write_all(if self.records == 0 { b"[]" } else { b"\n]" })?;
+        self.writer.flush()?;
+        self.writer.get_mut().finish()
+    }
+}
+
+// One compact JSON object per line
+struct JsonlWriter {
+    writer: BufWriter<Sink>,
+    // Flush after every line, so consumers can stream the file while it is written
+    flush_lines: bool,
+}
+
+impl RecordWriter for JsonlWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        if self.flush_lines {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_mut().finish()
+    }
+}
+
+// One MessagePack map per record, written back to back. Field names are kept so the stream
+// decodes to the same objects as the JSON outputs, at roughly half the size.
+struct MsgpackWriter {
+    writer: BufWriter<Sink>,
+}
+
+impl RecordWriter for MsgpackWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        rmp_serde::encode::write_named(&mut self.writer, record).map_err(columnar::to_io_error)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_mut().finish()
+    }
+}
+
+// Stream of varint length-prefixed MinifiedRecord messages, readable with parseDelimitedFrom
+struct ProtoWriter {
+    writer: BufWriter<Sink>,
+}
+
+impl RecordWriter for ProtoWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        let message = proto::MinifiedRecord::from(record);
+        let mut buffer = Vec::with_capacity(message.encoded_len() + 10);
+        message
+            .encode_length_delimited(&mut buffer)
+            .map_err(columnar::to_io_error)?;
+        self.writer.write_all(&buffer)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_mut().finish()
+    }
+}
+
+// Escape a TSV field so embedded tabs and line breaks don't break the row structure
+fn escape_tsv(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const TSV_HEADER: &str =
+    "id\tmhtml\tquestions\tnum_questions\tcontent_hash\traw_questions\tstructured\tlanguage\tdetected_language\tdetected_language_confidence\turi\tcanonical_uri\tip_address\tcrawl_date\trecord_id\tpayload_digest\ttitle\tog_title\tog_description\tmeta_description\tmetadata\ttags\tcontext\thas_bidi_controls\textraction_confidence\tconfig_hash\tnetwork\n";
+
+// One escaped record per line, for awk/sort based pipelines. Lists and objects are JSON encoded.
+struct TsvWriter {
+    writer: BufWriter<Sink>,
+    header_written: bool,
+}
+
+impl RecordWriter for TsvWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        if !self.header_written {
+            self.writer.write_all(TSV_HEADER.as_bytes())?;
+            self.header_written = true;
+        }
+        let metadata = match &record.metadata {
+            Some(metadata) => serde_json::to_string(metadata)?,
+            None => String::new(),
+        };
+        let questions = serde_json::to_string(&record.questions)?;
+        let num_questions = record.num_questions.to_string();
+        let raw_questions = serde_json::to_string(&record.raw_questions)?;
+        let structured = if record.structured.is_empty() {
+            String::new()
+        } else {
+            serde_json::to_string(&record.structured)?
+        };
+        let detected_language_confidence = record
+            .detected_language_confidence
+            .map(|confidence| confidence.to_string())
+            .unwrap_or_default();
+        let tags = serde_json::to_string(&record.tags)?;
+        let context = serde_json::to_string(&record.context)?;
+        let network = match &record.network {
+            Some(network) => serde_json::to_string(network)?,
+            None => String::new(),
+        };
+        let fields = [
+            record.id.as_str(),
+            record.mhtml.as_str(),
+            questions.as_str(),
+            num_questions.as_str(),
+            record.content_hash.as_str(),
+            raw_questions.as_str(),
+            structured.as_str(),
+            record.language.as_str(),
+            record.detected_language.as_deref().unwrap_or(""),
+            detected_language_confidence.as_str(),
+            record.uri.as_deref().unwrap_or(""),
+            record.canonical_uri.as_deref().unwrap_or(""),
+            record.ip_address.as_deref().unwrap_or(""),
+            record.crawl_date.as_deref().unwrap_or(""),
+            record.record_id.as_deref().unwrap_or(""),
+            record.payload_digest.as_deref().unwrap_or(""),
+            record.title.as_deref().unwrap_or(""),
+            record.og_title.as_deref().unwrap_or(""),
+            record.og_description.as_deref().unwrap_or(""),
+            record.meta_description.as_deref().unwrap_or(""),
+            metadata.as_str(),
+            tags.as_str(),
+            context.as_str(),
+            if record.has_bidi_controls {
+                "true"
+            } else {
+                "false"
+            },
+            record.extraction_confidence.name(),
+            record.config_hash.as_str(),
+            network.as_str(),
+        ];
+        let line = fields
+            .iter()
+            .map(|field| escape_tsv(field))
+            .collect::<Vec<String>>()
+            .join("\t");
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        if !self.header_written {
+            self.writer.write_all(TSV_HEADER.as_bytes())?;
+        }
+        self.writer.flush()?;
+        self.writer.get_mut().finish()
+    }
+}
+
+// Columnar files the record batches are written to
+enum ColumnarFile {
+    // Parquet compresses its column chunks internally, so the selected codec is applied there
+    // instead of to the whole file, at the codec's default level
+    Parquet(Option<ArrowWriter<File>>),
+    // Arrow IPC file (Feather v2), loadable zero-copy from pyarrow
+    ArrowIpc(FileWriter<BufWriter<Destination>>),
+}
+
+// Collects records into record batches of a columnar file
+struct ColumnarWriter {
+    file: ColumnarFile,
+    batch: Vec<HTMLMinified>,
+}
+
+impl ColumnarWriter {
+    fn parquet(file: File, compression: &Compression) -> std::io::Result<ColumnarWriter> {
+        let codec = match compression {
+            Compression::None => ParquetCompression::UNCOMPRESSED,
+            Compression::Gzip(_) => ParquetCompression::GZIP,
+            Compression::Zstd(_, _) => ParquetCompression::ZSTD,
+        };
+        let properties = WriterProperties::builder().set_compression(codec).build();
+        let writer = ArrowWriter::try_new(file, columnar::schema(), Some(properties))
+            .map_err(columnar::to_io_error)?;
+        Ok(ColumnarWriter::new(ColumnarFile::Parquet(Some(writer))))
+    }
+
+    fn arrow_ipc(file: Destination, compression: &Compression) -> std::io::Result<ColumnarWriter> {
+        if *compression != Compression::None {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Arrow IPC output can not be compressed, as it would no longer be memory-mappable",
+            ));
+        }
+        let writer = FileWriter::try_new(BufWriter::new(file), &columnar::schema())
+            .map_err(columnar::to_io_error)?;
+        Ok(ColumnarWriter::new(ColumnarFile::ArrowIpc(writer)))
+    }
+
+    fn new(file: ColumnarFile) -> ColumnarWriter {
+        ColumnarWriter {
+            file,
+            batch: Vec::with_capacity(columnar::BATCH_SIZE),
+        }
+    }
+
+    fn write_batch(&mut self) -> std::io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = columnar::to_record_batch(&self.batch).map_err(columnar::to_io_error)?;
+        match &mut self.file {
+            ColumnarFile::Parquet(writer) => writer
+                .as_mut()
+                .unwrap()
+                .write(&batch)
+                .map_err(columnar::to_io_error)?,
+            ColumnarFile::ArrowIpc(writer) => {
+                writer.write(&batch).map_err(columnar::to_io_error)?
+            }
+        }
+        self.batch.clear();
+        Ok(())
+    }
+}
+
+impl RecordWriter for ColumnarWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        self.batch.push(record.clone());
+        if self.batch.len() >= columnar::BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.write_batch()?;
+        match &mut self.file {
+            ColumnarFile::Parquet(writer) => {
+                if let Some(mut writer) = writer.take() {
+                    writer.close().map_err(columnar::to_io_error)?;
+                }
+            }
+            ColumnarFile::ArrowIpc(writer) => {
+                writer.finish().map_err(columnar::to_io_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn open_output(output_file_path: &str) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(output_file_path)
+}
+
+// Size limits after which the output rolls over to the next shard
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ShardLimits {
+    pub(crate) max_records: Option<u64>,
+    pub(crate) max_bytes: Option<u64>,
+}
+
+impl ShardLimits {
+    pub(crate) fn is_sharded(&self) -> bool {
+        self.max_records.is_some() || self.max_bytes.is_some()
+    }
+}
+
+// Path of a numbered shard, inserted before the file extensions:
+// output.jsonl -> output.00001.jsonl, output.jsonl.gz -> output.00001.jsonl.gz
+pub(crate) fn shard_path(output_file_path: &str, shard: usize) -> String {
+    let path = Path::new(output_file_path);
+    let file_name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let shard_name = match file_name.find('.') {
+        Some(pos) if pos > 0 => format!("{}.{:05}{}", &file_name[..pos], shard, &file_name[pos..]),
+        _ => format!("{}.{:05}", file_name, shard),
+    };
+    path.with_file_name(shard_name)
+        .to_string_lossy()
+        .to_string()
+}
+
+// Splits the output over numbered shards, each one a complete file of the chosen format
+struct ShardedWriter {
+    output_file_path: String,
+    format: OutputFormat,
+    compression: Compression,
+    limits: ShardLimits,
+    current: Option<Box<dyn RecordWriter>>,
+    current_path: String,
+    shards: usize,
+    records_in_shard: u64,
+}
+
+impl ShardedWriter {
+    // The byte limit is checked against the data written out, so a shard may exceed it by the
+    // writer's (and compressor's) buffered data
+    fn is_full(&self) -> std::io::Result<bool> {
+        if let Some(max_records) = self.limits.max_records {
+            if self.records_in_shard >= max_records {
+                return Ok(true);
+            }
+        }
+        if let Some(max_bytes) = self.limits.max_bytes {
+            if self.current.as_ref().unwrap().written_bytes()? >= max_bytes {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn next_shard(&mut self) -> std::io::Result<()> {
+        if let Some(mut writer) = self.current.take() {
+            writer.finish()?;
+        }
+        self.shards += 1;
+        self.current_path = shard_path(&self.output_file_path, self.shards);
+        self.current = Some(create_file_writer(
+            &self.current_path,
+            self.format,
+            &self.compression,
+        )?);
+        self.records_in_shard = 0;
+        Ok(())
+    }
+}
+
+impl RecordWriter for ShardedWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        if self.current.is_none() || self.is_full()? {
+            self.next_shard()?;
+        }
+        self.current.as_mut().unwrap().write_record(record)?;
+        self.records_in_shard += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        // Always leave at least one (empty) shard behind
+        if self.current.is_none() {
+            self.next_shard()?;
+        }
+        self.current.take().unwrap().finish()?;
+        eprintln!("Split the output over {} shards", self.shards);
+        Ok(())
+    }
+}
+
+pub(crate) fn create_writer(
+    output_file_path: &str,
+    format: OutputFormat,
+    compression: &Compression,
+    limits: ShardLimits,
+) -> std::io::Result<Box<dyn RecordWriter>> {
+    if !limits.is_sharded() {
+        return create_file_writer(output_file_path, format, compression);
+    }
+    if output_file_path == STDOUT_PATH {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Standard output can not be split into shards",
+        ));
+    }
+    Ok(Box::new(ShardedWriter {
+        output_file_path: output_file_path.to_string(),
+        format,
+        compression: compression.clone(),
+        limits,
+        current: None,
+        current_path: String::new(),
+        shards: 0,
+        records_in_shard: 0,
+    }))
+}
+
+// Writer appending to an existing output file (--append). Only the record stream formats can be
+// extended in place; compressed outputs get a new gzip member or zstd frame, which decoders read
+// as one continuous stream.
+pub(crate) fn create_append_writer(
+    output_file_path: &str,
+    format: OutputFormat,
+    compression: &Compression,
+) -> std::io::Result<Box<dyn RecordWriter>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output_file_path)?;
+    let sink = Sink::new(Destination::File(file), compression)?;
+    let writer: Box<dyn RecordWriter> = match format {
+        OutputFormat::Jsonl => Box::new(JsonlWriter {
+            flush_lines: !sink.is_compressed(),
+            writer: BufWriter::new(sink),
+        }),
+        OutputFormat::Msgpack => Box::new(MsgpackWriter {
+            writer: BufWriter::new(sink),
+        }),
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Only jsonl and msgpack outputs can be appended to",
+            ))
+        }
+    };
+    Ok(writer)
+}
+
+// Compression of a file, told apart by its magic bytes rather than its extension
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Codec {
+    None,
+    Gzip,
+    Zstd,
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+impl Codec {
+    pub(crate) fn sniff(content: &[u8]) -> Codec {
+        if content.starts_with(GZIP_MAGIC) {
+            Codec::Gzip
+        } else if content.starts_with(ZSTD_MAGIC) {
+            Codec::Zstd
+        } else {
+            Codec::None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+        }
+    }
+}
+
+impl Compression {
+    fn codec(&self) -> Codec {
+        match self {
+            Compression::None => Codec::None,
+            Compression::Gzip(_) => Codec::Gzip,
+            Compression::Zstd(_, _) => Codec::Zstd,
+        }
+    }
+}
+
+// Read a file, transparently decompressing gzip and zstd files
+pub(crate) fn read_decompressed(path: &Path) -> std::io::Result<Vec<u8>> {
+    let raw = std::fs::read(path)?;
+    let mut content = Vec::new();
+    match Codec::sniff(&raw) {
+        Codec::None => return Ok(raw),
+        Codec::Gzip => MultiGzDecoder::new(raw.as_slice()).read_to_end(&mut content)?,
+        Codec::Zstd => {
+            zstd::stream::read::Decoder::new(raw.as_slice())?.read_to_end(&mut content)?
+        }
+    };
+    Ok(content)
+}
+
+// Read all records of an output file written as json, jsonl or msgpack (optionally compressed),
+// telling the formats apart by their first byte
+pub(crate) fn read_records(path: &Path) -> std::io::Result<Vec<HTMLMinified>> {
+    let content = read_decompressed(path)?;
+    let invalid = |err: String| std::io::Error::new(std::io::ErrorKind::InvalidData, err);
+    match content.iter().find(|byte| !byte.is_ascii_whitespace()) {
+        None => Ok(Vec::new()),
+        Some(b'[') => serde_json::from_slice(&content).map_err(|err| invalid(err.to_string())),
+        Some(b'{') => String::from_utf8_lossy(&content)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(idx, line)| {
+                serde_json::from_str(line)
+                    .map_err(|err| invalid(format!("line {}: {}", idx + 1, err)))
+            })
+            .collect(),
+        Some(_) => {
+            let mut records = Vec::new();
+            let mut cursor = std::io::Cursor::new(&content);
+            while (cursor.position() as usize) < content.len() {
+                let mut deserializer = rmp_serde::Deserializer::new(&mut cursor);
+                records.push(
+                    HTMLMinified::deserialize(&mut deserializer)
+                        .map_err(|err| invalid(err.to_string()))?,
+                );
+            }
+            Ok(records)
+        }
+    }
+}
+
+// Decompress the complete gzip members or zstd frames of a compressed output, returning them
+// along with the number of bytes they take up in the file. Each append writes a member or frame
+// of its own, the one of a killed run ends the stream early.
+fn decompress_complete(content: &[u8], codec: Codec) -> (Vec<u8>, usize) {
+    let mut decompressed = Vec::new();
+    let mut cursor = std::io::Cursor::new(content);
+    let mut complete = 0;
+    while complete < content.len() {
+        let mut frame = Vec::new();
+        let read = match codec {
+            Codec::None => unreachable!(),
+            Codec::Gzip => flate2::bufread::GzDecoder::new(&mut cursor).read_to_end(&mut frame),
+            Codec::Zstd => zstd::stream::read::Decoder::with_buffer(&mut cursor)
+                .and_then(|decoder| decoder.single_frame().read_to_end(&mut frame)),
+        };
+        if read.is_err() || cursor.position() as usize == complete {
+            break;
+        }
+        decompressed.extend(frame);
+        complete = cursor.position() as usize;
+    }
+    (decompressed, complete)
+}
+
+// Hashes of the URIs of a jsonl or msgpack record stream, along with the number of bytes taken up
+// by its complete records. Only the last record may be cut off.
+fn stream_uris(content: &[u8], format: OutputFormat) -> std::io::Result<(HashSet<u64>, usize)> {
+    let mut uris = HashSet::new();
+    let mut complete = 0;
+    if format == OutputFormat::Msgpack {
+        let mut cursor = std::io::Cursor::new(content);
+        while complete < content.len() {
+            let mut deserializer = rmp_serde::Deserializer::new(&mut cursor);
+            let record = match HTMLMinified::deserialize(&mut deserializer) {
+                Ok(record) => record,
+                Err(_) => break,
+            };
+            if let Some(uri) = record.uri {
+                uris.insert(xxh3_64(uri.as_bytes()));
+            }
+            complete = cursor.position() as usize;
+        }
+    } else {
+        // Every record is written with its newline, a last line without one is cut off
+        while let Some(length) = content[complete..].iter().position(|byte| *byte == b'\n') {
+            let line = String::from_utf8_lossy(&content[complete..complete + length]);
+            if !line.trim().is_empty() {
+                let record: serde_json::Value = serde_json::from_str(&line)?;
+                if let Some(uri) = record.get("uri").and_then(|uri| uri.as_str()) {
+                    uris.insert(xxh3_64(uri.as_bytes()));
+                }
+            }
+            complete += length + 1;
+        }
+    }
+    Ok((uris, complete))
+}
+
+// Prepare an existing jsonl or msgpack output for --append: check that it is compressed like the
+// records about to be appended, cut off the record, gzip member or zstd frame left incomplete by
+// a killed run, and return the hashes of the URIs already present. Empty if the output doesn't
+// exist yet.
+pub(crate) fn prepare_append(
+    output_file_path: &str,
+    format: OutputFormat,
+    compression: &Compression,
+) -> std::io::Result<HashSet<u64>> {
+    let content = match std::fs::read(output_file_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(err) => return Err(err),
+    };
+    if content.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let codec = Codec::sniff(&content);
+    if codec != compression.codec() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{} is compressed with {}, append to it with --compress {}",
+                output_file_path,
+                codec.name(),
+                codec.name()
+            ),
+        ));
+    }
+    let (uris, complete) = if codec == Codec::None {
+        stream_uris(&content, format)?
+    } else {
+        let (decompressed, complete) = decompress_complete(&content, codec);
+        let (uris, records) = stream_uris(&decompressed, format)?;
+        // Members and frames are only completed once all of their records are written
+        if records < decompressed.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} holds an incomplete record", output_file_path),
+            ));
+        }
+        (uris, complete)
+    };
+    if complete < content.len() {
+        eprintln!(
+            "Cutting off the last {} bytes of {}, left incomplete by an earlier run",
+            content.len() - complete,
+            output_file_path
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(output_file_path)?
+            .set_len(complete as u64)?;
+    }
+    Ok(uris)
+}
+
+// File an output is written to until it is complete
+fn partial_path(output_file_path: &str) -> String {
+    format!("{}.tmp", output_file_path)
+}
+
+// Writes to the partial file and renames it to the output path once finished, so a killed job
+// never leaves a truncated output behind. The partial file is removed if the writer is dropped
+// unfinished, e.g. on an error.
+struct RenameOnFinish {
+    writer: Box<dyn RecordWriter>,
+    partial_path: String,
+    output_file_path: String,
+    finished: bool,
+}
+
+impl RecordWriter for RenameOnFinish {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        self.writer.write_record(record)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.finish()?;
+        std::fs::rename(&self.partial_path, &self.output_file_path)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    fn written_bytes(&self) -> std::io::Result<u64> {
+        Ok(std::fs::metadata(&self.partial_path)?.len())
+    }
+}
+
+impl Drop for RenameOnFinish {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = std::fs::remove_file(&self.partial_path);
+        }
+    }
+}
+
+// Uploads an output to S3 while it is written and completes the upload once finished
+struct UploadOnFinish {
+    writer: Box<dyn RecordWriter>,
+    upload: Option<upload::S3Upload>,
+}
+
+impl RecordWriter for UploadOnFinish {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        self.writer.write_record(record)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.finish()?;
+        self.upload.take().unwrap().finish()
+    }
+
+    fn written_bytes(&self) -> std::io::Result<u64> {
+        Ok(self
+            .upload
+            .as_ref()
+            .map_or(0, |upload| upload.written_bytes()))
+    }
+}
+
+// How a side stream gets into place once finished
+enum Completion {
+    Rename { partial_path: String, path: String },
+    Upload(upload::S3Upload),
+}
+
+// Side stream (rejections, HowTos, ...) written by concurrent workers like the outputs: to its
+// partial file renamed into place by finish and removed if dropped unfinished, or to an upload
+// only completed by finish
+pub(crate) struct StreamFile {
+    writer: Mutex<BufWriter<Destination>>,
+    completion: Mutex<Option<Completion>>,
+}
+
+impl StreamFile {
+    pub(crate) fn create(path: &str) -> std::io::Result<StreamFile> {
+        let (destination, completion) = match upload::S3Location::parse(path) {
+            Some(location) => {
+                let (writer, upload) = upload::S3Upload::start(&location)?;
+                (Destination::S3(writer), Completion::Upload(upload))
+            }
+            None => {
+                let partial_path = partial_path(path);
+                let file = open_output(&partial_path)?;
+                (
+                    Destination::File(file),
+                    Completion::Rename {
+                        partial_path,
+                        path: path.to_string(),
+                    },
+                )
+            }
+        };
+        Ok(StreamFile {
+            writer: Mutex::new(BufWriter::new(destination)),
+            completion: Mutex::new(Some(completion)),
+        })
+    }
+
+    pub(crate) fn write_all(&self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.lock().unwrap().write_all(bytes)
+    }
+
+    // Lines are written whole while holding the lock, so those of concurrent workers never
+    // interleave; serializing beforehand keeps the lock short
+    pub(crate) fn write_line(&self, value: &impl Serialize) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        self.write_all(&line)
+    }
+
+    pub(crate) fn finish(&self) -> std::io::Result<()> {
+        self.writer.lock().unwrap().flush()?;
+        match self.completion.lock().unwrap().take() {
+            Some(Completion::Rename { partial_path, path }) => std::fs::rename(partial_path, path),
+            Some(Completion::Upload(upload)) => upload.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for StreamFile {
+    fn drop(&mut self) {
+        if let Some(Completion::Rename { partial_path, .. }) =
+            self.completion.lock().unwrap().take()
+        {
+            let _ = std::fs::remove_file(partial_path);
+        }
+    }
+}
+
+fn create_file_writer(
+    output_file_path: &str,
+    format: OutputFormat,
+    compression: &Compression,
+) -> std::io::Result<Box<dyn RecordWriter>> {
+    if output_file_path == STDOUT_PATH {
+        let stdout = Destination::Stdout(std::io::stdout());
+        return create_format_writer(stdout, format, compression);
+    }
+    if let Some(location) = upload::S3Location::parse(output_file_path) {
+        let (writer, upload) = upload::S3Upload::start(&location)?;
+        return Ok(Box::new(UploadOnFinish {
+            writer: create_format_writer(Destination::S3(writer), format, compression)?,
+            upload: Some(upload),
+        }));
+    }
+    let partial_path = partial_path(output_file_path);
+    let file = Destination::File(open_output(&partial_path)?);
+    Ok(Box::new(RenameOnFinish {
+        writer: create_format_writer(file, format, compression)?,
+        partial_path,
+        output_file_path: output_file_path.to_string(),
+        finished: false,
+    }))
+}
+
+fn create_format_writer(
+    destination: Destination,
+    format: OutputFormat,
+    compression: &Compression,
+) -> std::io::Result<Box<dyn RecordWriter>> {
+    // Parquet writes its footer by seeking back into the file
+    if format == OutputFormat::Parquet {
+        return match destination {
+            Destination::File(file) => Ok(Box::new(ColumnarWriter::parquet(file, compression)?)),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Parquet output can only be written to a local file",
+            )),
+        };
+    }
+    if format == OutputFormat::Arrow {
+        return Ok(Box::new(ColumnarWriter::arrow_ipc(
+            destination,
+            compression,
+        )?));
+    }
+    let sink = Sink::new(destination, compression)?;
+    let writer: Box<dyn RecordWriter> = match format {
+        OutputFormat::Json => Box::new(JsonArrayWriter {
+            writer: BufWriter::new(sink),
+            records: 0,
+        }),
+        // Flushing a compressed stream per line would ruin the compression ratio
+        OutputFormat::Jsonl => Box::new(JsonlWriter {
+            flush_lines: !sink.is_compressed(),
+            writer: BufWriter::new(sink),
+        }),
+        OutputFormat::Tsv => Box::new(TsvWriter {
+            writer: BufWriter::new(sink),
+            header_written: false,
+        }),
+        OutputFormat::Msgpack => Box::new(MsgpackWriter {
+            writer: BufWriter::new(sink),
+        }),
+        OutputFormat::Proto => Box::new(ProtoWriter {
+            writer: BufWriter::new(sink),
+        }),
+        OutputFormat::Parquet | OutputFormat::Arrow => unreachable!(),
+    };
+    Ok(writer)
+}
+
+// Path of the rejection stream next to an output file: output.jsonl -> output.rejected.jsonl
+pub(crate) fn rejected_path(output_file_path: &str) -> String {
+    stream_path(output_file_path, "rejected")
+}
+
+// Path of the HowTo stream next to an output file: output.jsonl -> output.howto.jsonl
+pub(crate) fn howto_path(output_file_path: &str) -> String {
+    stream_path(output_file_path, "howto")
+}
+
+// Path of the FAQPage stream next to an output file: output.jsonl -> output.faqpage.jsonl
+pub(crate) fn faqpage_path(output_file_path: &str) -> String {
+    stream_path(output_file_path, "faqpage")
+}
+
+fn stream_path(output_file_path: &str, stream: &str) -> String {
+    let path = Path::new(output_file_path);
+    let file_name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = match file_name.find('.') {
+        Some(pos) if pos > 0 => &file_name[..pos],
+        _ => file_name.as_str(),
+    };
+    path.with_file_name(format!("{}.{}.jsonl", stem, stream))
+        .to_string_lossy()
+        .to_string()
+}
+
+// Path of the spillover file next to an output file: output.jsonl -> oversize-output.jsonl
+pub(crate) fn oversize_path(output_file_path: &str) -> String {
+    let path = Path::new(output_file_path);
+    let file_name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = match file_name.find('.') {
+        Some(pos) if pos > 0 => &file_name[..pos],
+        _ => file_name.as_str(),
+    };
+    path.with_file_name(format!("oversize-{}.jsonl", stem))
+        .to_string_lossy()
+        .to_string()
+}
+
+// Diverts records whose JSON serialization exceeds a size limit (--max-record-bytes) into an
+// uncompressed JSON Lines spillover file, keeping the main output friendly to loaders with record
+// size limits while preserving the long tail. The spillover file is only created once needed.
+pub(crate) struct SpilloverWriter {
+    writer: Box<dyn RecordWriter>,
+    max_record_bytes: u64,
+    oversize_path: String,
+    // Extend an existing spillover file instead of replacing it (--append)
+    append: bool,
+    oversize: Option<Box<dyn RecordWriter>>,
+    spilled: u64,
+}
+
+impl SpilloverWriter {
+    pub(crate) fn new(
+        writer: Box<dyn RecordWriter>,
+        max_record_bytes: u64,
+        output_file_path: &str,
+        append: bool,
+    ) -> SpilloverWriter {
+        SpilloverWriter {
+            writer,
+            max_record_bytes,
+            oversize_path: oversize_path(output_file_path),
+            append,
+            oversize: None,
+            spilled: 0,
+        }
+    }
+}
+
+impl RecordWriter for SpilloverWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        if (serde_json::to_vec(record)?.len() as u64) <= self.max_record_bytes {
+            return self.writer.write_record(record);
+        }
+        if self.oversize.is_none() {
+            self.oversize = Some(if self.append {
+                create_append_writer(&self.oversize_path, OutputFormat::Jsonl, &Compression::None)?
+            } else {
+                create_file_writer(&self.oversize_path, OutputFormat::Jsonl, &Compression::None)?
+            });
+        }
+        self.spilled += 1;
+        self.oversize.as_mut().unwrap().write_record(record)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.finish()?;
+        if let Some(mut oversize) = self.oversize.take() {
+            oversize.finish()?;
+            eprintln!(
+                "Wrote {} records over {} bytes to {}",
+                self.spilled, self.max_record_bytes, self.oversize_path
+            );
+        }
+        Ok(())
+    }
+}
+
+// A webpage dropped from the output, as written to the rejection stream
+#[derive(Serialize, Debug)]
+pub(crate) struct RejectedRecord<'a> {
+    pub(crate) id: &'a str,
+    pub(crate) uri: Option<&'a str>,
+    pub(crate) stage: &'a str,
+    pub(crate) reason: &'a str,
+    pub(crate) language: &'a str,
+}
+
+// JSON Lines file of all dropped webpages, to audit the corpus coverage
+pub(crate) struct RejectionLog {
+    file: StreamFile,
+}
+
+impl RejectionLog {
+    pub(crate) fn create(path: &str) -> std::io::Result<RejectionLog> {
+        Ok(RejectionLog {
+            file: StreamFile::create(path)?,
+        })
+    }
+
+    pub(crate) fn log(&self, record: &RejectedRecord) -> std::io::Result<()> {
+        self.file.write_line(record)
+    }
+
+    pub(crate) fn finish(&self) -> std::io::Result<()> {
+        self.file.finish()
+    }
+}
+
+// JSON Lines file of the records of one schema routed out of the main output (--split-schemas)
+pub(crate) struct RecordLog {
+    file: StreamFile,
+    records: AtomicU64,
+}
+
+impl RecordLog {
+    pub(crate) fn create(path: &str) -> std::io::Result<RecordLog> {
+        Ok(RecordLog {
+            file: StreamFile::create(path)?,
+            records: AtomicU64::new(0),
+        })
+    }
+
+    pub(crate) fn log(&self, record: &HTMLMinified) -> std::io::Result<()> {
+        self.file.write_line(record)?;
+        self.records.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub(crate) fn records(&self) -> u64 {
+        self.records.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn finish(&self) -> std::io::Result<()> {
+        self.file.finish()
+    }
+}
+
+// Train a zstd dictionary on existing JSON Lines outputs, using every record as a sample. Records
+// are highly repetitive HTML, so a shared dictionary improves the ratio considerably.
+pub(crate) fn train_zstd_dictionary(
+    sample_files: &[&str],
+    dictionary_path: &str,
+    max_size: usize,
+) -> std::io::Result<()> {
+    let mut samples = Vec::new();
+    for sample_file in sample_files {
+        let content = std::fs::read_to_string(sample_file)?;
+        samples.extend(
+            content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.as_bytes().to_vec()),
+        );
+    }
+    let dictionary = zstd::dict::from_samples(&samples, max_size)?;
+    std::fs::write(dictionary_path, &dictionary)?;
+    println!(
+        "Trained a {} byte dictionary on {} records",
+        dictionary.len(),
+        samples.len()
+    );
+    Ok(())
+}
+
+// A processed webpage tagged with its position among the processed webpages, None if dropped
+pub(crate) type Sequenced = (usize, Option<HTMLMinified>);
+
+// Write every record arriving on the channel until all senders are gone, returning the record count.
+// The writer is created on the writer thread itself; creation errors are returned right away.
+//
+// If `ordered`, records are written in sequence order instead of as they arrive, which requires
+// every processed webpage to be sent, dropped ones included.
+pub(crate) fn spawn_writer<F>(
+    make_writer: F,
+    receiver: Receiver<Sequenced>,
+    ordered: bool,
+    cancellation: extract::CancellationToken,
+) -> std::io::Result<JoinHandle<std::io::Result<u64>>>
+where
+    F: FnOnce() -> std::io::Result<Box<dyn RecordWriter>> + Send + 'static,
+{
+    let (ready_sender, ready_receiver) = sync_channel(1);
+    let handle = std::thread::spawn(move || -> std::io::Result<u64> {
+        let mut writer = match make_writer() {
+            Ok(writer) => {
+                let _ = ready_sender.send(Ok(()));
+                writer
+            }
+            Err(err) => {
+                let _ = ready_sender.send(Err(err));
+                return Ok(0);
+            }
+        };
+        let mut written = 0;
+        // Webpages finished ahead of their predecessors
+        let mut pending = BTreeMap::new();
+        let mut next_sequence = 0;
+        for (sequence, record) in receiver {
+            if !ordered {
+                if let Some(record) = record {
+                    writer.write_record(&record)?;
+                    written += 1;
+                }
+                continue;
+            }
+            pending.insert(sequence, record);
+            while let Some(record) = pending.remove(&next_sequence) {
+                if let Some(record) = record {
+                    writer.write_record(&record)?;
+                    written += 1;
+                }
+                next_sequence += 1;
+            }
+        }
+        // A failed run leaves no output behind that looks complete: the unfinished writer
+        // removes its partial file once dropped
+        if cancellation.is_cancelled() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "Cancelled before all records were written",
+            ));
+        }
+        writer.finish()?;
+        Ok(written)
+    });
+    ready_receiver
+        .recv()
+        .expect("Output writer exited before starting")?;
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("ccqa-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn renames_side_streams_once_finished() {
+        let path = temp_path("finished.jsonl");
+        let stream = StreamFile::create(&path).unwrap();
+        stream.write_all(b"{}\n").unwrap();
+        assert!(!Path::new(&path).exists());
+        stream.finish().unwrap();
+        drop(stream);
+        assert!(!Path::new(&partial_path(&path)).exists());
+        assert_eq!(std::fs::read(&path).unwrap(), b"{}\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn removes_unfinished_partial_files() {
+        let path = temp_path("unfinished.jsonl");
+        let stream = StreamFile::create(&path).unwrap();
+        stream.write_all(b"{}\n").unwrap();
+        drop(stream);
+        let writer = create_file_writer(&path, OutputFormat::Jsonl, &Compression::None).unwrap();
+        assert!(Path::new(&partial_path(&path)).exists());
+        drop(writer);
+        assert!(!Path::new(&partial_path(&path)).exists());
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn cuts_off_torn_records_before_appending() {
+        let path = temp_path("torn.jsonl");
+        std::fs::write(&path, "{\"uri\":\"a\"}\n{\"uri\":\"b").unwrap();
+        let uris = prepare_append(&path, OutputFormat::Jsonl, &Compression::None).unwrap();
+        assert_eq!(
+            uris,
+            [xxh3_64(b"a")].iter().copied().collect::<HashSet<u64>>()
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"{\"uri\":\"a\"}\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cuts_off_torn_gzip_members_before_appending() {
+        let gzip = |text: &str| {
+            let mut encoder = GzEncoder::new(Vec::new(), GzCompression::default());
+            encoder.write_all(text.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        };
+        let complete = gzip("{\"uri\":\"a\"}\n");
+        let torn = gzip("{\"uri\":\"b\"}\n");
+        let mut content = complete.clone();
+        content.extend_from_slice(&torn[..torn.len() - 4]);
+        let path = temp_path("torn.jsonl.gz");
+        std::fs::write(&path, &content).unwrap();
+        let mismatch = prepare_append(&path, OutputFormat::Jsonl, &Compression::None);
+        assert_eq!(
+            mismatch.unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        let uris = prepare_append(&path, OutputFormat::Jsonl, &Compression::Gzip(6)).unwrap();
+        assert_eq!(
+            uris,
+            [xxh3_64(b"a")].iter().copied().collect::<HashSet<u64>>()
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), complete);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
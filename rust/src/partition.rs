@@ -0,0 +1,113 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Output partitioned by registered domain (eTLD+1, `--partition-by domain`), for site-level
+// corpus analysis and per-site licensing review. The records of every domain go to a
+// subdirectory of that name next to the output path:
+//
+//     ccqa/out.jsonl -> ccqa/example.co.uk/out.jsonl, ccqa/example.com/out.jsonl, ...
+//
+// Records without a URI end up in `_unknown`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::events;
+use crate::output::{self, Compression, OutputFormat, RecordWriter, ShardLimits};
+use crate::HTMLMinified;
+
+const UNKNOWN_DOMAIN: &str = "_unknown";
+
+// Registered domain of a URI, the host itself if it has none (IP addresses, single labels)
+fn registered_domain(uri: &str) -> String {
+    let host = events::domain(uri).trim_end_matches('.').to_lowercase();
+    let domain = psl::domain_str(&host).unwrap_or(&host);
+    // Keep the directory name a single, portable path component
+    let domain = domain
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if domain.is_empty() || domain.starts_with('.') {
+        UNKNOWN_DOMAIN.to_string()
+    } else {
+        domain
+    }
+}
+
+// Path of the output of one domain
+fn partition_path(output_file_path: &str, domain: &str) -> String {
+    let path = Path::new(output_file_path);
+    let file_name = path.file_name().unwrap_or_default();
+    path.with_file_name(domain)
+        .join(file_name)
+        .to_string_lossy()
+        .to_string()
+}
+
+// Records are collected per domain and written once all have arrived. A WARC file spans far more
+// domains than a process may keep files open, while its output easily fits into memory.
+pub(crate) struct PartitionedWriter {
+    output_file_path: String,
+    format: OutputFormat,
+    compression: Compression,
+    limits: ShardLimits,
+    partitions: BTreeMap<String, Vec<HTMLMinified>>,
+}
+
+impl PartitionedWriter {
+    pub(crate) fn new(
+        output_file_path: &str,
+        format: OutputFormat,
+        compression: &Compression,
+        limits: ShardLimits,
+    ) -> PartitionedWriter {
+        PartitionedWriter {
+            output_file_path: output_file_path.to_string(),
+            format,
+            compression: compression.clone(),
+            limits,
+            partitions: BTreeMap::new(),
+        }
+    }
+}
+
+impl RecordWriter for PartitionedWriter {
+    fn write_record(&mut self, record: &HTMLMinified) -> std::io::Result<()> {
+        let domain = match &record.uri {
+            Some(uri) => registered_domain(uri),
+            None => UNKNOWN_DOMAIN.to_string(),
+        };
+        self.partitions
+            .entry(domain)
+            .or_insert_with(Vec::new)
+            .push(record.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        for (domain, records) in self.partitions.iter() {
+            let path = partition_path(&self.output_file_path, domain);
+            std::fs::create_dir_all(Path::new(&path).parent().unwrap())?;
+            let mut writer =
+                output::create_writer(&path, self.format, &self.compression, self.limits)?;
+            for record in records.iter() {
+                writer.write_record(record)?;
+            }
+            writer.finish()?;
+        }
+        eprintln!(
+            "Partitioned the output over {} domains",
+            self.partitions.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Passage retrieval (DPR, open-book) training files straight from minified outputs, the Rust
+// counterpart of `passage_retrieval_processing.py`. Every question becomes one JSON line of
+// `<output>.jsonl` with its answers as positive and hard negative passages, chosen by the markup
+// the page offers:
+//
+// - accepted and suggested answers with votes: the accepted answers and suggested answers with
+//   at least 2 upvotes are positives, suggested answers with fewer upvotes hard negatives
+// - accepted and suggested answers: accepted answers are positives, suggested ones hard negatives
+// - votes only: the most upvoted answer and answers with more than 1 upvote are positives, the
+//   other voted answers hard negatives
+// - neither: a single answer, picked by a hash of the question, is the positive
+//
+// The record id, URI and language are added as metadata next to the DPR fields.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+use serde::Serialize;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::qa::{self, Answer, AnswerStatus, QaOptions, Question, RecordQuestions};
+
+// Suggested answers with at least this many upvotes count as correct
+const MIN_POSITIVE_UPVOTES: i64 = 2;
+
+#[derive(Serialize)]
+struct Passage<'a> {
+    title: &'a str,
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+struct PassageInstance<'a> {
+    question: &'a str,
+    answers: Vec<&'a str>,
+    positive_ctxs: Vec<Passage<'a>>,
+    hard_negative_ctxs: Vec<Passage<'a>>,
+    id: &'a str,
+    uri: Option<&'a str>,
+    language: &'a str,
+}
+
+// Positive and hard negative answers of a question, following the markup available
+fn split_answers(question: &Question) -> (Vec<&Answer>, Vec<&Answer>) {
+    let answers = &question.answers;
+    let has_status = |status: AnswerStatus| answers.iter().any(|answer| answer.status == status);
+    let accepted = has_status(AnswerStatus::Accepted);
+    let suggested = has_status(AnswerStatus::Suggested);
+    let voted = answers.iter().any(|answer| answer.upvote_count.is_some());
+    let (mut positives, mut negatives) = (Vec::new(), Vec::new());
+    if accepted && suggested {
+        for answer in answers.iter() {
+            match (&answer.status, answer.upvote_count) {
+                (AnswerStatus::Accepted, _) => positives.push(answer),
+                (AnswerStatus::Suggested, Some(count)) if voted => {
+                    if count >= MIN_POSITIVE_UPVOTES {
+                        positives.push(answer)
+                    } else {
+                        negatives.push(answer)
+                    }
+                }
+                // Without any votes on the page, suggested answers are plain negatives
+                (AnswerStatus::Suggested, None) if !voted => negatives.push(answer),
+                _ => {}
+            }
+        }
+    } else if voted {
+        // The first of equally upvoted answers is the best one
+        let mut best: Option<usize> = None;
+        for (idx, answer) in answers.iter().enumerate() {
+            if let Some(count) = answer.upvote_count {
+                if best.map_or(true, |best| answers[best].upvote_count.unwrap() < count) {
+                    best = Some(idx);
+                }
+            }
+        }
+        let best = best.unwrap();
+        positives.push(&answers[best]);
+        for (idx, answer) in answers.iter().enumerate() {
+            match answer.upvote_count {
+                Some(count) if idx != best && count >= MIN_POSITIVE_UPVOTES => {
+                    positives.push(answer)
+                }
+                Some(_) if idx != best => negatives.push(answer),
+                _ => {}
+            }
+        }
+    } else if !answers.is_empty() {
+        // Deterministic stand-in for the random pick of the Python version
+        let idx = (xxh3_64(question.text.as_bytes()) % answers.len() as u64) as usize;
+        positives.push(&answers[idx]);
+    }
+    (positives, negatives)
+}
+
+fn passages<'a>(answers: &[&'a Answer]) -> Vec<Passage<'a>> {
+    answers
+        .iter()
+        .map(|answer| Passage {
+            title: "",
+            text: &answer.text,
+        })
+        .collect()
+}
+
+fn write_record(writer: &mut impl Write, record: &RecordQuestions) -> std::io::Result<usize> {
+    let mut num_instances = 0;
+    for question in record.questions.iter() {
+        let (positives, negatives) = split_answers(question);
+        if positives.is_empty() {
+            continue;
+        }
+        let instance = PassageInstance {
+            question: &question.text,
+            answers: Vec::new(),
+            positive_ctxs: passages(&positives),
+            hard_negative_ctxs: passages(&negatives),
+            id: &record.id,
+            uri: record.uri.as_deref(),
+            language: &record.language,
+        };
+        serde_json::to_writer(&mut *writer, &instance)?;
+        writer.write_all(b"\n")?;
+        num_instances += 1;
+    }
+    Ok(num_instances)
+}
+
+pub(crate) fn generate(
+    inputs: &[&str],
+    output_path: &str,
+    options: &QaOptions,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(format!("{}.jsonl", output_path))?);
+    let mut num_instances = 0;
+    for input in inputs.iter() {
+        let records = qa::read_questions(input, options)?;
+        for record in records.iter() {
+            num_instances += write_record(&mut writer, record)?;
+        }
+        eprintln!("Read {} records of {}", records.len(), input);
+    }
+    writer.flush()?;
+    println!(
+        "Wrote {} passage retrieval instances to {}.jsonl",
+        num_instances, output_path
+    );
+    Ok(())
+}
@@ -0,0 +1,199 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Plain text of the cleaned Questions (--text-mode plain), the Rust counterpart of
+// `extract_text(..., keep_markup=False)` in Python, for consumers that never need the markup.
+// Every property and block element starts a new line, `<br>` breaks the line and whitespace is
+// collapsed within lines. List items become "- " bullets and table rows tab-separated lines,
+// since the structure matters for the quality of the answers:
+//
+//     How do I repot a cactus?
+//     You need:
+//     - gloves
+//     - a bigger pot
+//     Size\tPot
+//     10 cm\t12 cm
+
+use std::borrow::Cow;
+
+use kuchiki::NodeRef;
+
+use crate::langrules::Entities;
+
+// What the Questions of the output are made of (--text-mode)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextMode {
+    // The cleaned microdata HTML
+    Markup,
+    // Normalized text without any markup
+    Plain,
+}
+
+impl TextMode {
+    pub fn from_name(name: &str) -> Option<TextMode> {
+        match name {
+            "markup" => Some(TextMode::Markup),
+            "plain" => Some(TextMode::Plain),
+            _ => None,
+        }
+    }
+}
+
+const BULLET: &str = "- ";
+
+// Elements rendered on lines of their own
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+fn render(node: &NodeRef, entities: Entities, text: &mut String) {
+    if let Some(content) = node.as_text() {
+        let content = content.borrow();
+        // With --entities encode the cleaning escaped the text once more, otherwise the text is
+        // already as it should read
+        let content = if entities == Entities::Encode {
+            html_escape::decode_html_entities(&*content)
+        } else {
+            Cow::Borrowed(content.as_str())
+        };
+        // The cleaning marked the newlines with tildes; tabs are reserved for separating table
+        // cells
+        text.push_str(&content.replace(|c| c == '~' || c == '\t', " "));
+        return;
+    }
+    let element = match node.as_element() {
+        Some(element) => element,
+        None => {
+            for child in node.children() {
+                render(&child, entities, text);
+            }
+            return;
+        }
+    };
+    match &*element.name.local {
+        "br" => {
+            text.push('\n');
+            return;
+        }
+        "tr" => {
+            let cells = node
+                .children()
+                .filter(|cell| {
+                    cell.as_element().map_or(false, |cell| {
+                        &*cell.name.local == "td" || &*cell.name.local == "th"
+                    })
+                })
+                .map(|cell| {
+                    let mut cell_text = String::new();
+                    render(&cell, entities, &mut cell_text);
+                    cell_text
+                        .split_whitespace()
+                        .collect::<Vec<&str>>()
+                        .join(" ")
+                })
+                .collect::<Vec<String>>();
+            break_line(text);
+            text.push_str(&cells.join("\t"));
+            text.push('\n');
+            return;
+        }
+        "li" => {
+            break_line(text);
+            text.push_str(BULLET);
+            for child in node.children() {
+                render(&child, entities, text);
+            }
+            text.push('\n');
+            return;
+        }
+        _ => {}
+    }
+    let is_block = BLOCK_ELEMENTS.contains(&&*element.name.local)
+        || element.attributes.borrow().contains("itemprop");
+    if is_block {
+        break_line(text);
+    }
+    for child in node.children() {
+        render(&child, entities, text);
+    }
+    if is_block {
+        text.push('\n');
+    }
+}
+
+// Start a new line, unless the text is at the start of a line or of a list item already, so
+// blocks inside list items (<li><p>...</p></li>) stay on the line of their bullet
+fn break_line(text: &mut String) {
+    let line = text.rsplit('\n').next().unwrap_or("");
+    if !line.trim().is_empty() && line.trim() != BULLET.trim() {
+        text.push('\n');
+    }
+}
+
+// Whitespace collapsed within the cells of a line
+fn collapse_line(line: &str) -> String {
+    line.split('\t')
+        .map(|cell| cell.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .collect::<Vec<String>>()
+        .join("\t")
+}
+
+// Normalized text of a cleaned Question, one non-empty line per property, block, list item, table
+// row or line break
+pub(crate) fn to_text(question: &NodeRef, entities: Entities) -> String {
+    let mut text = String::new();
+    render(question, entities, &mut text);
+    text.lines()
+        .map(collapse_line)
+        .filter(|line| !line.trim().is_empty() && line.trim() != BULLET.trim())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use kuchiki::traits::*;
+
+    use super::*;
+
+    #[test]
+    fn decodes_only_encoded_text() {
+        let document = kuchiki::parse_html().one("<p>AT&amp;amp;T &amp;lt;b&amp;gt;</p>");
+        assert_eq!(to_text(&document, Entities::Encode), "AT&T <b>");
+        assert_eq!(to_text(&document, Entities::Preserve), "AT&amp;T &lt;b&gt;");
+    }
+}
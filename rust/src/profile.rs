@@ -0,0 +1,66 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Cleaning profiles deciding which markup survives inside the Question properties. The default
+// keeps the schema.org skeleton for LM pre-training; the display profile additionally keeps
+// semantic formatting so the answers can be shown to humans.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CleaningProfile {
+    // Only schema.org related markup
+    Pretraining,
+    // Semantic formatting (bold, lists, code, links) on top of the schema.org markup
+    Display,
+}
+
+impl CleaningProfile {
+    pub fn from_name(name: &str) -> Option<CleaningProfile> {
+        match name {
+            "pretraining" => Some(CleaningProfile::Pretraining),
+            "display" => Some(CleaningProfile::Display),
+            _ => None,
+        }
+    }
+}
+
+// Formatting elements kept by the display profile
+const DISPLAY_ELEMENTS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "em",
+    "i",
+    "kbd",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "strong",
+    "sub",
+    "sup",
+    "ul",
+];
+
+// Elements removed together with their content by the display profile
+const UNSAFE_ELEMENTS: &[&str] = &[
+    "embed", "form", "iframe", "noscript", "object", "script", "style", "template",
+];
+
+pub(crate) fn is_display_element(name: &str) -> bool {
+    DISPLAY_ELEMENTS.contains(&name)
+}
+
+pub(crate) fn is_unsafe_element(name: &str) -> bool {
+    UNSAFE_ELEMENTS.contains(&name)
+}
+
+// Only plain web links are kept, no javascript: or data: URLs
+pub(crate) fn is_safe_href(href: &str) -> bool {
+    let href = href.trim_start().to_lowercase();
+    href.starts_with("https://") || href.starts_with("http://")
+}
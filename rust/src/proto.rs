@@ -0,0 +1,252 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Protobuf representation of HTMLMinified records, mirroring proto/ccqa.proto. The messages are
+// declared by hand rather than generated, so building doesn't need protoc. Keep both in sync.
+
+use crate::confidence::ExtractionConfidence;
+use crate::qa::{AnswerStatus, StructuredAnswer, StructuredComment, StructuredQuestion};
+use crate::HTMLMinified;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub(crate) enum ProtoExtractionConfidence {
+    Low = 0,
+    Medium = 1,
+    High = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub(crate) enum ProtoAnswerStatus {
+    Unknown = 0,
+    Accepted = 1,
+    Suggested = 2,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct ProtoStructuredAnswer {
+    #[prost(string, tag = "1")]
+    pub(crate) text: String,
+    #[prost(int64, optional, tag = "2")]
+    pub(crate) upvote_count: Option<i64>,
+    #[prost(int64, optional, tag = "3")]
+    pub(crate) downvote_count: Option<i64>,
+    #[prost(string, optional, tag = "4")]
+    pub(crate) date_created: Option<String>,
+    #[prost(bool, tag = "5")]
+    pub(crate) accepted: bool,
+    #[prost(enumeration = "ProtoAnswerStatus", tag = "6")]
+    pub(crate) status: i32,
+    #[prost(string, optional, tag = "7")]
+    pub(crate) date_published: Option<String>,
+    #[prost(string, optional, tag = "8")]
+    pub(crate) date_modified: Option<String>,
+    #[prost(string, optional, tag = "9")]
+    pub(crate) author: Option<String>,
+    #[prost(message, repeated, tag = "10")]
+    pub(crate) comments: Vec<ProtoStructuredComment>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct ProtoStructuredComment {
+    #[prost(string, tag = "1")]
+    pub(crate) text: String,
+    #[prost(int64, optional, tag = "2")]
+    pub(crate) upvote_count: Option<i64>,
+    #[prost(string, optional, tag = "3")]
+    pub(crate) date_created: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub(crate) author: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct ProtoStructuredQuestion {
+    #[prost(string, optional, tag = "1")]
+    pub(crate) name: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub(crate) text: Option<String>,
+    #[prost(int64, optional, tag = "3")]
+    pub(crate) upvote_count: Option<i64>,
+    #[prost(int64, optional, tag = "4")]
+    pub(crate) downvote_count: Option<i64>,
+    #[prost(string, optional, tag = "5")]
+    pub(crate) date_created: Option<String>,
+    #[prost(message, repeated, tag = "6")]
+    pub(crate) answers: Vec<ProtoStructuredAnswer>,
+    #[prost(string, optional, tag = "7")]
+    pub(crate) date_published: Option<String>,
+    #[prost(string, optional, tag = "8")]
+    pub(crate) date_modified: Option<String>,
+    #[prost(string, optional, tag = "9")]
+    pub(crate) author: Option<String>,
+    #[prost(int64, optional, tag = "10")]
+    pub(crate) answer_count: Option<i64>,
+    #[prost(int64, optional, tag = "11")]
+    pub(crate) view_count: Option<i64>,
+    #[prost(int64, optional, tag = "12")]
+    pub(crate) comment_count: Option<i64>,
+    #[prost(string, repeated, tag = "13")]
+    pub(crate) tags: Vec<String>,
+    #[prost(uint64, optional, tag = "14")]
+    pub(crate) best_answer_index: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct MinifiedRecord {
+    #[prost(string, tag = "1")]
+    pub(crate) id: String,
+    #[prost(string, tag = "2")]
+    pub(crate) mhtml: String,
+    #[prost(string, tag = "3")]
+    pub(crate) language: String,
+    #[prost(string, optional, tag = "4")]
+    pub(crate) uri: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub(crate) ip_address: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub(crate) metadata_json: Option<String>,
+    #[prost(string, repeated, tag = "7")]
+    pub(crate) context: Vec<String>,
+    #[prost(bool, tag = "8")]
+    pub(crate) has_bidi_controls: bool,
+    #[prost(enumeration = "ProtoExtractionConfidence", tag = "9")]
+    pub(crate) extraction_confidence: i32,
+    #[prost(string, tag = "10")]
+    pub(crate) config_hash: String,
+    #[prost(string, optional, tag = "11")]
+    pub(crate) network_json: Option<String>,
+    #[prost(string, optional, tag = "12")]
+    pub(crate) crawl_date: Option<String>,
+    #[prost(string, optional, tag = "13")]
+    pub(crate) record_id: Option<String>,
+    #[prost(string, optional, tag = "14")]
+    pub(crate) payload_digest: Option<String>,
+    #[prost(string, optional, tag = "15")]
+    pub(crate) title: Option<String>,
+    #[prost(string, repeated, tag = "16")]
+    pub(crate) questions: Vec<String>,
+    #[prost(uint64, tag = "17")]
+    pub(crate) num_questions: u64,
+    #[prost(string, tag = "18")]
+    pub(crate) content_hash: String,
+    #[prost(string, repeated, tag = "19")]
+    pub(crate) raw_questions: Vec<String>,
+    #[prost(message, repeated, tag = "20")]
+    pub(crate) structured: Vec<ProtoStructuredQuestion>,
+    #[prost(string, optional, tag = "21")]
+    pub(crate) canonical_uri: Option<String>,
+    #[prost(string, optional, tag = "22")]
+    pub(crate) og_title: Option<String>,
+    #[prost(string, optional, tag = "23")]
+    pub(crate) og_description: Option<String>,
+    #[prost(string, optional, tag = "24")]
+    pub(crate) meta_description: Option<String>,
+    #[prost(string, repeated, tag = "25")]
+    pub(crate) tags: Vec<String>,
+    #[prost(string, optional, tag = "26")]
+    pub(crate) detected_language: Option<String>,
+    #[prost(double, optional, tag = "27")]
+    pub(crate) detected_language_confidence: Option<f64>,
+}
+
+impl From<&StructuredAnswer> for ProtoStructuredAnswer {
+    fn from(answer: &StructuredAnswer) -> Self {
+        ProtoStructuredAnswer {
+            text: answer.text.clone(),
+            upvote_count: answer.upvote_count,
+            downvote_count: answer.downvote_count,
+            date_created: answer.date_created.clone(),
+            accepted: answer.accepted,
+            status: match answer.status {
+                AnswerStatus::Unknown => ProtoAnswerStatus::Unknown,
+                AnswerStatus::Accepted => ProtoAnswerStatus::Accepted,
+                AnswerStatus::Suggested => ProtoAnswerStatus::Suggested,
+            } as i32,
+            date_published: answer.date_published.clone(),
+            date_modified: answer.date_modified.clone(),
+            author: answer.author.clone(),
+            comments: answer.comments.iter().map(From::from).collect(),
+        }
+    }
+}
+
+impl From<&StructuredComment> for ProtoStructuredComment {
+    fn from(comment: &StructuredComment) -> Self {
+        ProtoStructuredComment {
+            text: comment.text.clone(),
+            upvote_count: comment.upvote_count,
+            date_created: comment.date_created.clone(),
+            author: comment.author.clone(),
+        }
+    }
+}
+
+impl From<&StructuredQuestion> for ProtoStructuredQuestion {
+    fn from(question: &StructuredQuestion) -> Self {
+        ProtoStructuredQuestion {
+            name: question.name.clone(),
+            text: question.text.clone(),
+            upvote_count: question.upvote_count,
+            downvote_count: question.downvote_count,
+            date_created: question.date_created.clone(),
+            answers: question.answers.iter().map(From::from).collect(),
+            date_published: question.date_published.clone(),
+            date_modified: question.date_modified.clone(),
+            author: question.author.clone(),
+            answer_count: question.answer_count,
+            view_count: question.view_count,
+            comment_count: question.comment_count,
+            tags: question.tags.clone(),
+            best_answer_index: question.best_answer_index.map(|index| index as u64),
+        }
+    }
+}
+
+impl From<&HTMLMinified> for MinifiedRecord {
+    fn from(record: &HTMLMinified) -> Self {
+        let extraction_confidence = match record.extraction_confidence {
+            ExtractionConfidence::Low => ProtoExtractionConfidence::Low,
+            ExtractionConfidence::Medium => ProtoExtractionConfidence::Medium,
+            ExtractionConfidence::High => ProtoExtractionConfidence::High,
+        };
+        MinifiedRecord {
+            id: record.id.clone(),
+            mhtml: record.mhtml.clone(),
+            language: record.language.clone(),
+            uri: record.uri.clone(),
+            ip_address: record.ip_address.clone(),
+            metadata_json: record
+                .metadata
+                .as_ref()
+                .map(|metadata| serde_json::Value::Object(metadata.clone()).to_string()),
+            context: record.context.clone(),
+            has_bidi_controls: record.has_bidi_controls,
+            extraction_confidence: extraction_confidence as i32,
+            config_hash: record.config_hash.clone(),
+            network_json: record
+                .network
+                .as_ref()
+                .map(|network| serde_json::to_string(network).unwrap()),
+            crawl_date: record.crawl_date.clone(),
+            record_id: record.record_id.clone(),
+            payload_digest: record.payload_digest.clone(),
+            title: record.title.clone(),
+            questions: record.questions.clone(),
+            num_questions: record.num_questions as u64,
+            content_hash: record.content_hash.clone(),
+            raw_questions: record.raw_questions.clone(),
+            structured: record.structured.iter().map(From::from).collect(),
+            canonical_uri: record.canonical_uri.clone(),
+            og_title: record.og_title.clone(),
+            og_description: record.og_description.clone(),
+            meta_description: record.meta_description.clone(),
+            tags: record.tags.clone(),
+            detected_language: record.detected_language.clone(),
+            detected_language_confidence: record.detected_language_confidence,
+        }
+    }
+}
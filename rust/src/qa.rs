@@ -0,0 +1,826 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Question/answer pairs read back from minified records, shared by the training format
+// generation subcommands. This mirrors what `mhtml_to_json.py` collects, limited to the parts the
+// training formats need: the question name and text, and every answer with its status and votes.
+//
+// The same parsing also turns the cleaned Questions into typed records during the WARC
+// processing (--structured), so consumers don't need to parse the minified HTML at all.
+
+use std::path::Path;
+
+use kuchiki::traits::*;
+use kuchiki::NodeRef;
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::microdata::{attribute, has_itemprop, is_item};
+use crate::{dates, output};
+use crate::{emptyspaces, HTMLMinified};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AnswerStatus {
+    Accepted,
+    Suggested,
+    // Answer itemtype without an acceptedAnswer or suggestedAnswer itemprop
+    Unknown,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Answer {
+    pub(crate) status: AnswerStatus,
+    pub(crate) text: String,
+    pub(crate) upvote_count: Option<i64>,
+    // Starts with a reference to something outside of the answer (see has_back_reference)
+    pub(crate) back_reference: bool,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Question {
+    // Name and text of the question, space separated
+    pub(crate) text: String,
+    // Name (title) and text (body) on their own, empty if missing
+    pub(crate) name: String,
+    pub(crate) body: String,
+    // Answers in page order, only those with a non-empty text
+    pub(crate) answers: Vec<Answer>,
+}
+
+// The Questions of one record, with what identifies the record
+#[derive(Clone, Debug)]
+pub(crate) struct RecordQuestions {
+    pub(crate) id: String,
+    pub(crate) uri: Option<String>,
+    pub(crate) language: String,
+    pub(crate) questions: Vec<Question>,
+}
+
+// An answer of a Question as written with --structured
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct StructuredAnswer {
+    pub(crate) text: String,
+    #[serde(default)]
+    pub(crate) upvote_count: Option<i64>,
+    #[serde(default)]
+    pub(crate) downvote_count: Option<i64>,
+    // dateCreated, datePublished and dateModified in ISO-8601 (see dates.rs)
+    #[serde(default)]
+    pub(crate) date_created: Option<String>,
+    #[serde(default)]
+    pub(crate) date_published: Option<String>,
+    #[serde(default)]
+    pub(crate) date_modified: Option<String>,
+    // Marked up as acceptedAnswer
+    #[serde(default)]
+    pub(crate) accepted: bool,
+    // Container the answer came from: acceptedAnswer, suggestedAnswer or neither
+    #[serde(default = "unknown_status")]
+    pub(crate) status: AnswerStatus,
+    // Name of the author, or its SHA-256 (--authors)
+    #[serde(default)]
+    pub(crate) author: Option<String>,
+    // Comments marked up beneath the answer in page order, replies included (--comments)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) comments: Vec<StructuredComment>,
+}
+
+// A schema.org Comment of an answer
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct StructuredComment {
+    pub(crate) text: String,
+    #[serde(default)]
+    pub(crate) upvote_count: Option<i64>,
+    #[serde(default)]
+    pub(crate) date_created: Option<String>,
+    #[serde(default)]
+    pub(crate) author: Option<String>,
+}
+
+fn unknown_status() -> AnswerStatus {
+    AnswerStatus::Unknown
+}
+
+// A cleaned Question as written with --structured
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct StructuredQuestion {
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+    #[serde(default)]
+    pub(crate) text: Option<String>,
+    #[serde(default)]
+    pub(crate) upvote_count: Option<i64>,
+    #[serde(default)]
+    pub(crate) downvote_count: Option<i64>,
+    // dateCreated, datePublished and dateModified in ISO-8601 (see dates.rs)
+    #[serde(default)]
+    pub(crate) date_created: Option<String>,
+    #[serde(default)]
+    pub(crate) date_published: Option<String>,
+    #[serde(default)]
+    pub(crate) date_modified: Option<String>,
+    // Name of the author, or its SHA-256 (--authors)
+    #[serde(default)]
+    pub(crate) author: Option<String>,
+    // The answerCount property, which may count answers the page doesn't show
+    #[serde(default)]
+    pub(crate) answer_count: Option<i64>,
+    // Popularity from the interactionStatistic counters (UserPageVisits / ViewAction), and
+    // UserComments / CommentAction or the commentCount property
+    #[serde(default)]
+    pub(crate) view_count: Option<i64>,
+    #[serde(default)]
+    pub(crate) comment_count: Option<i64>,
+    // Topics of the question from its keywords property or tag-list markup, lowercased
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) tags: Vec<String>,
+    // Answers in page order, only those with a non-empty text
+    #[serde(default)]
+    pub(crate) answers: Vec<StructuredAnswer>,
+    // Position in answers of the answer `--answers best` trains on (see best_answer)
+    #[serde(default)]
+    pub(crate) best_answer_index: Option<usize>,
+}
+
+// Which part of a question becomes the training source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum QuestionPart {
+    // The title only
+    Name,
+    // The body only
+    Text,
+    Both,
+}
+
+impl QuestionPart {
+    pub(crate) fn from_name(name: &str) -> Option<QuestionPart> {
+        match name {
+            "name" => Some(QuestionPart::Name),
+            "text" => Some(QuestionPart::Text),
+            "both" => Some(QuestionPart::Both),
+            _ => None,
+        }
+    }
+}
+
+impl Question {
+    // The selected part, empty if the question doesn't have it
+    pub(crate) fn part(&self, part: QuestionPart) -> &str {
+        match part {
+            QuestionPart::Name => &self.name,
+            QuestionPart::Text => &self.body,
+            QuestionPart::Both => &self.text,
+        }
+    }
+}
+
+// Which answers of a question become training targets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AnswerSelection {
+    // Every answer, as `closed_book_processing.py` does
+    All,
+    // The answer at the best_answer_index: the first accepted one, otherwise the most upvoted one,
+    // otherwise the first one
+    Best,
+}
+
+impl AnswerSelection {
+    pub(crate) fn from_name(name: &str) -> Option<AnswerSelection> {
+        match name {
+            "all" => Some(AnswerSelection::All),
+            "best" => Some(AnswerSelection::Best),
+            _ => None,
+        }
+    }
+}
+
+// What --structured keeps of the author of questions and answers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AuthorPolicy {
+    Keep,
+    // SHA-256 of the name, still good for per-author dedup and spam filtering
+    Hash,
+    Drop,
+}
+
+impl AuthorPolicy {
+    pub(crate) fn from_name(name: &str) -> Option<AuthorPolicy> {
+        match name {
+            "keep" => Some(AuthorPolicy::Keep),
+            "hash" => Some(AuthorPolicy::Hash),
+            "drop" => Some(AuthorPolicy::Drop),
+            _ => None,
+        }
+    }
+}
+
+// Options common to the training format subcommands
+#[derive(Clone, Debug)]
+pub(crate) struct QaOptions {
+    // Keep the HTML markup of the texts instead of only their text nodes
+    pub(crate) keep_markup: bool,
+    // Only read records of this language (primary subtag of the lang attribute, e.g. "en")
+    pub(crate) language: Option<String>,
+}
+
+// First node with the itemprop below node, not descending into nested items (Answers, authors),
+// which hold properties of their own
+fn find_itemprop(node: &NodeRef, prop: &str) -> Option<NodeRef> {
+    for child in node.children() {
+        if has_itemprop(&child, prop) {
+            return Some(child);
+        }
+        if is_item(&child, "/Answer") || attribute(&child, "itemscope").is_some() {
+            continue;
+        }
+        if let Some(found) = find_itemprop(&child, prop) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+// Text of an itemprop node. The minified text is HTML escaped once more than the page was, which
+// is undone here like `html.unescape` does in Python.
+fn node_text(node: &NodeRef, keep_markup: bool) -> String {
+    let text = if keep_markup {
+        node.children()
+            .map(|child| child.to_string())
+            .collect::<String>()
+    } else {
+        node.inclusive_descendants()
+            .text_nodes()
+            .map(|text| text.borrow().clone())
+            .collect::<Vec<String>>()
+            .join(" ")
+    };
+    let text = html_escape::decode_html_entities(&text)
+        .replace("\n", "")
+        .replace("\r", "");
+    emptyspaces(text).trim().to_string()
+}
+
+// Abbreviated magnitudes written after counts, e.g. "1.2k", "3 Mio.", "2万", longest first. Kept
+// in sync with COUNT_SUFFIXES of python/mhtml_to_json.py, whose parse_count this mirrors.
+const COUNT_SUFFIXES: [(&str, f64); 11] = [
+    ("tsd", 1e3),
+    ("mil", 1e3),
+    ("mio", 1e6),
+    ("mln", 1e6),
+    ("mrd", 1e9),
+    ("k", 1e3),
+    ("千", 1e3),
+    ("万", 1e4),
+    ("萬", 1e4),
+    ("m", 1e6),
+    ("b", 1e9),
+];
+
+// Zeros of the runs of Unicode decimal digits (general category Nd), each followed by one to nine
+const DECIMAL_ZEROS: [u32; 66] = [
+    0x0030, 0x0660, 0x06F0, 0x07C0, 0x0966, 0x09E6, 0x0A66, 0x0AE6, 0x0B66, 0x0BE6, 0x0C66, 0x0CE6,
+    0x0D66, 0x0DE6, 0x0E50, 0x0ED0, 0x0F20, 0x1040, 0x1090, 0x17E0, 0x1810, 0x1946, 0x19D0, 0x1A80,
+    0x1A90, 0x1B50, 0x1BB0, 0x1C40, 0x1C50, 0xA620, 0xA8D0, 0xA900, 0xA9D0, 0xA9F0, 0xAA50, 0xABF0,
+    0xFF10, 0x104A0, 0x10D30, 0x11066, 0x110F0, 0x11136, 0x111D0, 0x112F0, 0x11450, 0x114D0,
+    0x11650, 0x116C0, 0x11730, 0x118E0, 0x11950, 0x11C50, 0x11D50, 0x11DA0, 0x16A60, 0x16AC0,
+    0x16B50, 0x1D7CE, 0x1D7D8, 0x1D7E2, 0x1D7EC, 0x1D7F6, 0x1E140, 0x1E2F0, 0x1E950, 0x1FBF0,
+];
+
+fn decimal_digit(c: char) -> Option<char> {
+    let code = c as u32;
+    DECIMAL_ZEROS
+        .iter()
+        .find(|zero| (**zero..**zero + 10).contains(&code))
+        .and_then(|zero| std::char::from_digit(code - zero, 10))
+}
+
+// Count as displayed on the page, e.g. "12", "1,234", "1.234", "-3", "1,2 k" or "١٢٣", None if it
+// isn't one
+pub(crate) fn parse_count(value: &str) -> Option<i64> {
+    lazy_static! {
+        static ref NUMBER: Regex = Regex::new(r"([-+]?)([0-9][0-9.,'\s]*)").unwrap();
+    }
+    let normalized = value
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            // Arabic decimal and thousands separators
+            '\u{066B}' => '.',
+            '\u{066C}' => ',',
+            c if c.is_whitespace() => ' ',
+            c => decimal_digit(c).unwrap_or(c),
+        })
+        .collect::<String>();
+    let captures = NUMBER.captures(&normalized)?;
+    // A magnitude only counts as one when no other word follows it, "5 km" isn't 5000
+    let rest = normalized[captures.get(0)?.end()..].trim_start();
+    let suffix = COUNT_SUFFIXES.iter().find(|(suffix, _)| {
+        rest.strip_prefix(*suffix).map_or(false, |after| {
+            let after = after.strip_prefix('.').unwrap_or(after);
+            !after.starts_with(|c: char| c.is_ascii_lowercase())
+        })
+    });
+    let number = captures[2]
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '\'')
+        .collect::<String>();
+    let number = number.trim_end_matches(|c| c == '.' || c == ',');
+    let separators = number
+        .chars()
+        .filter(|c| *c == '.' || *c == ',')
+        .collect::<Vec<char>>();
+    let number = match separators.as_slice() {
+        [] => number.to_string(),
+        [separator] => {
+            let (integer, fraction) = number.split_at(number.find(*separator)?);
+            let fraction = &fraction[1..];
+            // "1,234" groups thousands, "1,2" (or "1,234k") has a decimal part
+            if fraction.len() == 3 && suffix.is_none() {
+                format!("{}{}", integer, fraction)
+            } else {
+                format!("{}.{}", integer, fraction)
+            }
+        }
+        [.., decimal] if separators.iter().any(|separator| separator != decimal) => {
+            // The last separator is the decimal one, the other groups thousands
+            let thousands = if *decimal == '.' { ',' } else { '.' };
+            number.replace(thousands, "").replace(*decimal, ".")
+        }
+        [separator, ..] => number.replace(*separator, ""),
+    };
+    let magnitude = suffix.map_or(1.0, |(_, magnitude)| *magnitude);
+    // Halves round up, as in Python
+    let count = (number.parse::<f64>().ok()? * magnitude + 0.5).floor() as i64;
+    if &captures[1] == "-" {
+        Some(-count)
+    } else {
+        Some(count)
+    }
+}
+
+// Lightweight heuristic for answers opening with a reference they don't resolve themselves
+// ("It is", "Yes.", "As above"), which make no sense as closed-book targets without the
+// question's context. English only, other languages are never flagged.
+pub(crate) fn has_back_reference(text: &str) -> bool {
+    lazy_static! {
+        static ref BACK_REFERENCE: Regex = Regex::new(
+            r"(?i)^\W*(it(\s+is|'s|\s+was|\s+does|\s+doesn't|\s+depends)\b|(yes|no|yeah|yep|nope|correct|exactly|agreed|same|this|that)\s*[.!,]|(as|see|like)\s+(above|below|mentioned|said|stated|others\s+(have\s+)?said)\b|same\s+(here|problem|issue)\b|(the\s+)?(above|previous|former|latter)\s+(answer|post|comment|reply)\b|this\s+(works|worked|helped|is\s+correct)\b|thanks?\b|thank\s+you\b|\+1\b)"
+        )
+        .unwrap();
+    }
+    BACK_REFERENCE.is_match(text)
+}
+
+// Count of a property from its content attribute, otherwise (or if the attribute holds no
+// number, e.g. content="votes") from its displayed text
+fn find_count(node: &NodeRef, prop: &str) -> Option<i64> {
+    let count_node = find_itemprop(node, prop)?;
+    attribute(&count_node, "content")
+        .and_then(|content| parse_count(&content))
+        .or_else(|| parse_count(&count_node.text_contents()))
+}
+
+// Text of a property, None if missing or empty
+fn find_text(node: &NodeRef, prop: &str) -> Option<String> {
+    let text = node_text(&find_itemprop(node, prop)?, false);
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+// Date of a property in ISO-8601, from its machine readable datetime or content attribute,
+// otherwise from its displayed text
+fn find_date(node: &NodeRef, prop: &str) -> Option<String> {
+    let date_node = find_itemprop(node, prop)?;
+    ["datetime", "content"]
+        .iter()
+        .filter_map(|name| attribute(&date_node, name))
+        .find_map(|date| dates::normalize_date(&date))
+        .or_else(|| dates::normalize_date(&node_text(&date_node, false)))
+}
+
+fn answer_status(node: &NodeRef) -> AnswerStatus {
+    if has_itemprop(node, "acceptedAnswer") {
+        AnswerStatus::Accepted
+    } else if has_itemprop(node, "suggestedAnswer") {
+        AnswerStatus::Suggested
+    } else {
+        AnswerStatus::Unknown
+    }
+}
+
+fn is_answer(node: &NodeRef) -> bool {
+    is_item(node, "/Answer")
+        || has_itemprop(node, "acceptedAnswer")
+        || has_itemprop(node, "suggestedAnswer")
+}
+
+fn collect_answer(node: &NodeRef, keep_markup: bool) -> Option<Answer> {
+    let text = node_text(&find_itemprop(node, "text")?, keep_markup);
+    if text.is_empty() {
+        return None;
+    }
+    Some(Answer {
+        status: answer_status(node),
+        back_reference: has_back_reference(&text),
+        text,
+        upvote_count: find_count(node, "upvoteCount"),
+    })
+}
+
+fn collect_question(question: &NodeRef, keep_markup: bool) -> Option<Question> {
+    let part = |prop: &str| {
+        find_itemprop(question, prop).map_or_else(String::new, |node| node_text(&node, keep_markup))
+    };
+    let (name, body) = (part("name"), part("text"));
+    let text = [name.as_str(), body.as_str()]
+        .iter()
+        .filter(|text| !text.is_empty())
+        .cloned()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if text.is_empty() {
+        return None;
+    }
+    let answers = question
+        .descendants()
+        .filter(is_answer)
+        .filter_map(|node| collect_answer(&node, keep_markup))
+        .collect();
+    Some(Question {
+        text,
+        name,
+        body,
+        answers,
+    })
+}
+
+// Name of the author of a question or answer: the name of an author item (Person,
+// Organization), otherwise the text of the author property
+fn find_author(node: &NodeRef, authors: AuthorPolicy) -> Option<String> {
+    if authors == AuthorPolicy::Drop {
+        return None;
+    }
+    let author = find_itemprop(node, "author")?;
+    let name = if attribute(&author, "itemscope").is_some() {
+        find_text(&author, "name")?
+    } else {
+        Some(node_text(&author, false)).filter(|name| !name.is_empty())?
+    };
+    match authors {
+        AuthorPolicy::Hash => Some(format!("{:x}", Sha256::digest(name.as_bytes()))),
+        _ => Some(name),
+    }
+}
+
+// interactionType values of the InteractionCounter items counting views and comments, both the
+// legacy types and the actions replacing them
+const VIEW_INTERACTIONS: [&str; 3] = ["UserPageVisits", "ViewAction", "WatchAction"];
+const COMMENT_INTERACTIONS: [&str; 2] = ["UserComments", "CommentAction"];
+
+// userInteractionCount of the item's first interactionStatistic counter of one of the given
+// interaction types. The type is a URL (link or meta content) or a bare name.
+fn find_interaction_count(node: &NodeRef, interactions: &[&str]) -> Option<i64> {
+    node.descendants()
+        .filter(|statistic| has_itemprop(statistic, "interactionStatistic"))
+        .filter(|statistic| {
+            statistic
+                .ancestors()
+                .find(|ancestor| attribute(ancestor, "itemscope").is_some())
+                .as_ref()
+                == Some(node)
+        })
+        .find_map(|statistic| {
+            let type_node = find_itemprop(&statistic, "interactionType")?;
+            let interaction = attribute(&type_node, "content")
+                .unwrap_or_else(|| type_node.text_contents())
+                .trim()
+                .trim_end_matches('/')
+                .to_string();
+            let matches = interactions.iter().any(|name| {
+                interaction == *name || interaction.ends_with(&format!("schema.org/{}", name))
+            });
+            if matches {
+                find_count(&statistic, "userInteractionCount")
+            } else {
+                None
+            }
+        })
+}
+
+// Longer "tags" are sentences rather than topics
+const MAX_TAG_LENGTH: usize = 64;
+
+// Classes of the links of common forum tag lists
+const TAG_CLASSES: [&str; 3] = ["tag", "post-tag", "question-tag"];
+
+fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = emptyspaces(tag.to_string())
+        .trim()
+        .trim_start_matches('#')
+        .trim()
+        .to_lowercase();
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH {
+        None
+    } else {
+        Some(tag)
+    }
+}
+
+fn is_tag_link(node: &NodeRef) -> bool {
+    let has_token = |name: &str, tokens: &[&str]| {
+        attribute(node, name).map_or(false, |value| {
+            value.split_whitespace().any(|token| {
+                tokens
+                    .iter()
+                    .any(|wanted| token.eq_ignore_ascii_case(wanted))
+            })
+        })
+    };
+    has_token("rel", &["tag"]) || has_token("class", &TAG_CLASSES)
+}
+
+// Tags of an uncleaned Question subtree, in page order without duplicates: the comma separated
+// keywords property (content attribute, its child elements or its text), otherwise the links of
+// a tag list (rel="tag", class="post-tag", ...). Tags of the answers aren't the question's.
+pub(crate) fn find_tags(question: &NodeRef) -> Vec<String> {
+    let own = |node: &NodeRef| {
+        node.ancestors()
+            .take_while(|ancestor| ancestor != question)
+            .all(|ancestor| !is_answer(&ancestor))
+    };
+    let mut tags = Vec::new();
+    for keywords in question
+        .descendants()
+        .filter(|node| has_itemprop(node, "keywords") && own(node))
+    {
+        if let Some(content) = attribute(&keywords, "content") {
+            tags.extend(content.split(',').map(String::from));
+            continue;
+        }
+        let children = keywords
+            .children()
+            .filter(|child| child.as_element().is_some())
+            .collect::<Vec<NodeRef>>();
+        if children.len() > 1 {
+            tags.extend(children.iter().map(|child| child.text_contents()));
+        } else {
+            tags.extend(keywords.text_contents().split(',').map(String::from));
+        }
+    }
+    if tags.is_empty() {
+        tags.extend(
+            question
+                .descendants()
+                .filter(|node| is_tag_link(node) && own(node))
+                .map(|link| link.text_contents()),
+        );
+    }
+    let mut unique = Vec::new();
+    for tag in tags.iter().filter_map(|tag| normalize_tag(tag)) {
+        if !unique.contains(&tag) {
+            unique.push(tag);
+        }
+    }
+    unique
+}
+
+fn is_comment(node: &NodeRef) -> bool {
+    is_item(node, "/Comment")
+        || (has_itemprop(node, "comment") && attribute(node, "itemscope").is_some())
+}
+
+// Comments whose closest enclosing answer is the given one, in document order
+fn collect_comments(answer: &NodeRef, authors: AuthorPolicy) -> Vec<StructuredComment> {
+    answer
+        .descendants()
+        .filter(is_comment)
+        .filter(|comment| comment.ancestors().find(is_answer).as_ref() == Some(answer))
+        .filter_map(|comment| {
+            Some(StructuredComment {
+                text: find_text(&comment, "text")?,
+                upvote_count: find_count(&comment, "upvoteCount"),
+                date_created: find_date(&comment, "dateCreated"),
+                author: find_author(&comment, authors),
+            })
+        })
+        .collect()
+}
+
+// Typed representation of a cleaned Question subtree, with the comments of its answers if asked
+pub(crate) fn structure_question(
+    question: &NodeRef,
+    authors: AuthorPolicy,
+    comments: bool,
+) -> StructuredQuestion {
+    let answers = question
+        .descendants()
+        .filter(is_answer)
+        .filter_map(|node| {
+            Some(StructuredAnswer {
+                text: find_text(&node, "text")?,
+                upvote_count: find_count(&node, "upvoteCount"),
+                downvote_count: find_count(&node, "downvoteCount"),
+                date_created: find_date(&node, "dateCreated"),
+                date_published: find_date(&node, "datePublished"),
+                date_modified: find_date(&node, "dateModified"),
+                accepted: has_itemprop(&node, "acceptedAnswer"),
+                status: answer_status(&node),
+                author: find_author(&node, authors),
+                comments: if comments {
+                    collect_comments(&node, authors)
+                } else {
+                    Vec::new()
+                },
+            })
+        })
+        .collect();
+    StructuredQuestion {
+        name: find_text(question, "name"),
+        text: find_text(question, "text"),
+        upvote_count: find_count(question, "upvoteCount"),
+        downvote_count: find_count(question, "downvoteCount"),
+        date_created: find_date(question, "dateCreated"),
+        date_published: find_date(question, "datePublished"),
+        date_modified: find_date(question, "dateModified"),
+        author: find_author(question, authors),
+        answer_count: find_count(question, "answerCount"),
+        view_count: find_interaction_count(question, &VIEW_INTERACTIONS),
+        comment_count: find_count(question, "commentCount")
+            .or_else(|| find_interaction_count(question, &COMMENT_INTERACTIONS)),
+        // Tag-list markup doesn't survive the cleaning, the extraction collects them beforehand
+        tags: Vec::new(),
+        best_answer_index: best_answer(
+            &answers,
+            |answer| answer.accepted,
+            |answer| answer.upvote_count,
+        ),
+        answers,
+    }
+}
+
+// Number of answers of a cleaned Question subtree with a non-empty text, as in structure_question
+pub(crate) fn count_answers(question: &NodeRef) -> usize {
+    question
+        .descendants()
+        .filter(is_answer)
+        .filter(|node| find_text(node, "text").is_some())
+        .count()
+}
+
+// Parse minified HTML into its Questions, skipping those with neither a name nor a text
+pub(crate) fn parse_questions(html: &str, keep_markup: bool) -> Vec<Question> {
+    let document = kuchiki::parse_html().one(html);
+    let mut nodes = document
+        .descendants()
+        .filter(|node| is_item(node, "/Question"))
+        .collect::<Vec<NodeRef>>();
+    if nodes.is_empty() {
+        nodes.push(document);
+    }
+    nodes
+        .iter()
+        .filter_map(|node| collect_question(node, keep_markup))
+        .collect()
+}
+
+pub(crate) fn matches_language(record: &HTMLMinified, language: Option<&str>) -> bool {
+    match language {
+        Some(language) => record
+            .language
+            .split(|c| c == '-' || c == '_')
+            .next()
+            .map_or(false, |primary| primary.eq_ignore_ascii_case(language)),
+        None => true,
+    }
+}
+
+// The Questions of all records of an input (json, jsonl or msgpack), in record order
+pub(crate) fn read_questions(
+    input: &str,
+    options: &QaOptions,
+) -> std::io::Result<Vec<RecordQuestions>> {
+    let records = output::read_records(Path::new(input))?;
+    Ok(records
+        .par_iter()
+        .filter(|record| matches_language(record, options.language.as_deref()))
+        .map(|record| RecordQuestions {
+            id: record.id.clone(),
+            uri: record.uri.clone(),
+            language: record.language.clone(),
+            // Records written before the questions field only have their concatenation
+            questions: if record.questions.is_empty() {
+                parse_questions(&record.mhtml, options.keep_markup)
+            } else {
+                record
+                    .questions
+                    .iter()
+                    .flat_map(|html| parse_questions(html, options.keep_markup))
+                    .collect()
+            },
+        })
+        .collect())
+}
+
+// Position of the best answer: the first accepted answer, otherwise the most upvoted one (the
+// first of equally upvoted answers), otherwise the first one. The training formats and the
+// --structured records share it, so both agree on what the answer of a question is.
+pub(crate) fn best_answer<T>(
+    answers: &[T],
+    accepted: impl Fn(&T) -> bool,
+    upvote_count: impl Fn(&T) -> Option<i64>,
+) -> Option<usize> {
+    if let Some(position) = answers.iter().position(|answer| accepted(answer)) {
+        return Some(position);
+    }
+    let mut best: Option<(usize, i64)> = None;
+    for (position, answer) in answers.iter().enumerate() {
+        if let Some(count) = upvote_count(answer) {
+            if best.map_or(true, |(_, best_count)| best_count < count) {
+                best = Some((position, count));
+            }
+        }
+    }
+    best.map(|(position, _)| position)
+        .or(if answers.is_empty() { None } else { Some(0) })
+}
+
+// Answers of a question used as training targets
+pub(crate) fn select_answers(question: &Question, selection: AnswerSelection) -> Vec<&Answer> {
+    match selection {
+        AnswerSelection::All => question.answers.iter().collect(),
+        AnswerSelection::Best => best_answer(
+            &question.answers,
+            |answer| answer.status == AnswerStatus::Accepted,
+            |answer| answer.upvote_count,
+        )
+        .map(|position| &question.answers[position])
+        .into_iter()
+        .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_displayed_counts() {
+        // The cases of parse_count in python/mhtml_to_json.py, which gives the same results
+        let cases = [
+            ("12", Some(12)),
+            ("-3", Some(-3)),
+            ("+7", Some(7)),
+            ("12 votes", Some(12)),
+            ("12votes", Some(12)),
+            ("Votes: 42", Some(42)),
+            // Thousands separators
+            ("1,234", Some(1234)),
+            ("1.234", Some(1234)),
+            ("1 234", Some(1234)),
+            ("1'234", Some(1234)),
+            ("1.234.567", Some(1234567)),
+            ("1,234.5", Some(1235)),
+            ("1.234,5", Some(1235)),
+            // Decimal parts
+            ("1.5", Some(2)),
+            ("2,5", Some(3)),
+            // Abbreviated magnitudes
+            ("1.2k", Some(1200)),
+            ("1,2 K", Some(1200)),
+            ("1.234k", Some(1234)),
+            ("1.2k views", Some(1200)),
+            ("1.2M", Some(1200000)),
+            ("3 Mio.", Some(3000000)),
+            ("2,5 mrd", Some(2500000000)),
+            ("3b", Some(3000000000)),
+            ("2万", Some(20000)),
+            ("1.5萬", Some(15000)),
+            ("5 km", Some(5)),
+            // Localized digits and separators
+            ("١٢٣", Some(123)),
+            ("١٬٢٣٤", Some(1234)),
+            ("٣٫٥k", Some(3500)),
+            ("۱۲", Some(12)),
+            ("१२३", Some(123)),
+            ("１２", Some(12)),
+            // No count
+            ("", None),
+            ("   ", None),
+            ("votes", None),
+        ];
+        for (value, expected) in cases.iter() {
+            assert_eq!(parse_count(value), *expected, "{}", value);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Run-state file shared by all jobs of a fleet submission, recording which output was produced
+// from which input content under which configuration. An input whose checksum and config hash
+// match a recorded run is up to date and skipped, so resubmitting the same jobs is idempotent.
+//
+// The file holds one JSON object per line:
+//
+//     {"input": "...", "input_sha256": "...", "config_hash": "...", "output": "..."}
+
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct RunEntry {
+    pub(crate) input: String,
+    pub(crate) input_sha256: String,
+    pub(crate) config_hash: String,
+    pub(crate) output: String,
+}
+
+// Most recent run of the same input content under the same configuration, if any
+pub(crate) fn find_run(
+    run_state_path: &str,
+    input_sha256: &str,
+    config_hash: &str,
+) -> std::io::Result<Option<RunEntry>> {
+    let content = match std::fs::read_to_string(run_state_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    Ok(content
+        .lines()
+        // A line cut off by a crashed job is ignored rather than failing every later job
+        .filter_map(|line| serde_json::from_str::<RunEntry>(line).ok())
+        .filter(|entry| entry.input_sha256 == input_sha256 && entry.config_hash == config_hash)
+        .last())
+}
+
+// Record a completed run with a single append, like the skip-list
+pub(crate) fn record_run(run_state_path: &str, entry: &RunEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(run_state_path)?;
+    file.write_all(&line)?;
+    file.sync_data()
+}
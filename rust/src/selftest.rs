@@ -0,0 +1,120 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Self test guarding against nondeterminism in the parallel pipeline: a bundled fixture WARC is
+// processed with different thread counts in deterministic mode, and every run has to produce
+// byte-identical output.
+
+use std::sync::mpsc::sync_channel;
+
+use crate::columnar::to_io_error;
+use crate::output;
+use crate::{cli, minify, minify_config, MinifyConfig};
+
+const FIXTURE: &[u8] = include_bytes!("../fixtures/selftest.warc");
+
+// A sequential run as the reference and a parallel one with more threads than fixture records
+// finishing out of order
+const THREAD_COUNTS: &[usize] = &[1, 8];
+
+fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("ccqa-{}-{}", std::process::id(), name))
+        .to_string_lossy()
+        .to_string()
+}
+
+// Default options of the command line, except for the deterministic output order
+fn selftest_config(fixture_path: &str, output_path: &str) -> std::io::Result<MinifyConfig> {
+    let matches = cli().get_matches_from(&["ccqa", fixture_path, output_path, "--deterministic"]);
+    minify_config(
+        &matches,
+        output_path,
+        output::OutputFormat::Jsonl,
+        &output::Compression::None,
+        "selftest".to_string(),
+    )
+}
+
+// Process the fixture on a dedicated pool of the given size, returning the JSON Lines output
+fn run_with_threads(fixture_path: &str, threads: usize) -> std::io::Result<Vec<u8>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(to_io_error)?;
+    let output_path = temp_path(&format!("selftest-{}.jsonl", threads));
+    let config = selftest_config(fixture_path, &output_path)?;
+    let (sender, receiver) = sync_channel(output::CHANNEL_CAPACITY);
+    let writer_path = output_path.clone();
+    let writer = output::spawn_writer(
+        move || {
+            output::create_writer(
+                &writer_path,
+                output::OutputFormat::Jsonl,
+                &output::Compression::None,
+                output::ShardLimits::default(),
+            )
+        },
+        receiver,
+        true,
+        config.cancellation.clone(),
+    )?;
+    let minified = pool.install(|| minify(fixture_path, &config, sender));
+    let written = writer.join().expect("Output writer panicked");
+    minified?;
+    written?;
+    let content = std::fs::read(&output_path)?;
+    std::fs::remove_file(&output_path)?;
+    Ok(content)
+}
+
+// Run the self test, printing the outcome; false if the runs disagree
+pub(crate) fn selftest() -> std::io::Result<bool> {
+    let fixture_path = temp_path("selftest.warc");
+    std::fs::write(&fixture_path, FIXTURE)?;
+    let outputs = THREAD_COUNTS
+        .iter()
+        .map(|threads| run_with_threads(&fixture_path, *threads))
+        .collect::<std::io::Result<Vec<Vec<u8>>>>();
+    std::fs::remove_file(&fixture_path)?;
+    let outputs = outputs?;
+
+    let mut problems = Vec::new();
+    let reference = &outputs[0];
+    if reference.is_empty() {
+        problems.push("the fixture produced no records".to_string());
+    }
+    for (threads, output) in THREAD_COUNTS.iter().zip(outputs.iter()).skip(1) {
+        let reference_lines = reference.split(|byte| *byte == b'\n');
+        let lines = output.split(|byte| *byte == b'\n');
+        if let Some(line) = reference_lines.zip(lines).position(|(a, b)| a != b) {
+            problems.push(format!(
+                "output with {} threads differs from {} thread(s) on line {}",
+                threads,
+                THREAD_COUNTS[0],
+                line + 1
+            ));
+        } else if output.len() != reference.len() {
+            problems.push(format!(
+                "output with {} threads has {} bytes instead of {}",
+                threads,
+                output.len(),
+                reference.len()
+            ));
+        }
+    }
+
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    println!(
+        "Compared {} runs with {:?} threads: {}",
+        THREAD_COUNTS.len(),
+        THREAD_COUNTS,
+        if problems.is_empty() { "OK" } else { "FAILED" }
+    );
+    Ok(problems.is_empty())
+}
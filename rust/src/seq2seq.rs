@@ -0,0 +1,76 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Sequence-to-sequence pre-training text from minified outputs: every question with answers is
+// linearized into one line of `<output>.txt`, ready to be tokenized for fairseq or HuggingFace
+// training, e.g. with the default special tokens:
+//
+//     <question> How do I ...? <best_answer> Use ... <answer> You can also ...
+//
+// The best answer is the accepted one, otherwise the most upvoted one, otherwise the first one.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+use crate::qa::{self, AnswerSelection, QaOptions, Question};
+
+// Tokens marking the parts of a sequence, added to the tokenizer's vocabulary for training
+#[derive(Clone, Debug)]
+pub(crate) struct SpecialTokens {
+    pub(crate) question: String,
+    pub(crate) best_answer: String,
+    pub(crate) answer: String,
+    // Appended to every sequence, if any
+    pub(crate) end: String,
+}
+
+fn linearize(question: &Question, tokens: &SpecialTokens) -> Option<String> {
+    let best = *qa::select_answers(question, AnswerSelection::Best).first()?;
+    let mut parts = vec![
+        tokens.question.as_str(),
+        question.text.as_str(),
+        tokens.best_answer.as_str(),
+        best.text.as_str(),
+    ];
+    for answer in question.answers.iter() {
+        if !std::ptr::eq(answer, best) {
+            parts.push(tokens.answer.as_str());
+            parts.push(answer.text.as_str());
+        }
+    }
+    parts.push(tokens.end.as_str());
+    Some(
+        parts
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<&str>>()
+            .join(" "),
+    )
+}
+
+pub(crate) fn export(
+    inputs: &[&str],
+    output_path: &str,
+    options: &QaOptions,
+    tokens: &SpecialTokens,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(format!("{}.txt", output_path))?);
+    let mut num_sequences = 0;
+    for input in inputs.iter() {
+        let records = qa::read_questions(input, options)?;
+        for question in records.iter().flat_map(|record| record.questions.iter()) {
+            if let Some(sequence) = linearize(question, tokens) {
+                writeln!(writer, "{}", sequence)?;
+                num_sequences += 1;
+            }
+        }
+        eprintln!("Read {} records of {}", records.len(), input);
+    }
+    writer.flush()?;
+    println!("Wrote {} sequences to {}.txt", num_sequences, output_path);
+    Ok(())
+}
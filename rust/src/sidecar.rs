@@ -0,0 +1,75 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Externally computed per-URL metadata (quality scores, site categories, ...) joined onto the
+// output records during extraction. Two sidecar layouts are supported:
+//
+// * TSV (`.tsv`): a header row, the URL in the first column and one metadata field per column
+// * JSON: an object keyed by URL, holding one metadata object per URL
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+pub(crate) type Metadata = Map<String, Value>;
+
+fn parse_tsv(content: &str) -> Result<HashMap<String, Metadata>, String> {
+    let mut lines = content.lines();
+    let header = lines
+        .next()
+        .ok_or_else(|| "missing header row".to_string())?
+        .split('\t')
+        .collect::<Vec<&str>>();
+    let mut sidecar = HashMap::new();
+    for (idx, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        if fields.len() != header.len() {
+            return Err(format!(
+                "line {} has {} fields, expected {}",
+                idx + 2,
+                fields.len(),
+                header.len()
+            ));
+        }
+        let metadata = header[1..]
+            .iter()
+            .zip(fields[1..].iter())
+            .map(|(key, value)| (key.to_string(), Value::String(value.to_string())))
+            .collect::<Metadata>();
+        sidecar.insert(fields[0].to_string(), metadata);
+    }
+    Ok(sidecar)
+}
+
+fn parse_json(content: &str) -> Result<HashMap<String, Metadata>, String> {
+    let parsed: HashMap<String, Value> =
+        serde_json::from_str(content).map_err(|err| err.to_string())?;
+    parsed
+        .into_iter()
+        .map(|(url, value)| match value {
+            Value::Object(metadata) => Ok((url, metadata)),
+            _ => Err(format!("metadata for {} is not an object", url)),
+        })
+        .collect()
+}
+
+pub(crate) fn load_sidecar(path: &str) -> std::io::Result<HashMap<String, Metadata>> {
+    let content = std::fs::read_to_string(path)?;
+    let parsed = if path.ends_with(".tsv") {
+        parse_tsv(&content)
+    } else {
+        parse_json(&content)
+    };
+    parsed.map_err(|err| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid sidecar file {}: {}", path, err),
+        )
+    })
+}
@@ -0,0 +1,242 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Byte-scanning primitives for the hot paths (Question prefilter, whitespace cleaning) with
+// AVX2 (x86_64) and NEON (aarch64) variants, selected at runtime based on the CPU features.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+
+fn find_scalar(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn find_any_scalar(haystack: &[u8], set: &[u8]) -> Option<usize> {
+    haystack.iter().position(|byte| set.contains(byte))
+}
+
+// Compares the first and last needle byte against 32 haystack positions at once and only
+// verifies the full needle at positions where both match
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn find_avx2(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let last_offset = needle.len() - 1;
+    let first = _mm256_set1_epi8(needle[0] as i8);
+    let last = _mm256_set1_epi8(needle[last_offset] as i8);
+    let mut pos = 0;
+    while pos + last_offset + 32 <= haystack.len() {
+        let block_first = _mm256_loadu_si256(haystack.as_ptr().add(pos) as *const __m256i);
+        let block_last =
+            _mm256_loadu_si256(haystack.as_ptr().add(pos + last_offset) as *const __m256i);
+        let matches = _mm256_and_si256(
+            _mm256_cmpeq_epi8(first, block_first),
+            _mm256_cmpeq_epi8(last, block_last),
+        );
+        let mut mask = _mm256_movemask_epi8(matches) as u32;
+        while mask != 0 {
+            let candidate = pos + mask.trailing_zeros() as usize;
+            if &haystack[candidate..candidate + needle.len()] == needle {
+                return Some(candidate);
+            }
+            mask &= mask - 1;
+        }
+        pos += 32;
+    }
+    find_scalar(&haystack[pos..], needle).map(|found| pos + found)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn find_any_avx2(haystack: &[u8], set: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while pos + 32 <= haystack.len() {
+        let block = _mm256_loadu_si256(haystack.as_ptr().add(pos) as *const __m256i);
+        let mut matches = _mm256_setzero_si256();
+        for byte in set {
+            matches = _mm256_or_si256(
+                matches,
+                _mm256_cmpeq_epi8(block, _mm256_set1_epi8(*byte as i8)),
+            );
+        }
+        let mask = _mm256_movemask_epi8(matches) as u32;
+        if mask != 0 {
+            return Some(pos + mask.trailing_zeros() as usize);
+        }
+        pos += 32;
+    }
+    find_any_scalar(&haystack[pos..], set).map(|found| pos + found)
+}
+
+// NEON has no movemask, so blocks with a candidate are verified position by position
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn find_neon(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let last_offset = needle.len() - 1;
+    let first = vdupq_n_u8(needle[0]);
+    let last = vdupq_n_u8(needle[last_offset]);
+    let mut pos = 0;
+    while pos + last_offset + 16 <= haystack.len() {
+        let block_first = vld1q_u8(haystack.as_ptr().add(pos));
+        let block_last = vld1q_u8(haystack.as_ptr().add(pos + last_offset));
+        let matches = vandq_u8(vceqq_u8(first, block_first), vceqq_u8(last, block_last));
+        if vmaxvq_u8(matches) != 0 {
+            for candidate in pos..pos + 16 {
+                if &haystack[candidate..candidate + needle.len()] == needle {
+                    return Some(candidate);
+                }
+            }
+        }
+        pos += 16;
+    }
+    find_scalar(&haystack[pos..], needle).map(|found| pos + found)
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn find_any_neon(haystack: &[u8], set: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while pos + 16 <= haystack.len() {
+        let block = vld1q_u8(haystack.as_ptr().add(pos));
+        let mut matches = vdupq_n_u8(0);
+        for byte in set {
+            matches = vorrq_u8(matches, vceqq_u8(block, vdupq_n_u8(*byte)));
+        }
+        if vmaxvq_u8(matches) != 0 {
+            return find_any_scalar(&haystack[pos..pos + 16], set).map(|found| pos + found);
+        }
+        pos += 16;
+    }
+    find_any_scalar(&haystack[pos..], set).map(|found| pos + found)
+}
+
+// Position of the first occurrence of `needle` in `haystack`
+pub(crate) fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    if haystack.len() < needle.len() {
+        return None;
+    }
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { find_avx2(haystack, needle) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return unsafe { find_neon(haystack, needle) };
+        }
+    }
+    find_scalar(haystack, needle)
+}
+
+// Position of the first byte of `haystack` contained in `set`
+pub(crate) fn find_any(haystack: &[u8], set: &[u8]) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { find_any_avx2(haystack, set) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return unsafe { find_any_neon(haystack, set) };
+        }
+    }
+    find_any_scalar(haystack, set)
+}
+
+// Name of the implementation selected on this CPU, reported at startup
+pub(crate) fn implementation() -> &'static str {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return "avx2";
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return "neon";
+        }
+    }
+    "scalar"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Haystack lengths around the 16 and 32 byte vectors and their tails
+    const LENGTHS: [usize; 16] = [0, 1, 2, 7, 15, 16, 17, 31, 32, 33, 47, 48, 63, 64, 65, 100];
+
+    #[test]
+    fn finds_needles_like_the_scalar_version() {
+        let needles: [&[u8]; 5] = [
+            b"i",
+            b"it",
+            b"itp",
+            b"itemprop",
+            b"itemprop=\"acceptedAnswer",
+        ];
+        for length in LENGTHS.iter() {
+            for needle in needles.iter() {
+                let mut haystack = vec![b'-'; *length];
+                assert_eq!(find(&haystack, needle), find_scalar(&haystack, needle));
+                for pos in 0..(length + 1).saturating_sub(needle.len()) {
+                    haystack = vec![b'-'; *length];
+                    // A decoy matching the first and last byte only, before the needle
+                    if needle.len() > 2 && pos >= needle.len() {
+                        haystack[pos - needle.len()] = needle[0];
+                        haystack[pos - 1] = needle[needle.len() - 1];
+                    }
+                    haystack[pos..pos + needle.len()].copy_from_slice(needle);
+                    assert_eq!(find(&haystack, needle), Some(pos), "{} {}", length, pos);
+                    assert_eq!(find(&haystack, needle), find_scalar(&haystack, needle));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn finds_needles_in_empty_and_short_input() {
+        assert_eq!(find(b"", b"itemprop"), None);
+        assert_eq!(find(b"", b""), Some(0));
+        assert_eq!(find(b"item", b"itemprop"), None);
+        assert_eq!(find(b"itemprop", b"itemprop"), Some(0));
+    }
+
+    #[test]
+    fn finds_any_byte_like_the_scalar_version() {
+        let sets: [&[u8]; 6] = [b"", b" ", b" \t", b" \t\n", b" \t\n\r", b" \t\n\r\x0c"];
+        for length in LENGTHS.iter() {
+            for set in sets.iter() {
+                let haystack = vec![b'-'; *length];
+                assert_eq!(find_any(&haystack, set), None);
+                for pos in 0..*length {
+                    for byte in set.iter() {
+                        let mut haystack = vec![b'-'; *length];
+                        haystack[pos] = *byte;
+                        // A later match doesn't hide the first one
+                        if pos + 1 < *length {
+                            haystack[length - 1] = set[0];
+                        }
+                        assert_eq!(find_any(&haystack, set), Some(pos), "{} {}", length, pos);
+                        assert_eq!(find_any(&haystack, set), find_any_scalar(&haystack, set));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Direct upload of the output to S3 (or an S3 compatible store). Outputs given as
+// `s3://bucket/key`, and their side streams, are streamed into multipart uploads while written:
+// every part is uploaded as soon as it is filled, so nothing is staged on local disk. The object
+// only appears once its writer is finished; uploads of failed runs are never completed. Inputs
+// are still read from local paths.
+//
+// rust-s3 doesn't hand out the id of the multipart upload behind its streaming upload, so an
+// upload cut short can't be aborted from here: its parts stay in the bucket, and are billed,
+// until they are removed. Buckets written to need a lifecycle rule aborting incomplete multipart
+// uploads, e.g. AbortIncompleteMultipartUpload with DaysAfterInitiation 1.
+//
+// Credentials are read from the usual AWS environment variables or profile. The region is taken
+// from `AWS_REGION` (default us-east-1) and a custom endpoint (MinIO, Ceph, ...) from
+// `AWS_ENDPOINT_URL`.
+
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use s3::bucket::Bucket;
+use s3::creds::Credentials;
+use s3::region::Region;
+
+use crate::columnar::to_io_error;
+
+const S3_SCHEME: &str = "s3://";
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct S3Location {
+    pub(crate) bucket: String,
+    pub(crate) key: String,
+}
+
+impl S3Location {
+    // Parse an `s3://bucket/key` URL, None for local paths
+    pub(crate) fn parse(url: &str) -> Option<S3Location> {
+        let without_scheme = url.strip_prefix(S3_SCHEME)?;
+        let mut parts = without_scheme.splitn(2, '/');
+        let bucket = parts.next().unwrap_or("");
+        let key = parts.next().unwrap_or("");
+        if bucket.is_empty() || key.is_empty() {
+            return None;
+        }
+        Some(S3Location {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        })
+    }
+}
+
+fn region() -> std::io::Result<Region> {
+    let region = std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+    match std::env::var("AWS_ENDPOINT_URL") {
+        Ok(endpoint) => Ok(Region::Custom { region, endpoint }),
+        Err(_) => region.parse().map_err(to_io_error),
+    }
+}
+
+// Written data on its way to the upload thread. End tells a finished stream from one whose writer
+// was dropped, e.g. on an error, and Abandon stops the upload while the writer is still around.
+enum Chunk {
+    Data(Vec<u8>),
+    End,
+    Abandon,
+}
+
+// Chunks buffered between a writer and its upload thread
+const PIPE_CAPACITY: usize = 64;
+
+// Reading end of the pipe, from which rust-s3 fills the parts of the multipart upload
+struct PipeReader {
+    receiver: Receiver<Chunk>,
+    chunk: Vec<u8>,
+    position: usize,
+    ended: bool,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if self.ended {
+                return Ok(0);
+            }
+            match self.receiver.recv() {
+                Ok(Chunk::Data(data)) => {
+                    self.chunk = data;
+                    self.position = 0;
+                }
+                Ok(Chunk::End) => self.ended = true,
+                // Failing the read keeps the upload from being completed
+                Ok(Chunk::Abandon) | Err(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        "Output abandoned before it was finished",
+                    ))
+                }
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.position);
+        buf[..len].copy_from_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+// Writing end of an upload, to be buffered by the caller
+pub(crate) struct S3Writer {
+    sender: SyncSender<Chunk>,
+    written: Arc<AtomicU64>,
+    location: S3Location,
+}
+
+impl Write for S3Writer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.sender.send(Chunk::Data(buf.to_vec())).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!(
+                    "Upload to s3://{}/{} failed",
+                    self.location.bucket, self.location.key
+                ),
+            )
+        })?;
+        self.written.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// A running multipart upload, completed by finish once everything was written to its S3Writer.
+// Dropping it unfinished abandons the upload and waits for its thread to stop.
+pub(crate) struct S3Upload {
+    sender: SyncSender<Chunk>,
+    written: Arc<AtomicU64>,
+    thread: Option<JoinHandle<std::io::Result<()>>>,
+    location: S3Location,
+}
+
+impl S3Upload {
+    pub(crate) fn start(location: &S3Location) -> std::io::Result<(S3Writer, S3Upload)> {
+        let credentials = Credentials::default().map_err(to_io_error)?;
+        let bucket = Bucket::new(&location.bucket, region()?, credentials).map_err(to_io_error)?;
+        let (sender, receiver) = sync_channel(PIPE_CAPACITY);
+        let written = Arc::new(AtomicU64::new(0));
+        let upload_location = location.clone();
+        let thread = std::thread::spawn(move || {
+            let mut reader = PipeReader {
+                receiver,
+                chunk: Vec::new(),
+                position: 0,
+                ended: false,
+            };
+            let status = bucket
+                .put_object_stream_blocking(&mut reader, &upload_location.key)
+                .map_err(to_io_error)?;
+            if status != 200 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!(
+                        "Upload to s3://{}/{} failed with status {}",
+                        upload_location.bucket, upload_location.key, status
+                    ),
+                ));
+            }
+            eprintln!(
+                "Uploaded s3://{}/{}",
+                upload_location.bucket, upload_location.key
+            );
+            Ok(())
+        });
+        let writer = S3Writer {
+            sender: sender.clone(),
+            written: written.clone(),
+            location: location.clone(),
+        };
+        Ok((
+            writer,
+            S3Upload {
+                sender,
+                written,
+                thread: Some(thread),
+                location: location.clone(),
+            },
+        ))
+    }
+
+    // Bytes handed to the upload so far
+    pub(crate) fn written_bytes(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    // Upload the last part and complete the upload, once the writer wrote and flushed everything
+    pub(crate) fn finish(mut self) -> std::io::Result<()> {
+        // Fails only if the upload thread has already stopped, whose error is returned below
+        let _ = self.sender.send(Chunk::End);
+        self.thread
+            .take()
+            .unwrap()
+            .join()
+            .expect("S3 upload panicked")
+    }
+}
+
+impl Drop for S3Upload {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.sender.send(Chunk::Abandon);
+            let _ = thread.join();
+            eprintln!(
+                "Abandoned the upload to s3://{}/{}, its parts are left to the lifecycle rule of the bucket",
+                self.location.bucket, self.location.key
+            );
+        }
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Markup report of a WARC file (--validate), instead of the corpus: the problems of the microdata
+// Questions of every page, before any cleaning, to weigh cleaning-rule changes before a full-crawl
+// run. One tab-separated row per problem, with the index of the Question within its page:
+//
+//     id                      uri                      question  problem              detail
+//     CC-MAIN-...-00000:1234  https://example.com/q/1  0         malformed count      upvoteCount=votes
+//     CC-MAIN-...-00000:1234  https://example.com/q/1  0         empty acceptedAnswer
+//
+// JSON-LD Questions are left out, their markup is generated by the extractor itself.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::prelude::*;
+
+use kuchiki::traits::*;
+use kuchiki::NodeRef;
+use rayon::prelude::*;
+use warc::header::WarcHeader;
+use warc::{RawRecord, WarcReader};
+
+use crate::microdata::{attribute, collapse_whitespace, has_itemprop, scope};
+use crate::{dates, extract, http, itemtype, qa};
+use crate::{generate_id, is_binary_payload, resolve_itemrefs, transform_outside, warc_header};
+
+pub(crate) const REPORT_HEADER: &str = "id\turi\tquestion\tproblem\tdetail";
+
+const COUNT_PROPERTIES: [&str; 4] = [
+    "upvoteCount",
+    "downvoteCount",
+    "answerCount",
+    "commentCount",
+];
+const DATE_PROPERTIES: [&str; 3] = ["dateCreated", "dateModified", "datePublished"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Problem {
+    // Neither a name nor a text property with text
+    MissingQuestionText,
+    // Neither an acceptedAnswer nor a suggestedAnswer
+    MissingAnswer,
+    // An acceptedAnswer without any text
+    EmptyAcceptedAnswer,
+    // An answer with text, but no text property holding it
+    AnswerWithoutText,
+    // A vote, answer or comment count that isn't a number
+    MalformedCount,
+    // A date in none of the known formats
+    MalformedDate,
+    // An itemref to an id missing from the page
+    UnresolvedItemref,
+}
+
+impl Problem {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Problem::MissingQuestionText => "missing question text",
+            Problem::MissingAnswer => "missing answer",
+            Problem::EmptyAcceptedAnswer => "empty acceptedAnswer",
+            Problem::AnswerWithoutText => "answer without text",
+            Problem::MalformedCount => "malformed count",
+            Problem::MalformedDate => "malformed date",
+            Problem::UnresolvedItemref => "unresolved itemref",
+        }
+    }
+}
+
+// A problem of one Question of a page
+pub(crate) struct Finding {
+    pub(crate) question: usize,
+    pub(crate) problem: Problem,
+    pub(crate) detail: String,
+}
+
+// The `prop` properties of an item, not those of its nested items
+fn properties(item: &NodeRef, prop: &str) -> Vec<NodeRef> {
+    item.descendants()
+        .filter(|node| has_itemprop(node, prop) && scope(node).as_ref() == Some(item))
+        .collect()
+}
+
+fn has_text(node: &NodeRef) -> bool {
+    attribute(node, "content").map_or(false, |content| !content.trim().is_empty())
+        || !node.text_contents().trim().is_empty()
+}
+
+// Problems of the counts and dates of an item
+fn check_values(item: &NodeRef, question: usize, findings: &mut Vec<Finding>) {
+    for prop in COUNT_PROPERTIES.iter() {
+        for node in properties(item, prop) {
+            let value = attribute(&node, "content").unwrap_or_else(|| node.text_contents());
+            // Counts displayed as text often come with a label ("12 votes")
+            let parsed = attribute(&node, "content")
+                .and_then(|content| qa::parse_count(&content))
+                .or_else(|| qa::parse_count(&node.text_contents()));
+            if parsed.is_none() {
+                findings.push(Finding {
+                    question,
+                    problem: Problem::MalformedCount,
+                    detail: format!("{}={}", prop, collapse_whitespace(&value)),
+                });
+            }
+        }
+    }
+    for prop in DATE_PROPERTIES.iter() {
+        for node in properties(item, prop) {
+            let values = ["datetime", "content"]
+                .iter()
+                .filter_map(|name| attribute(&node, name))
+                .chain(std::iter::once(node.text_contents()))
+                .collect::<Vec<String>>();
+            if !values
+                .iter()
+                .any(|value| dates::normalize_date(value).is_some())
+            {
+                findings.push(Finding {
+                    question,
+                    problem: Problem::MalformedDate,
+                    detail: format!("{}={}", prop, collapse_whitespace(&values[0])),
+                });
+            }
+        }
+    }
+}
+
+fn check_question(node: &NodeRef, question: usize, findings: &mut Vec<Finding>) {
+    let has_question_text = ["name", "text"]
+        .iter()
+        .any(|prop| properties(node, prop).iter().any(has_text));
+    if !has_question_text {
+        findings.push(Finding {
+            question,
+            problem: Problem::MissingQuestionText,
+            detail: String::new(),
+        });
+    }
+    check_values(node, question, findings);
+    let answers = properties(node, "acceptedAnswer")
+        .into_iter()
+        .map(|answer| (answer, true))
+        .chain(
+            properties(node, "suggestedAnswer")
+                .into_iter()
+                .map(|answer| (answer, false)),
+        )
+        .collect::<Vec<(NodeRef, bool)>>();
+    if answers.is_empty() {
+        findings.push(Finding {
+            question,
+            problem: Problem::MissingAnswer,
+            detail: String::new(),
+        });
+    }
+    for (answer, accepted) in answers.iter() {
+        if !has_text(answer) {
+            if *accepted {
+                findings.push(Finding {
+                    question,
+                    problem: Problem::EmptyAcceptedAnswer,
+                    detail: String::new(),
+                });
+            }
+            continue;
+        }
+        // Answers without an item of their own are their text
+        if attribute(answer, "itemscope").is_some() {
+            if properties(answer, "text").is_empty() {
+                findings.push(Finding {
+                    question,
+                    problem: Problem::AnswerWithoutText,
+                    detail: String::new(),
+                });
+            }
+            check_values(answer, question, findings);
+        }
+    }
+}
+
+// Problems of the microdata Questions of a page, None if it has none
+pub(crate) fn validate_document(
+    document: &NodeRef,
+    itemtypes: &itemtype::Itemtypes,
+    lenient: bool,
+    vocabularies: &[&itemtype::Vocabulary],
+) -> Option<Vec<Finding>> {
+    itemtype::map_vocabularies(document, vocabularies);
+    let questions = transform_outside(
+        document.clone(),
+        itemtypes,
+        lenient,
+        &extract::CancellationToken::new(),
+    )?;
+    let ids = document
+        .descendants()
+        .filter_map(|node| attribute(&node, "id"))
+        .collect::<HashSet<String>>();
+    let mut findings = Vec::new();
+    for (index, question) in questions.iter().enumerate() {
+        for node in question.inclusive_descendants() {
+            for id in attribute(&node, "itemref")
+                .unwrap_or_default()
+                .split_whitespace()
+            {
+                if !ids.contains(id) {
+                    findings.push(Finding {
+                        question: index,
+                        problem: Problem::UnresolvedItemref,
+                        detail: id.to_string(),
+                    });
+                }
+            }
+        }
+        resolve_itemrefs(document, question);
+        check_question(question, index, &mut findings);
+    }
+    Some(findings)
+}
+
+// Write the markup report of a WARC file, and summarize it on stderr
+pub(crate) fn validate_warc(
+    file_path: &str,
+    id_namespace: &Option<String>,
+    itemtypes: &itemtype::Itemtypes,
+    lenient: bool,
+    vocabularies: &[&itemtype::Vocabulary],
+    report: &mut dyn Write,
+) -> std::io::Result<()> {
+    let records = WarcReader::from_path(file_path)?
+        .enumerate()
+        .filter_map(|(index, record)| record.ok().map(|record| (index, record)))
+        .collect::<Vec<(usize, RawRecord)>>();
+    let pages = records
+        .par_iter()
+        .filter_map(|(index, record)| {
+            let response = http::Response::parse(&record.body)?;
+            if is_binary_payload(response.payload()) {
+                return None;
+            }
+            let payload = response.text();
+            if !itemtypes.contains_any(&payload)
+                && !(itemtypes.includes_question()
+                    && itemtype::contains_any_question(&payload, vocabularies))
+                && !(lenient && !itemtype::find_variants(&payload).is_empty())
+            {
+                return None;
+            }
+            let document = kuchiki::parse_html().one(payload.as_ref());
+            let findings = validate_document(&document, itemtypes, lenient, vocabularies)?;
+            Some((*index, warc_header(record, WarcHeader::TargetURI), findings))
+        })
+        .collect::<Vec<(usize, Option<String>, Vec<Finding>)>>();
+    let mut counts: BTreeMap<Problem, u64> = BTreeMap::new();
+    let mut pages_with_problems = 0;
+    for (index, uri, findings) in pages.iter() {
+        if !findings.is_empty() {
+            pages_with_problems += 1;
+        }
+        for finding in findings.iter() {
+            *counts.entry(finding.problem).or_insert(0) += 1;
+            writeln!(
+                report,
+                "{}\t{}\t{}\t{}\t{}",
+                generate_id(id_namespace, file_path, *index),
+                uri.as_deref().unwrap_or_default(),
+                finding.question,
+                finding.problem.name(),
+                finding.detail.replace(|c| c == '\t' || c == '\n', " ")
+            )?;
+        }
+    }
+    eprintln!(
+        "Validated {} pages with Questions, {} with markup problems",
+        pages.len(),
+        pages_with_problems
+    );
+    for (problem, count) in counts.iter() {
+        eprintln!("  {}: {}", problem.name(), count);
+    }
+    Ok(())
+}
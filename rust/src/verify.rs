@@ -0,0 +1,139 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Read-only integrity check of a published dataset directory.
+//
+// A release directory contains a `manifest.json` of the form
+//
+//     {
+//       "total_records": 1234,
+//       "shards": [
+//         {"path": "ccqa.00001.jsonl", "sha256": "<hex digest>", "records": 1000},
+//         {"path": "ccqa.00002.jsonl", "sha256": "<hex digest>", "records": 234}
+//       ]
+//     }
+//
+// where every shard holds HTMLMinified records as a JSON array, JSON Lines or MessagePack,
+// optionally gzip or zstd compressed.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::output;
+
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct ShardEntry {
+    pub(crate) path: String,
+    pub(crate) sha256: String,
+    pub(crate) records: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Manifest {
+    pub(crate) total_records: u64,
+    pub(crate) shards: Vec<ShardEntry>,
+}
+
+pub(crate) fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Check a single shard against its manifest entry, adding its ids to the global set
+fn verify_shard(dir: &Path, shard: &ShardEntry, ids: &mut HashSet<String>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let shard_path = dir.join(&shard.path);
+    match sha256_file(&shard_path) {
+        Ok(digest) => {
+            if digest != shard.sha256.to_lowercase() {
+                problems.push(format!(
+                    "{}: checksum mismatch (manifest {}, actual {})",
+                    shard.path, shard.sha256, digest
+                ));
+            }
+        }
+        Err(err) => {
+            problems.push(format!("{}: cannot be read: {}", shard.path, err));
+            return problems;
+        }
+    }
+    let records = match output::read_records(&shard_path) {
+        Ok(records) => records,
+        Err(err) => {
+            problems.push(format!("{}: invalid record: {}", shard.path, err));
+            return problems;
+        }
+    };
+    if records.len() as u64 != shard.records {
+        problems.push(format!(
+            "{}: declares {} records but contains {}",
+            shard.path,
+            shard.records,
+            records.len()
+        ));
+    }
+    for record in records {
+        if !ids.insert(record.id.clone()) {
+            problems.push(format!("{}: duplicate id {}", shard.path, record.id));
+        }
+    }
+    problems
+}
+
+// Verify a release directory, printing every problem found. Returns whether the release is valid.
+pub(crate) fn verify_release(dir: &str) -> std::io::Result<bool> {
+    let dir = Path::new(dir);
+    let manifest_content = std::fs::read_to_string(dir.join(MANIFEST_FILE))?;
+    let manifest: Manifest = serde_json::from_str(&manifest_content)?;
+
+    let mut problems = Vec::new();
+    let mut ids = HashSet::new();
+    let mut declared_total = 0;
+    for shard in manifest.shards.iter() {
+        declared_total += shard.records;
+        problems.extend(verify_shard(dir, shard, &mut ids));
+    }
+    if declared_total != manifest.total_records {
+        problems.push(format!(
+            "manifest declares {} total records but its shards declare {}",
+            manifest.total_records, declared_total
+        ));
+    }
+    if ids.len() as u64 != manifest.total_records {
+        problems.push(format!(
+            "manifest declares {} total records but {} unique ids were found",
+            manifest.total_records,
+            ids.len()
+        ));
+    }
+
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    println!(
+        "Verified {} shards with {} unique records: {}",
+        manifest.shards.len(),
+        ids.len(),
+        if problems.is_empty() { "OK" } else { "FAILED" }
+    );
+    Ok(problems.is_empty())
+}
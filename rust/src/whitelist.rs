@@ -0,0 +1,34 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// All rights reserved.
+//
+// This source code is licensed under the license found in the
+// LICENSE file in the root directory of this source tree.
+
+// Records that no deduplication stage may remove (`--dedup-whitelist`), e.g. curated evaluation
+// questions that aligned train/eval releases depend on. The file lists one record id or URI per
+// line; empty lines and lines starting with `#` are ignored. Within a page, Questions are also
+// looked up by their itemid and url property.
+
+use std::collections::HashSet;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct DedupWhitelist {
+    entries: HashSet<String>,
+}
+
+impl DedupWhitelist {
+    pub fn load(path: &str) -> std::io::Result<DedupWhitelist> {
+        let entries = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect::<HashSet<String>>();
+        eprintln!("Loaded {} dedup whitelist entries", entries.len());
+        Ok(DedupWhitelist { entries })
+    }
+
+    pub fn contains(&self, id: &str, uri: Option<&str>) -> bool {
+        self.entries.contains(id) || uri.map_or(false, |uri| self.entries.contains(uri))
+    }
+}